@@ -1,7 +1,7 @@
 #!/bin/bash
 echo "output path: $1"
 echo "golden path: $2"
-echo "epsilon: $epsilon"
+echo "epsilon: $REGRESSION_EPSILON"
 output=$(cat $1)
 golden=$(cat $2)
 
@@ -0,0 +1 @@
+ls
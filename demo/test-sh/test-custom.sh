@@ -1,2 +1,2 @@
-echo 11111 > $name.1.out
-echo 22222 > $name.2.out
\ No newline at end of file
+echo 11111 > $REGRESSION_NAME.1.out
+echo 22222 > $REGRESSION_NAME.2.out
\ No newline at end of file
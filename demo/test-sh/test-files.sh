@@ -0,0 +1,2 @@
+echo "ok" > $REGRESSION_NAME.1.log
+echo "ok" > $REGRESSION_NAME.2.log
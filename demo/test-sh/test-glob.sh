@@ -1,9 +1,9 @@
 mkdir log
-echo 1 > log/$name.1.log
-echo 2 > log/$name.2.log
-echo 3 > log/$name.3.log
-echo 4 > log/$name.4.log
-echo 5 > log/$name.5.log
-echo 6 > log/$name.6.log
-echo 7 > log/$name.7.log
-echo 8 > log/$name.8.log
\ No newline at end of file
+echo 1 > log/$REGRESSION_NAME.1.log
+echo 2 > log/$REGRESSION_NAME.2.log
+echo 3 > log/$REGRESSION_NAME.3.log
+echo 4 > log/$REGRESSION_NAME.4.log
+echo 5 > log/$REGRESSION_NAME.5.log
+echo 6 > log/$REGRESSION_NAME.6.log
+echo 7 > log/$REGRESSION_NAME.7.log
+echo 8 > log/$REGRESSION_NAME.8.log
\ No newline at end of file
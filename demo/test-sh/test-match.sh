@@ -1,9 +1,9 @@
-# $name is added to ENV
+# $REGRESSION_NAME is added to ENV
 
 # k2 should be none
-echo "$k2" >> $name.text
+echo "$k2" >> $REGRESSION_NAME.text
 # k4 should be v4
-echo "$k4" >> $name.text
+echo "$k4" >> $REGRESSION_NAME.text
 echo fo
 echo foo
 echo fooo
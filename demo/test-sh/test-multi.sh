@@ -0,0 +1,2 @@
+echo 'foo 4.05'
+echo 'fo after'
@@ -0,0 +1,2 @@
+mkdir -p out/sub
+cat data/sub/config.json > out/sub/result.json
@@ -0,0 +1,2 @@
+echo "idempotent" > $REGRESSION_NAME.1.out
+cat $REGRESSION_NAME.1.out > $REGRESSION_NAME.2.out
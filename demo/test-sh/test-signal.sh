@@ -0,0 +1,2 @@
+echo "terminate myself"
+kill -TERM $$
@@ -0,0 +1,2 @@
+printf "apple\nBanana\ncherry\n"
+printf "2 b\n10 a\n" > $REGRESSION_NAME.log
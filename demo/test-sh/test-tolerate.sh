@@ -0,0 +1,2 @@
+echo "broken pipe is benign"
+exit 141
@@ -0,0 +1 @@
+printf "serde\ntokio\nregex\n"
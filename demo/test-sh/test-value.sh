@@ -1,2 +1,6 @@
 echo 'foo 4.05'
-echo '0.9 after'
\ No newline at end of file
+echo '0.9 after'
+echo 'residual 0.5'
+echo 'residual 0.25'
+echo 'residual 0.25'
+echo 'final 0.25'
@@ -1,44 +1,349 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use indexmap::IndexMap;
+use itertools::Itertools;
+use serde::Deserialize;
 use std::{
   collections::HashSet,
   ffi::OsString,
+  hash::{BuildHasher, Hasher, RandomState},
+  io,
   mem::take,
   path::{Path, PathBuf},
 };
 
-use crate::regression::BuildError;
+use crate::{
+  assert::GoldenRefs,
+  regression::{BuildError, LAST_FAILED},
+  timings::{Timings, read_timings},
+};
+
+/// The project-level defaults, in the current directory or else in rootdir
+pub(crate) const PROJECT_CONFIG: &str = ".cargo-regression.toml";
 
 #[derive(Debug, Parser)]
-#[command(version)]
+#[command(
+  version,
+  args_conflicts_with_subcommands = true,
+  subcommand_negates_reqs = true
+)]
 pub struct Args {
+  #[command(subcommand)]
+  pub(crate) command: Option<Command>,
   #[clap(long, help = "NoDebug mode flag")]
   pub(crate) nodebug: bool,
+  #[clap(long, help = "Verbose mode flag, e.g., report the tolerated exit codes")]
+  pub(crate) verbose: bool,
   #[clap(long, help = "Print errors [default: false, save errs to report]")]
   pub(crate) print_errs: bool,
-  #[clap(long, help = "Default executable path", default_value_t = String::new())]
+  #[clap(
+    long,
+    help = "Run the tasks with a cleared environment, only `envs` and `env-allowlist` are passed"
+  )]
+  pub(crate) clear_env: bool,
+  #[clap(
+    long,
+    help = "Max bytes of each failure report (or printed errors), the rest is truncated",
+    default_value_t = 4 << 20
+  )]
+  pub(crate) max_report_bytes: usize,
+  #[clap(
+    long,
+    help = "Default executable path of the tasks whose configs have no `cmd`",
+    default_value_t = String::new()
+  )]
   pub(crate) cmd: String,
+  #[clap(
+    long,
+    help = "Wrap all tasks' command, e.g., --wrapper \"valgrind --error-exitcode=99\""
+  )]
+  pub(crate) wrapper: Option<String>,
   #[clap(long, help = "Default arguements", default_value = "{{name}}.{{extension}}", num_args = 1..)]
   pub(crate) args: Vec<String>,
   #[clap(long, help="Default input extensions(s)", num_args = 1..)]
   pub(crate) extensions: Vec<String>,
   #[clap(long, help="Input include. E.g., --include ./cases/*", num_args = 1..)]
   include: Vec<PathBuf>,
+  #[clap(
+    long,
+    help = "Read more inputs to include from file(s), one path per line",
+    num_args = 1..
+  )]
+  include_from: Vec<PathBuf>,
   #[clap(skip)]
   include_set: HashSet<PathBuf>,
+  /// The include paths as walked, only their parents are canonicalized,
+  /// so that the directories of the included symlinks are not pruned
+  #[clap(skip)]
+  include_walked: HashSet<PathBuf>,
   #[clap(long, help="Input exclude. E.g., --exclude ./cases/*", num_args = 1..)]
   exclude: Vec<PathBuf>,
+  #[clap(
+    long,
+    help = "Read more inputs to exclude from file(s), one path per line",
+    num_args = 1..
+  )]
+  exclude_from: Vec<PathBuf>,
   #[clap(skip)]
   exclude_set: HashSet<PathBuf>,
+  #[clap(long, help = "Load environment variables from .env file(s)", num_args = 1..)]
+  env_file: Vec<PathBuf>,
+  #[clap(skip)]
+  pub(crate) file_envs: IndexMap<String, String>,
+  /// The default `epsilon` of all tasks, only set by `.cargo-regression.toml`
+  #[clap(skip)]
+  pub(crate) epsilon: Option<f32>,
+  #[clap(long, help = "Output format", value_enum, default_value_t = Format::Pretty)]
+  pub(crate) format: Format,
+  #[clap(
+    long,
+    help = "The final summary style, only for --format pretty",
+    value_enum,
+    default_value_t = SummaryStyle::Line
+  )]
+  pub(crate) summary: SummaryStyle,
+  #[clap(long, help = "Print every failure, instead of once for the identical ones")]
+  pub(crate) no_dedupe_failures: bool,
+  #[clap(long, help = "Print the task paths relative to rootdir, unless --verbose")]
+  pub(crate) short_ids: bool,
+  #[clap(
+    long,
+    help = "Show the progress in place, only when stdout is a terminal and --format pretty"
+  )]
+  pub(crate) progress: bool,
+  #[clap(
+    long,
+    help = "Summarize the failures by the directories at that depth under rootdir, 0 to disable",
+    default_value_t = 1
+  )]
+  pub(crate) group_depth: usize,
+  #[clap(long, help = "Buffer the results and print them in path order")]
+  pub(crate) sort_output: bool,
+  #[clap(long, help = "Fail if any task is ignored")]
+  pub(crate) deny_ignored: bool,
+  #[clap(long, help = "Fail if any task is filtered out")]
+  pub(crate) deny_filtered: bool,
+  #[clap(
+    long,
+    help = "List the ignored tasks, and count the filtered out tasks per top-level directory",
+    value_enum,
+    num_args = 0..=1,
+    require_equals = true,
+    default_missing_value = "dirs"
+  )]
+  pub(crate) show_skipped: Option<ShowSkipped>,
+  #[clap(long, help = "Warn the golden files that no assert references")]
+  pub(crate) check_unused_goldens: bool,
+  #[clap(
+    long,
+    help = "Fail if any golden file is unused, implies --check-unused-goldens"
+  )]
+  pub(crate) deny_unused_goldens: bool,
+  /// The golden files referenced by the asserts, only for `--check-unused-goldens`
+  #[clap(skip)]
+  pub(crate) golden_refs: Option<GoldenRefs>,
+  #[clap(long, help = "Only rerun the failed tasks of last run")]
+  only_failed: bool,
+  #[clap(skip)]
+  last_failed_set: Option<HashSet<PathBuf>>,
   #[clap(long, help = "Total permits to limit max parallelism", default_value_t = 1)]
   pub(crate) permits: u32,
+  #[clap(
+    long,
+    help = "The permit of the tasks that do not set `permit` in their configs",
+    default_value_t = 0
+  )]
+  pub(crate) permit_default: u32,
+  #[clap(
+    long,
+    help = "Total count of a named resource, e.g., --resource gpu=2 license=4",
+    num_args = 1..
+  )]
+  resource: Vec<String>,
+  #[clap(skip)]
+  pub(crate) resources: IndexMap<String, u32>,
+  #[clap(long, help = "Start the tasks with larger permit first")]
+  pub(crate) heavy_first: bool,
+  #[clap(
+    long,
+    help = "Total permits to limit the parallelism of preparing workdirs [default: unlimited]"
+  )]
+  pub(crate) io_permits: Option<u32>,
   #[clap(long, help = "Timeout second for each task", default_value_t = 600)]
   pub(crate) timeout: u64,
+  #[clap(
+    long,
+    help = "Directory to find {{bin.<name>}} [default: $CARGO_BIN_EXE_<name>]"
+  )]
+  pub(crate) bin_dir: Option<PathBuf>,
+  #[clap(skip)]
+  bins: IndexMap<String, PathBuf>,
+  #[clap(
+    long,
+    help = "Inject unique LLVM_PROFILE_FILE=<coverage-dir>/<task>-%p.profraw"
+  )]
+  pub(crate) coverage_dir: Option<PathBuf>,
+  #[clap(
+    long,
+    help = "Write the merged configs of all tasks as JSON, with their sources unless --nodebug"
+  )]
+  pub(crate) dump_config: Option<PathBuf>,
+  #[clap(long, help = "Write a Markdown summary of the results, e.g., for a PR comment")]
+  pub(crate) summary_md: Option<PathBuf>,
+  #[clap(
+    long,
+    help = "Max bytes of each failure's errors in the Markdown summary",
+    default_value_t = 8192
+  )]
+  pub(crate) summary_md_budget: usize,
+  #[clap(
+    long,
+    help = "Write a self-contained HTML report of the results, e.g., to share"
+  )]
+  pub(crate) html: Option<PathBuf>,
+  #[clap(long, help = "Write the durations of the tasks as JSON, e.g., as a --baseline")]
+  pub(crate) timings: Option<PathBuf>,
+  #[clap(long, help = "Report the tasks slower than the durations of a --timings file")]
+  baseline: Option<PathBuf>,
+  #[clap(skip)]
+  pub(crate) baseline_timings: Option<Timings>,
+  #[clap(
+    long,
+    help = "The task is slower when it exceeds that factor of its baseline",
+    default_value_t = 1.3
+  )]
+  pub(crate) baseline_tolerance: f64,
+  #[clap(long, help = "Fail if any task is slower than its baseline")]
+  pub(crate) baseline_strict: bool,
+  #[clap(long, help = "Copy the inputs into workdir rather than symlink them")]
+  pub(crate) copy_inputs: bool,
+  #[clap(long, help = "Fail the tasks that modify their linked inputs")]
+  pub(crate) protect_inputs: bool,
+  #[clap(
+    long,
+    help = "Unix only, run each task in a new process group, and kill the orphans left by it"
+  )]
+  pub(crate) process_group: bool,
+  #[clap(
+    long,
+    help = "Stream the task's stdout/stderr to the terminal when only one task is selected"
+  )]
+  pub(crate) no_capture: bool,
+  #[clap(
+    long,
+    help = "Which workdirs of the tasks survive the run",
+    value_enum,
+    default_value_t = Retain::All
+  )]
+  pub(crate) retain: Retain,
+  #[clap(
+    long,
+    help = "How the workdirs of the tasks are laid out under the workdir",
+    value_enum,
+    default_value_t = WorkdirLayout::Mirror
+  )]
+  pub(crate) workdir_layout: WorkdirLayout,
+  #[clap(
+    long,
+    help = "The base of {{seed}}, which is the hash of the task path XOR <SEED>",
+    default_value_t = 0
+  )]
+  pub(crate) seed: u64,
+  #[clap(
+    long,
+    help = "Pick a random --seed for this run, printed in the summary for replay",
+    conflicts_with = "seed"
+  )]
+  pub(crate) random_seed: bool,
   #[clap(long, help = "Change the directory to perform test", default_value = "./tmp")]
   pub(crate) workdir: PathBuf,
-  #[clap(value_parser)]
+  #[clap(value_parser, help = "The root of the tasks", default_value = ".")]
   pub(crate) rootdir: PathBuf,
   #[clap(skip)]
   pub(crate) rootdir_abs: PathBuf,
+  /// The absolute workdir, only when it is inside rootdir
+  #[clap(skip)]
+  nested_workdir: Option<PathBuf>,
+  /// Whether the filesystem of the workdir is case-insensitive
+  #[clap(skip)]
+  pub(crate) workdir_case_insensitive: bool,
+}
+
+/// The flags in `.cargo-regression.toml`, the ones given in the CLI take precedence,
+/// and the relative paths are relative to its directory
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ProjectConfig {
+  cmd: Option<String>,
+  args: Option<Vec<String>>,
+  wrapper: Option<String>,
+  extensions: Option<Vec<String>>,
+  permits: Option<u32>,
+  permit_default: Option<u32>,
+  timeout: Option<u64>,
+  epsilon: Option<f32>,
+  workdir: Option<PathBuf>,
+  env_file: Option<Vec<PathBuf>>,
+  bin_dir: Option<PathBuf>,
+  nodebug: Option<bool>,
+  verbose: Option<bool>,
+  print_errs: Option<bool>,
+  clear_env: Option<bool>,
+  protect_inputs: Option<bool>,
+  process_group: Option<bool>,
+  check_unused_goldens: Option<bool>,
+  deny_unused_goldens: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, Subcommand)]
+pub(crate) enum Command {
+  #[command(about = "Print the JSON schema of the TOML config")]
+  Schema,
+}
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum Format {
+  #[default]
+  Pretty,
+  /// Test Anything Protocol
+  Tap,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SummaryStyle {
+  /// One sentence of the counts
+  #[default]
+  Line,
+  /// An aligned table of the counts, with a row per directory at `--group-depth`
+  Table,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ShowSkipped {
+  /// Count the filtered out tasks per top-level directory
+  #[default]
+  Dirs,
+  /// List every filtered out task
+  All,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Retain {
+  /// Keep all workdirs
+  #[default]
+  All,
+  /// Remove the workdir of each passed task right after it passes
+  Failed,
+  /// Remove all workdirs, only keep the reports of the failed tasks
+  None,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum WorkdirLayout {
+  /// Mirror the source tree, e.g., `<workdir>/sub/test.sh/`
+  #[default]
+  Mirror,
+  /// One level with the separators replaced by `--`, e.g., `<workdir>/sub--test.sh/`
+  Flat,
 }
 
 impl Args {
@@ -46,26 +351,219 @@ impl Args {
     self.nodebug = true;
     self
   }
+  pub const fn verbose(mut self) -> Self {
+    self.verbose = true;
+    self
+  }
   pub const fn print_errs(mut self) -> Self {
     self.print_errs = true;
     self
   }
+  /// Run the tasks with a cleared environment, only `envs` and `env-allowlist` are passed
+  pub const fn clear_env(mut self) -> Self {
+    self.clear_env = true;
+    self
+  }
+  /// Max bytes of each failure report (or printed errors), the rest is truncated
+  pub const fn max_report_bytes(mut self, max_report_bytes: usize) -> Self {
+    self.max_report_bytes = max_report_bytes;
+    self
+  }
+  pub const fn short_ids(mut self) -> Self {
+    self.short_ids = true;
+    self
+  }
+  pub const fn progress(mut self) -> Self {
+    self.progress = true;
+    self
+  }
+  pub const fn sort_output(mut self) -> Self {
+    self.sort_output = true;
+    self
+  }
+  pub const fn deny_ignored(mut self) -> Self {
+    self.deny_ignored = true;
+    self
+  }
+  pub const fn deny_filtered(mut self) -> Self {
+    self.deny_filtered = true;
+    self
+  }
+  /// Warn the golden files that no assert references
+  pub const fn check_unused_goldens(mut self) -> Self {
+    self.check_unused_goldens = true;
+    self
+  }
+  /// Fail if any golden file is unused, implies `check_unused_goldens`
+  pub const fn deny_unused_goldens(mut self) -> Self {
+    self.deny_unused_goldens = true;
+    self
+  }
+  /// List the ignored tasks, and the filtered out tasks as `show` in the summary
+  pub const fn show_skipped(mut self, show: ShowSkipped) -> Self {
+    self.show_skipped = Some(show);
+    self
+  }
+  pub const fn only_failed(mut self) -> Self {
+    self.only_failed = true;
+    self
+  }
+  pub const fn copy_inputs(mut self) -> Self {
+    self.copy_inputs = true;
+    self
+  }
+  /// Fail the tasks that modify their linked inputs
+  pub const fn protect_inputs(mut self) -> Self {
+    self.protect_inputs = true;
+    self
+  }
+  /// Unix only, run each task in a new process group, and kill the orphans left by it
+  pub const fn process_group(mut self) -> Self {
+    self.process_group = true;
+    self
+  }
+  /// Stream the task's stdout/stderr to the terminal when only one task is selected
+  pub const fn no_capture(mut self) -> Self {
+    self.no_capture = true;
+    self
+  }
+  /// Start the tasks with larger permit first
+  pub const fn heavy_first(mut self) -> Self {
+    self.heavy_first = true;
+    self
+  }
+  pub const fn format(mut self, format: Format) -> Self {
+    self.format = format;
+    self
+  }
+  /// Print every failure, instead of once for the identical ones
+  pub const fn no_dedupe_failures(mut self) -> Self {
+    self.no_dedupe_failures = true;
+    self
+  }
+  pub const fn summary(mut self, summary: SummaryStyle) -> Self {
+    self.summary = summary;
+    self
+  }
+  /// Which workdirs of the tasks survive the run, e.g., `Retain::Failed` to
+  /// save the disk space of a large suite
+  pub const fn retain(mut self, retain: Retain) -> Self {
+    self.retain = retain;
+    self
+  }
+  /// Lay out the workdirs of the tasks in one level, e.g., for the short paths
+  pub const fn workdir_layout(mut self, workdir_layout: WorkdirLayout) -> Self {
+    self.workdir_layout = workdir_layout;
+    self
+  }
+  /// The base of `{{seed}}`, i.e., `{{seed}}` is the hash of the task path XOR `seed`
+  pub const fn seed(mut self, seed: u64) -> Self {
+    self.seed = seed;
+    self
+  }
+  /// Pick a random base of `{{seed}}` for this run, printed in the summary for replay
+  pub const fn random_seed(mut self) -> Self {
+    self.random_seed = true;
+    self
+  }
   pub const fn permits(mut self, permits: u32) -> Self {
     self.permits = permits;
     self
   }
+  /// The permit of the tasks without `permit` in their configs, instead of 0
+  pub const fn permit_default(mut self, permit_default: u32) -> Self {
+    self.permit_default = permit_default;
+    self
+  }
+  /// Declare the total count of a named resource, e.g., `.resource("gpu", 2)`,
+  /// which is requested by the tasks' `resources = { gpu = 1 }`
+  pub fn resource(mut self, name: impl AsRef<str>, total: u32) -> Self {
+    self.resources.insert(name.as_ref().into(), total);
+    self
+  }
+  /// Limit the parallelism of preparing workdirs, which is released before
+  /// the task executes, so that slow disks are not thrashed
+  pub const fn io_permits(mut self, io_permits: u32) -> Self {
+    self.io_permits = Some(io_permits);
+    self
+  }
+  /// Summarize the failures by the directories at that depth under rootdir,
+  /// `0` to disable
+  pub const fn group_depth(mut self, group_depth: usize) -> Self {
+    self.group_depth = group_depth;
+    self
+  }
   pub const fn timeout(mut self, timeout: u64) -> Self {
     self.timeout = timeout;
     self
   }
+  /// Set the default executable of the tasks whose configs have no `cmd`,
+  /// e.g., `.cmd("bash").extensions(["sh"])` for a shell suite
   pub fn cmd(mut self, cmd: impl AsRef<str>) -> Self {
     self.cmd = cmd.as_ref().into();
     self
   }
+  pub fn wrapper(mut self, wrapper: impl AsRef<str>) -> Self {
+    self.wrapper = Some(wrapper.as_ref().into());
+    self
+  }
   pub fn args(mut self, iter: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
     self.args = iter.into_iter().map(|s| s.as_ref().into()).collect();
     self
   }
+  /// Register the binary path of `{{bin.<name>}}`, e.g.,
+  /// `.bin("mytool", env!("CARGO_BIN_EXE_mytool"))` in integration tests
+  pub fn bin(mut self, name: impl AsRef<str>, path: impl AsRef<Path>) -> Self {
+    self.bins.insert(name.as_ref().into(), path.as_ref().to_path_buf());
+    self
+  }
+  pub fn bin_dir(mut self, dir: impl AsRef<Path>) -> Self {
+    self.bin_dir = Some(dir.as_ref().to_path_buf());
+    self
+  }
+  pub fn coverage_dir(mut self, dir: impl AsRef<Path>) -> Self {
+    self.coverage_dir = Some(dir.as_ref().to_path_buf());
+    self
+  }
+  /// Write the merged configs of all tasks as JSON, for external tooling
+  pub fn dump_config(mut self, file: impl AsRef<Path>) -> Self {
+    self.dump_config = Some(file.as_ref().to_path_buf());
+    self
+  }
+  /// Write a Markdown summary of the results, e.g., for a PR comment
+  pub fn summary_md(mut self, file: impl AsRef<Path>) -> Self {
+    self.summary_md = Some(file.as_ref().to_path_buf());
+    self
+  }
+  pub const fn summary_md_budget(mut self, budget: usize) -> Self {
+    self.summary_md_budget = budget;
+    self
+  }
+  /// Write a self-contained HTML report of the results, e.g., to share
+  pub fn html(mut self, file: impl AsRef<Path>) -> Self {
+    self.html = Some(file.as_ref().to_path_buf());
+    self
+  }
+  /// Write the durations of the tasks as JSON, which can be a later `baseline`
+  pub fn timings(mut self, file: impl AsRef<Path>) -> Self {
+    self.timings = Some(file.as_ref().to_path_buf());
+    self
+  }
+  /// Report the tasks slower than the durations of a `timings` file
+  pub fn baseline(mut self, file: impl AsRef<Path>) -> Self {
+    self.baseline = Some(file.as_ref().to_path_buf());
+    self
+  }
+  /// The task is slower when it exceeds that factor of its baseline, default is 1.3
+  pub const fn baseline_tolerance(mut self, tolerance: f64) -> Self {
+    self.baseline_tolerance = tolerance;
+    self
+  }
+  /// Fail if any task is slower than its baseline
+  pub const fn baseline_strict(mut self) -> Self {
+    self.baseline_strict = true;
+    self
+  }
   pub fn workdir(mut self, dir: impl AsRef<Path>) -> Self {
     self.workdir = dir.as_ref().to_path_buf();
     self
@@ -74,6 +572,10 @@ impl Args {
     self.extensions = iter.into_iter().map(|s| s.as_ref().into()).collect();
     self
   }
+  pub fn env_file(mut self, iter: impl IntoIterator<Item = impl AsRef<Path>>) -> Self {
+    self.env_file = iter.into_iter().map(|s| s.as_ref().to_path_buf()).collect();
+    self
+  }
   pub fn include(mut self, iter: impl IntoIterator<Item = impl AsRef<Path>>) -> Self {
     self.include = iter.into_iter().map(|s| s.as_ref().to_path_buf()).collect();
     self
@@ -82,6 +584,24 @@ impl Args {
     self.exclude = iter.into_iter().map(|s| s.as_ref().to_path_buf()).collect();
     self
   }
+  /// Read more inputs to include from the files, one path per line,
+  /// the blank lines and `#` comments are ignored
+  pub fn include_from(
+    mut self,
+    iter: impl IntoIterator<Item = impl AsRef<Path>>,
+  ) -> Self {
+    self.include_from = iter.into_iter().map(|s| s.as_ref().to_path_buf()).collect();
+    self
+  }
+  /// Read more inputs to exclude from the files, one path per line,
+  /// the blank lines and `#` comments are ignored
+  pub fn exclude_from(
+    mut self,
+    iter: impl IntoIterator<Item = impl AsRef<Path>>,
+  ) -> Self {
+    self.exclude_from = iter.into_iter().map(|s| s.as_ref().to_path_buf()).collect();
+    self
+  }
   pub fn new(rootdir: impl AsRef<Path>) -> Self {
     <Self as Parser>::parse_from([Path::new(""), rootdir.as_ref()])
   }
@@ -93,40 +613,509 @@ impl Args {
     <Self as Parser>::parse_from(itr)
   }
   pub(crate) fn rebuild(mut self) -> Result<&'static Self, BuildError> {
-    self.rootdir_abs = std::fs::canonicalize(&self.rootdir)
-      .map_err(|e| BuildError::ReadDir(self.rootdir.to_path_buf(), e))?;
+    self.load_project_config()?;
+    self.rootdir_abs = std::fs::canonicalize(&self.rootdir).map_err(|e| {
+      if e.kind() == io::ErrorKind::NotFound {
+        BuildError::RootDirNotFound(self.rootdir.to_path_buf())
+      } else {
+        BuildError::ReadDir(self.rootdir.to_path_buf(), e)
+      }
+    })?;
+    let workdir_abs = std::fs::canonicalize(&self.workdir)
+      .or_else(|_| std::path::absolute(&self.workdir))
+      .map_err(|e| BuildError::ReadDir(self.workdir.to_path_buf(), e))?;
+    if self.permit_default > self.permits {
+      return Err(BuildError::PermitDefaultExceed(self.permit_default, self.permits));
+    }
+    self.workdir_case_insensitive = case_insensitive(&workdir_abs);
+    // the workdir will be cleaned, it should not contain the sources
+    if self.rootdir_abs.starts_with(&workdir_abs) {
+      return Err(BuildError::WorkdirContainsRootDir(
+        self.workdir.to_path_buf(),
+        self.rootdir.to_path_buf(),
+      ));
+    }
+    if workdir_abs.starts_with(&self.rootdir_abs) {
+      eprintln!(
+        "warning: workdir \"{}\" is inside rootdir \"{}\", its subtree is excluded",
+        self.workdir.display(),
+        self.rootdir.display()
+      );
+      self.nested_workdir = Some(workdir_abs);
+    }
+    for path in take(&mut self.include_from) {
+      self.include.extend(parse_path_list(&path)?);
+    }
+    for path in take(&mut self.exclude_from) {
+      self.exclude.extend(parse_path_list(&path)?);
+    }
+    self.include_walked = self
+      .include
+      .iter()
+      .filter_map(|path| {
+        let parent = std::fs::canonicalize(path.parent()?.join(".")).ok()?;
+        Some(parent.join(path.file_name()?))
+      })
+      .collect();
     self.include_set = take(&mut self.include)
       .into_iter()
       .map(|path| match std::fs::canonicalize(&path) {
         Ok(p) => Ok(p),
-        Err(e) => Err(BuildError::ReadDir(path, e)),
+        Err(_) => Err(BuildError::FilterNotFound("include", path)),
       })
       .collect::<Result<HashSet<_>, _>>()?;
     self.exclude_set = take(&mut self.exclude)
       .into_iter()
       .map(|path| match std::fs::canonicalize(&path) {
         Ok(p) => Ok(p),
-        Err(e) => Err(BuildError::ReadDir(path, e)),
+        Err(_) => Err(BuildError::FilterNotFound("exclude", path)),
       })
       .collect::<Result<HashSet<_>, _>>()?;
+    if let Some(dir) = self.coverage_dir.as_mut() {
+      std::fs::create_dir_all(&*dir)
+        .and_then(|_| std::fs::canonicalize(&*dir))
+        .map(|abs| *dir = abs)
+        .map_err(|e| BuildError::ReadDir(dir.to_path_buf(), e))?;
+    }
+    if self.only_failed {
+      let path = self.workdir.join(LAST_FAILED);
+      let s = std::fs::read_to_string(&path)
+        .map_err(|e| BuildError::UnableToRead(path.to_path_buf(), e))?;
+      self.last_failed_set = Some(s.lines().map(PathBuf::from).collect());
+    }
+    for resource in take(&mut self.resource) {
+      let Some((name, total)) = resource
+        .split_once('=')
+        .and_then(|(name, total)| Some((name.trim(), total.trim().parse().ok()?)))
+        .filter(|(name, _)| !name.is_empty())
+      else {
+        return Err(BuildError::InvalidResource(resource));
+      };
+      self.resources.insert(name.to_owned(), total);
+    }
+    if let Some(baseline) = &self.baseline {
+      self.baseline_timings = Some(read_timings(baseline)?);
+    }
+    if self.check_unused_goldens || self.deny_unused_goldens {
+      self.golden_refs = Some(GoldenRefs::default());
+    }
+    for path in take(&mut self.env_file) {
+      let envs = parse_env_file(&path)?;
+      self.file_envs.extend(envs);
+    }
     if self.extensions.iter().any(|s| s == "toml") {
       return Err(BuildError::InputExtToml);
     }
+    if self.random_seed {
+      self.seed = RandomState::new().build_hasher().finish();
+    }
     Ok(Box::leak(Box::new(self)))
   }
-  pub(super) fn filtered(&self, file: &Path) -> Result<bool, BuildError> {
-    let file_abs = std::fs::canonicalize(file)
-      .map_err(|e| BuildError::ReadDir(file.to_path_buf(), e))?;
+  /// Fill the flags that are not given (i.e., still the defaults) by
+  /// `.cargo-regression.toml` in the current directory, or else in rootdir
+  fn load_project_config(&mut self) -> Result<(), BuildError> {
+    fn fill<T: PartialEq>(flag: &mut T, default: T, project: Option<T>) {
+      if let Some(project) = project
+        && *flag == default
+      {
+        *flag = project;
+      }
+    }
+    let Some(path) = [PathBuf::from(PROJECT_CONFIG), self.rootdir.join(PROJECT_CONFIG)]
+      .into_iter()
+      .find(|path| path.is_file())
+    else {
+      return Ok(());
+    };
+    let s = std::fs::read_to_string(&path)
+      .map_err(|e| BuildError::UnableToRead(path.to_path_buf(), e))?;
+    let project = toml::from_str::<ProjectConfig>(&s)
+      .map_err(|e| BuildError::Toml(path.to_path_buf(), e))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let default = <Self as Parser>::parse_from([""]);
+    fill(&mut self.cmd, default.cmd, project.cmd);
+    fill(&mut self.args, default.args, project.args);
+    fill(&mut self.wrapper, default.wrapper, project.wrapper.map(Some));
+    fill(&mut self.extensions, default.extensions, project.extensions);
+    fill(&mut self.permits, default.permits, project.permits);
+    fill(&mut self.permit_default, default.permit_default, project.permit_default);
+    fill(&mut self.timeout, default.timeout, project.timeout);
+    fill(&mut self.epsilon, default.epsilon, project.epsilon.map(Some));
+    fill(&mut self.workdir, default.workdir, project.workdir.map(|p| dir.join(p)));
+    fill(
+      &mut self.env_file,
+      default.env_file,
+      project
+        .env_file
+        .map(|files| files.iter().map(|p| dir.join(p)).collect()),
+    );
+    fill(&mut self.bin_dir, default.bin_dir, project.bin_dir.map(|p| Some(dir.join(p))));
+    fill(&mut self.nodebug, default.nodebug, project.nodebug);
+    fill(&mut self.verbose, default.verbose, project.verbose);
+    fill(&mut self.print_errs, default.print_errs, project.print_errs);
+    fill(&mut self.clear_env, default.clear_env, project.clear_env);
+    fill(&mut self.protect_inputs, default.protect_inputs, project.protect_inputs);
+    fill(&mut self.process_group, default.process_group, project.process_group);
+    fill(
+      &mut self.check_unused_goldens,
+      default.check_unused_goldens,
+      project.check_unused_goldens,
+    );
+    fill(
+      &mut self.deny_unused_goldens,
+      default.deny_unused_goldens,
+      project.deny_unused_goldens,
+    );
+    Ok(())
+  }
+  /// Whether the dir (canonicalized) is the workdir, which should not be walked
+  pub(crate) fn is_workdir(&self, dir_abs: &Path) -> bool {
+    self.nested_workdir.as_deref() == Some(dir_abs)
+  }
+  /// The task path relative to rootdir
+  pub(crate) fn relative_path<'a>(&self, path: &'a Path) -> &'a str {
+    path.strip_prefix(&self.rootdir).unwrap_or(path).to_str().unwrap()
+  }
+  /// The workdir of the task by `--workdir-layout`
+  pub(crate) fn task_workdir(&self, path: &Path) -> PathBuf {
+    let relative = self.relative_path(path);
+    match self.workdir_layout {
+      WorkdirLayout::Mirror => self.workdir.join(relative),
+      WorkdirLayout::Flat => self.workdir.join(relative.replace(['/', '\\'], "--")),
+    }
+  }
+  /// The printed task id, relative to rootdir with `--short-ids`,
+  /// the full path is kept in verbose mode
+  pub(crate) fn task_id(&self, path: &Path) -> String {
+    if self.short_ids && !self.verbose {
+      self.relative_path(path).to_owned()
+    } else {
+      path.display().to_string()
+    }
+  }
+  /// The group of the task, i.e., its directory at `--group-depth` under rootdir
+  pub(crate) fn group(&self, path: &Path) -> String {
+    self.dir_at(path, self.group_depth)
+  }
+  /// The directory of the task at `depth` under rootdir, `.` for the top-level tasks
+  pub(crate) fn dir_at(&self, path: &Path, depth: usize) -> String {
+    let relative = Path::new(self.relative_path(path));
+    let dir = relative.parent().map_or(Path::new(""), |dir| dir);
+    let group = dir
+      .components()
+      .take(depth)
+      .map(|c| c.as_os_str().to_string_lossy())
+      .join("/");
+    if group.is_empty() { ".".to_owned() } else { group }
+  }
+  /// Resolve `{{bin.<name>}}` by the registered binaries,
+  /// then `$CARGO_BIN_EXE_<name>`, then `--bin-dir`
+  pub(crate) fn bin_path(&self, name: &str) -> Option<PathBuf> {
+    self.bin_path_in(name, std::env::var_os)
+  }
+  /// The same as [`Self::bin_path`], but read the variables by `var`
+  fn bin_path_in(
+    &self,
+    name: &str,
+    var: impl Fn(String) -> Option<OsString>,
+  ) -> Option<PathBuf> {
+    if let Some(path) = self.bins.get(name) {
+      return Some(path.clone());
+    }
+    if let Some(path) = var(format!("CARGO_BIN_EXE_{name}")) {
+      return Some(PathBuf::from(path));
+    }
+    self
+      .bin_dir
+      .as_ref()
+      .map(|dir| dir.join(name))
+      .filter(|path| path.is_file())
+  }
+  /// Whether all files under the dir (canonicalized) are filtered out,
+  /// except the symlinks that may point outside
+  pub(super) fn subtree_filtered(&self, dir_abs: &Path) -> bool {
+    let outside = |set: &HashSet<PathBuf>| !set.iter().any(|p| p.starts_with(dir_abs));
+    (!self.include_set.is_empty()
+      && outside(&self.include_set)
+      && outside(&self.include_walked))
+      || self.last_failed_set.as_ref().is_some_and(outside)
+  }
+  /// Whether the filtered out subtree is not walked at all, unless its tasks are
+  /// listed by `--show-skipped` or `--deny-filtered`
+  pub(super) fn pruned(&self, dir_abs: &Path) -> bool {
+    self.show_skipped.is_none() && !self.deny_filtered && self.subtree_filtered(dir_abs)
+  }
+  /// The `file_abs` should be canonicalized, so that the symlinks and their
+  /// targets are filtered the same
+  pub(super) fn filtered(&self, file_abs: &Path) -> bool {
     let included = if self.include_set.is_empty() {
       true
     } else {
-      self.include_set.contains(&file_abs)
+      self.include_set.contains(file_abs)
     };
+    let included =
+      included && self.last_failed_set.as_ref().is_none_or(|set| set.contains(file_abs));
     let excluded = if self.exclude_set.is_empty() {
       false
     } else {
-      self.exclude_set.contains(&file_abs)
+      self.exclude_set.contains(file_abs)
+    };
+    !included || excluded
+  }
+}
+
+/// Whether the filesystem of `path` is case-insensitive, probed by the nearest
+/// existing ancestor whose name has a cased letter: it is insensitive when the
+/// name with the case swapped is the same file. Otherwise guessed by the platform
+fn case_insensitive(path: &Path) -> bool {
+  for dir in path.ancestors() {
+    let Some(name) = dir.file_name().and_then(|name| name.to_str()) else {
+      continue;
+    };
+    let swapped: String = name
+      .chars()
+      .map(|c| {
+        if c.is_ascii_lowercase() {
+          c.to_ascii_uppercase()
+        } else {
+          c.to_ascii_lowercase()
+        }
+      })
+      .collect();
+    if swapped == name {
+      continue;
+    }
+    let Ok(meta) = dir.symlink_metadata() else {
+      continue;
+    };
+    let Ok(swapped_meta) = dir.with_file_name(swapped).symlink_metadata() else {
+      return false;
+    };
+    // both exist on a case-sensitive filesystem, as different files
+    #[cfg(unix)]
+    let same = {
+      use std::os::unix::fs::MetadataExt as _;
+      meta.dev() == swapped_meta.dev() && meta.ino() == swapped_meta.ino()
+    };
+    #[cfg(not(unix))]
+    let same = meta.file_type() == swapped_meta.file_type();
+    return same;
+  }
+  cfg!(any(windows, target_os = "macos"))
+}
+
+/// Parse the paths of `--include-from`/`--exclude-from`, one per line,
+/// with `#` comments
+fn parse_path_list(path: &Path) -> Result<Vec<PathBuf>, BuildError> {
+  let s = std::fs::read_to_string(path)
+    .map_err(|e| BuildError::UnableToRead(path.to_path_buf(), e))?;
+  Ok(
+    s.lines()
+      .map(str::trim)
+      .filter(|line| !line.is_empty() && !line.starts_with('#'))
+      .map(PathBuf::from)
+      .collect(),
+  )
+}
+
+/// Parse `KEY=VALUE` lines, with `#` comments and quoted values
+fn parse_env_file(path: &Path) -> Result<IndexMap<String, String>, BuildError> {
+  let s = std::fs::read_to_string(path)
+    .map_err(|e| BuildError::UnableToRead(path.to_path_buf(), e))?;
+  let mut envs = IndexMap::new();
+  for (idx, line) in s.lines().enumerate() {
+    let err = |msg| BuildError::EnvFile(path.to_path_buf(), idx + 1, msg);
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    let line = line.strip_prefix("export ").unwrap_or(line);
+    let Some((key, value)) = line.split_once('=') else {
+      return Err(err("expect 'KEY=VALUE'"));
     };
-    Ok(!included || excluded)
+    let key = key.trim();
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+      return Err(err("invalid key"));
+    }
+    let value = value.trim();
+    let value = if let Some(quoted) = value.strip_prefix('"') {
+      let Some(end) = quoted.rfind('"') else {
+        return Err(err("unclosed '\"'"));
+      };
+      quoted[..end]
+        .replace("\\n", "\n")
+        .replace("\\\"", "\"")
+        .replace("\\\\", "\\")
+    } else if let Some(quoted) = value.strip_prefix('\'') {
+      let Some(end) = quoted.rfind('\'') else {
+        return Err(err("unclosed '\\''"));
+      };
+      quoted[..end].to_owned()
+    } else {
+      // inline comment
+      value.split(" #").next().unwrap_or_default().trim_end().to_owned()
+    };
+    envs.insert(key.to_owned(), value);
+  }
+  Ok(envs)
+}
+
+#[test]
+fn test_env_file() {
+  let tmpdir = crate::TempDir::new("env-file");
+  let path = tmpdir.join("env-file.env");
+  std::fs::write(
+    &path,
+    r#"
+# comment
+K1=v1
+export K2 = "quoted # not comment \"v2\""
+K3='single $quoted'
+K4=v4 # inline comment
+K1=override
+"#,
+  )
+  .unwrap();
+  let envs = parse_env_file(&path).unwrap();
+  assert_eq!(envs["K1"], "override");
+  assert_eq!(envs["K2"], "quoted # not comment \"v2\"");
+  assert_eq!(envs["K3"], "single $quoted");
+  assert_eq!(envs["K4"], "v4");
+  std::fs::write(&path, "K1=v1\nK2 \"v2\"\n").unwrap();
+  assert!(matches!(parse_env_file(&path), Err(BuildError::EnvFile(_, 2, _))));
+}
+
+#[test]
+fn test_default_rootdir() {
+  let args = Args::parse_from([""]).rebuild().unwrap();
+  assert_eq!(args.rootdir, Path::new("."));
+  assert_eq!(args.rootdir_abs, std::env::current_dir().unwrap().canonicalize().unwrap());
+  assert_eq!(args.relative_path(Path::new("./demo/test.sh")), "demo/test.sh");
+}
+
+#[test]
+fn test_project_config() {
+  let tmpdir = crate::TempDir::new("project-config");
+  let rootdir = tmpdir.join("rootdir");
+  std::fs::create_dir_all(&rootdir).unwrap();
+  std::fs::write(
+    rootdir.join(PROJECT_CONFIG),
+    r#"
+cmd = "bash"
+extensions = ["sh"]
+permits = 4
+epsilon = 0.5
+workdir = "../cargo-regression-project-config-workdir"
+bin-dir = "bin"
+"#,
+  )
+  .unwrap();
+  let args = Args::new(&rootdir).rebuild().unwrap();
+  assert_eq!(args.cmd, "bash");
+  assert_eq!(args.extensions, ["sh"]);
+  assert_eq!(args.permits, 4);
+  assert_eq!(args.epsilon, Some(0.5));
+  // relative to the config's directory
+  assert_eq!(args.workdir, rootdir.join("../cargo-regression-project-config-workdir"));
+  assert_eq!(args.bin_dir, Some(rootdir.join("bin")));
+  // the CLI flags take precedence
+  let args = Args::parse_from([
+    Path::new(""),
+    Path::new("--permits"),
+    Path::new("2"),
+    Path::new("--cmd"),
+    Path::new("sh"),
+    &rootdir,
+  ])
+  .rebuild()
+  .unwrap();
+  assert_eq!((args.permits, args.cmd.as_str()), (2, "sh"));
+  assert_eq!(args.extensions, ["sh"]);
+  std::fs::write(rootdir.join(PROJECT_CONFIG), "permits = \"many\"").unwrap();
+  assert!(matches!(Args::new(&rootdir).rebuild(), Err(BuildError::Toml(..))));
+}
+
+#[test]
+fn test_bin_path() {
+  let args = Args::new("demo").bin("mytool", "/path/to/mytool");
+  assert_eq!(args.bin_path("mytool"), Some(PathBuf::from("/path/to/mytool")));
+  assert_eq!(args.bin_path("cargo-regression-unknown-bin"), None);
+  // not by the process environment, which the other tests read concurrently
+  let var = |key: String| {
+    (key == "CARGO_BIN_EXE_cargo-regression-env-bin").then(|| OsString::from("/env/bin"))
+  };
+  assert_eq!(
+    args.bin_path_in("cargo-regression-env-bin", var),
+    Some(PathBuf::from("/env/bin"))
+  );
+  // the registered binaries take precedence
+  assert_eq!(args.bin_path_in("mytool", var), Some(PathBuf::from("/path/to/mytool")));
+  let args = Args::new("demo").bin_dir("demo");
+  assert_eq!(args.bin_path("cmp.sh"), Some(PathBuf::from("demo/cmp.sh")));
+}
+
+#[test]
+fn test_not_found() {
+  let err = Args::new("cargo-regression-not-exist").rebuild().unwrap_err();
+  assert!(matches!(err, BuildError::RootDirNotFound(_)));
+  // the other errors are kept
+  #[cfg(unix)]
+  {
+    let err = Args::new("demo/cmp.sh/cargo-regression").rebuild().unwrap_err();
+    assert!(
+      matches!(err, BuildError::ReadDir(_, e) if e.kind() == io::ErrorKind::NotADirectory)
+    );
+  }
+  let err = Args::new("demo")
+    .exclude(["demo/cargo-regression-not-exist.sh"])
+    .rebuild()
+    .unwrap_err();
+  assert!(matches!(err, BuildError::FilterNotFound("exclude", _)));
+  for workdir in [".", "demo", "./demo/"] {
+    let err = Args::new("demo").workdir(workdir).rebuild().unwrap_err();
+    assert!(matches!(err, BuildError::WorkdirContainsRootDir(..)));
+  }
+}
+
+#[test]
+fn test_case_insensitive() {
+  let tmpdir = crate::TempDir::new("case-insensitive");
+  std::fs::write(tmpdir.join("probe"), "").unwrap();
+  let insensitive = tmpdir.join("PROBE").exists();
+  std::fs::create_dir(tmpdir.join("Work")).unwrap();
+  // probed by the nearest existing ancestor
+  assert_eq!(case_insensitive(&tmpdir.join("Work/not-exist/tmp")), insensitive);
+  if !insensitive {
+    // different files of the same name in different cases
+    std::fs::create_dir(tmpdir.join("WORK")).unwrap();
+    assert!(!case_insensitive(&tmpdir.join("Work")));
+  }
+}
+
+#[test]
+fn test_filter_from() {
+  let tmpdir = crate::TempDir::new("filter-from");
+  let list = tmpdir.join("filter-from.txt");
+  std::fs::write(
+    &list,
+    "# generated\ndemo/test-sh/test-match.sh\n\n  demo/test-py/test-exit.py  \n",
+  )
+  .unwrap();
+  let args = Args::new("demo")
+    .include(["demo/test-sh/test-glob.sh"])
+    .include_from([&list])
+    .rebuild()
+    .unwrap();
+  for file in ["test-sh/test-glob.sh", "test-sh/test-match.sh", "test-py/test-exit.py"] {
+    let file_abs = std::fs::canonicalize(Path::new("demo").join(file)).unwrap();
+    assert!(!args.filtered(&file_abs), "{file}");
   }
+  let other = std::fs::canonicalize("demo/test-sh/test-files.sh").unwrap();
+  assert!(args.filtered(&other));
+  let args = Args::new("demo").exclude_from([&list]).rebuild().unwrap();
+  assert!(args.filtered(&std::fs::canonicalize("demo/test-sh/test-match.sh").unwrap()));
+  assert!(!args.filtered(&other));
+  std::fs::write(&list, "demo/cargo-regression-not-exist.sh\n").unwrap();
+  let err = Args::new("demo").exclude_from([&list]).rebuild().unwrap_err();
+  assert!(matches!(err, BuildError::FilterNotFound("exclude", _)));
 }
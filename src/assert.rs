@@ -1,33 +1,145 @@
-use core::fmt;
+use core::{cmp::Ordering, fmt};
 use std::{
+  borrow::Cow,
+  collections::{BTreeSet, HashMap, HashSet},
   fmt::Display,
   io,
   iter::once,
   ops::Deref,
   path::{Path, PathBuf},
   process::{ExitStatus, Output},
+  sync::{Arc, Mutex, Weak},
+  time::Duration,
 };
 
+use colored::{ColoredString, Colorize};
 use indexmap::IndexMap;
+use itertools::Itertools;
 use serde::{Deserialize, Deserializer, Serialize};
-use tokio::{fs::read_to_string, process::Command};
+use similar::ChangeTag;
+use tokio::{
+  fs::{File, read},
+  io::{AsyncBufReadExt, BufReader},
+  process::Command,
+  sync::OnceCell,
+};
 
-use crate::config::{CmdDisplay, SigIntDisplay};
+use crate::{
+  config::{
+    CmdDisplay, DIFF_MAX_HUNKS, DIFF_THRESHOLD, EPSILON, STREAM_THRESHOLD, SigIntDisplay,
+    exit_signal, signal_name,
+  },
+  regression::GOLDEN_DIR,
+};
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 #[serde(rename_all = "kebab-case")]
 pub struct Assert {
   pub exit_code: Option<i32>,
+  /// Tolerate these exit codes regardless of `exit_code`, e.g., 141 (SIGPIPE)
+  pub ignore_exit_codes: Option<Vec<i32>>,
+  /// Unix only, assert the process is terminated by this signal
+  pub signal: Option<SignalName>,
+  /// Unix only, the max peak resident set size of the process in bytes
+  pub max_rss: Option<u64>,
+  /// Sugar of a golden on the stdout file with `equal = true`
+  pub stdout_equal: Option<bool>,
+  /// Sugar of a golden on the stdout file with these `match`
+  pub stdout_match: Option<Vec<Match>>,
+  /// Sugar of a golden on the stderr file, which should be empty
+  pub stderr_empty: Option<bool>,
+  /// Re-evaluate the failed goldens without rerunning the command
+  pub assert_retry: Option<AssertRetry>,
   pub golden: Option<Vec<Golden>>,
 }
 
+/// `assert-retry = { attempts = 3, delay-ms = 200 }`, the goldens are evaluated
+/// at most `attempts` times, waiting `delay-ms` before each retry
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub struct AssertRetry {
+  pub attempts: u32,
+  #[serde(default)]
+  pub delay_ms: u64,
+}
+
+impl Assert {
+  /// Desugar `stdout-equal`, `stdout-match` and `stderr-empty` into the goldens on
+  /// the stream files, `stderr` is `None` when it is merged into stdout.
+  /// The explicit golden on the same stream with the same assert is a conflict
+  pub(crate) fn desugar(
+    &mut self,
+    stdout: &str,
+    stderr: Option<&str>,
+  ) -> Result<(), String> {
+    let equal = self.stdout_equal.take().filter(|&equal| equal);
+    let matches = self.stdout_match.take();
+    let empty = self.stderr_empty.take().filter(|&empty| empty);
+    let has_equal: fn(&Golden) -> bool = |golden| golden.equal.is_some();
+    let has_match: fn(&Golden) -> bool = |golden| golden.r#match.is_some();
+    let conflict = |goldens: &[Golden], sugar: &str, file: &str, key: &str, has| {
+      goldens
+        .iter()
+        .filter(|golden| golden.patterns().any(|pattern| pattern == file))
+        .any(has)
+        .then(|| format!("`{sugar}` conflicts with the `{key}` of golden \"{file}\""))
+    };
+    let goldens = self.golden.get_or_insert_default();
+    if equal.is_some() || matches.is_some() {
+      let conflict = equal
+        .and_then(|_| conflict(goldens, "stdout-equal", stdout, "equal", has_equal))
+        .or_else(|| {
+          matches
+            .as_ref()
+            .and_then(|_| conflict(goldens, "stdout-match", stdout, "match", has_match))
+        });
+      if let Some(e) = conflict {
+        return Err(e);
+      }
+      goldens.push(Golden {
+        file: stdout.to_owned(),
+        equal,
+        r#match: matches,
+        ..Default::default()
+      });
+    }
+    if empty.is_some() {
+      let Some(stderr) = stderr else {
+        return Err(
+          "`stderr-empty` conflicts with the stderr merged into stdout".to_owned(),
+        );
+      };
+      if let Some(e) = conflict(goldens, "stderr-empty", stderr, "equal", has_equal) {
+        return Err(e);
+      }
+      // any character, even a newline
+      let pattern = PatternMatch(regex::Regex::new("(?s).+").expect("valid regex"));
+      let empty = Match {
+        pattern,
+        count: Some(0),
+        count_at_most: None,
+        count_at_least: None,
+      };
+      goldens.push(Golden {
+        file: stderr.to_owned(),
+        r#match: Some(vec![empty]),
+        ..Default::default()
+      });
+    }
+    if goldens.is_empty() {
+      self.golden = None;
+    }
+    Ok(())
+  }
+}
+
 trait AssertT {
   async fn assert(
     &self,
     config: AssertConfig,
     workdir: &Path,
     file_name: &str,
-    golden: Option<&str>,
+    golden: Result<&str, &io::Error>,
     output: &str,
     errs: &mut Vec<AssertError>,
   );
@@ -41,10 +153,20 @@ pub enum AssertError {
   ProcessStatus(String, String),
   #[error("execute: {1}\n{0}")]
   Executes(String, io::Error),
-  #[error("exit code, want: {want}, got: {got}")]
-  ExitCode { want: i32, got: i32 },
-  #[error("file \"{0}\": Unable to read")]
-  UnableToRead(String),
+  #[error("exit code, want: {want}, got: {got}\n{cmd}")]
+  ExitCode { want: i32, got: i32, cmd: String },
+  #[error("signal, want: {want}, got: {got}{raw}\n{cmd}")]
+  Signal { want: &'static str, got: String, raw: SigIntDisplay, cmd: String },
+  #[error("peak RSS, want at most: {want} bytes, got: {got} bytes\n{cmd}")]
+  MaxRss { want: u64, got: u64, cmd: String },
+  #[error("`max-rss` is only supported on unix")]
+  MaxRssUnsupported,
+  #[error("file \"{0}\": {1}")]
+  UnableToRead(String, io::Error),
+  #[error("file \"{0}\": no such file")]
+  NoSuchFile(String),
+  #[error("file \"{0}\": contains invalid UTF-8, compared lossily")]
+  Lossy(String),
   #[error("dir \"{0}\": {1}")]
   UnableToReadDir(String, io::Error),
   #[error("dir \"{0}\": {1}")]
@@ -53,8 +175,20 @@ pub enum AssertError {
   UnableToDeleteDir(String, io::Error),
   #[error("link \"{0}\" to \"{1}\": {2}")]
   LinkFile(String, String, io::Error),
+  #[error("link \"{0}\" to \"{1}\": collides with an already linked file")]
+  LinkCollision(String, String),
   #[error("file \"{file_name}\" not equal\n{diffs}")]
   Eq { file_name: String, diffs: TextDiffs },
+  #[error("file \"{file_name}\" not equal to \"{other}\"\n{diffs}")]
+  SameAs { file_name: String, other: String, diffs: TextDiffs },
+  #[error(
+    "file \"{0}\": its size {1} exceeds `stream-threshold` = {2}, can not assert `equal`, `same-as`, `unique`, `json-contains`, or `yaml-equal`, use `custom` instead, e.g., compare the checksums"
+  )]
+  TooLarge(String, u64, u64),
+  #[error(
+    "file \"{0}\": pattern '{1}' contains newline, can not be streamed line by line, raise `stream-threshold`"
+  )]
+  MultiLine(String, regex::Regex),
   #[error("write file \"{0}\": {1}")]
   Write(String, io::Error),
   #[error("execution terminated by a signal: {0}{1}\n{2}")]
@@ -65,88 +199,471 @@ pub enum AssertError {
   CountConfig,
   #[error("file \"{0}\" match failed\n{1}")]
   Match(String, MatchReport),
+  #[error("file \"{file_name}\" want {}{count} lines, got: {got}", cond_str(*.cond))]
+  Lines { file_name: String, count: usize, cond: Option<MatchCond>, got: usize },
   #[error("file \"{0}\" value assert failed\n{1}")]
   Value(String, ValueReport),
+  #[error("file \"{0}\" not sorted\n{1}")]
+  Sorted(String, SortedReport),
+  #[error("file \"{0}\" not unique\n{1}")]
+  Unique(String, UniqueReport),
+  #[error("file \"{0}\" json-contains failed\n{1}")]
+  JsonContains(String, StructReport),
+  #[error("file \"{0}\" yaml-equal failed\n{1}")]
+  YamlEqual(String, StructReport),
   #[error("file \"{0}\" custom assert failed\n{1}")]
   Custom(String, Box<CustomReport>),
+  #[error("dir \"{0}\" not equal\n{1}")]
+  Tree(String, TreeReport),
+  #[error(
+    "input \"{path}\" modified by the task{}",
+    .diffs.as_ref().map(|diffs| format!("\n{diffs}")).unwrap_or_default()
+  )]
+  InputModified { path: String, diffs: Option<TextDiffs> },
   #[error("regular expression: {0}")]
   Regex(regex::Error),
   #[error("path pattern: {0}")]
   PatternError(glob::PatternError),
   #[error("path: {0}")]
   GlobError(glob::GlobError),
+  #[error("lock \"{0}\": {1}")]
+  Flock(String, io::Error),
+  #[error("could not acquire lock \"{0}\" within {1} s")]
+  FlockTimeOut(String, u64),
+  #[error("reserve a port: {0}")]
+  ReservePort(io::Error),
   #[error("run out of timeout = {0} secend(s)")]
   TimeOut(u64),
+  #[error("the task left orphaned processes in its process group, they are killed\n{0}")]
+  Orphaned(String),
+  #[error("the goldens below still failed after {0} attempts of `assert-retry`")]
+  AssertRetried(u32),
   #[error("{0}")]
   IO(#[from] io::Error),
+  /// The error of a `soft` golden, which is only a warning
+  #[error("{0}")]
+  Soft(Box<AssertError>),
+}
+
+impl AssertError {
+  /// Whether it is only a warning, see `soft`
+  pub const fn is_soft(&self) -> bool {
+    matches!(self, Self::Soft(_))
+  }
+  /// The stable category of the error, e.g., `"exit_code"`, `"eq"`, `"match"`,
+  /// or `"timeout"`, so that the consumers need not match the messages.
+  /// A `soft` error has the code of the error it wraps
+  pub fn code(&self) -> &'static str {
+    match self {
+      Self::ProcessExec(..) => "process_exec",
+      Self::ProcessStatus(..) => "process_status",
+      Self::Executes(..) => "executes",
+      Self::ExitCode { .. } => "exit_code",
+      Self::Signal { .. } => "signal",
+      Self::MaxRss { .. } => "max_rss",
+      Self::MaxRssUnsupported => "max_rss_unsupported",
+      Self::UnableToRead(..) => "unable_to_read",
+      Self::NoSuchFile(..) => "no_such_file",
+      Self::Lossy(..) => "lossy",
+      Self::UnableToReadDir(..) => "unable_to_read_dir",
+      Self::UnableToCreateDir(..) => "unable_to_create_dir",
+      Self::UnableToDeleteDir(..) => "unable_to_delete_dir",
+      Self::LinkFile(..) => "link_file",
+      Self::LinkCollision(..) => "link_collision",
+      Self::Eq { .. } => "eq",
+      Self::SameAs { .. } => "same_as",
+      Self::TooLarge(..) => "too_large",
+      Self::MultiLine(..) => "multi_line",
+      Self::Write(..) => "write",
+      Self::Terminated(..) => "terminated",
+      Self::CountConfig => "count_config",
+      Self::Match(..) => "match",
+      Self::Lines { .. } => "lines",
+      Self::Value(..) => "value",
+      Self::Sorted(..) => "sorted",
+      Self::Unique(..) => "unique",
+      Self::JsonContains(..) => "json_contains",
+      Self::YamlEqual(..) => "yaml_equal",
+      Self::Custom(..) => "custom",
+      Self::Tree(..) => "tree",
+      Self::InputModified { .. } => "input_modified",
+      Self::Regex(..) => "regex",
+      Self::PatternError(..) => "pattern",
+      Self::GlobError(..) => "glob",
+      Self::Flock(..) => "flock",
+      Self::FlockTimeOut(..) => "flock_timeout",
+      Self::ReservePort(..) => "reserve_port",
+      Self::TimeOut(..) => "timeout",
+      Self::Orphaned(..) => "orphaned",
+      Self::AssertRetried(..) => "assert_retried",
+      Self::IO(..) => "io",
+      Self::Soft(e) => e.code(),
+    }
+  }
+  /// The asserted file (relative to workdir) or the file failed to read/write,
+  /// `None` if the error is not about a file
+  pub fn file(&self) -> Option<&str> {
+    match self {
+      Self::UnableToRead(file, _)
+      | Self::NoSuchFile(file)
+      | Self::Lossy(file)
+      | Self::Eq { file_name: file, .. }
+      | Self::SameAs { file_name: file, .. }
+      | Self::TooLarge(file, ..)
+      | Self::MultiLine(file, _)
+      | Self::Write(file, _)
+      | Self::Match(file, _)
+      | Self::Lines { file_name: file, .. }
+      | Self::Value(file, _)
+      | Self::Sorted(file, _)
+      | Self::Unique(file, _)
+      | Self::JsonContains(file, _)
+      | Self::YamlEqual(file, _)
+      | Self::Custom(file, _)
+      | Self::Tree(file, _)
+      | Self::InputModified { path: file, .. } => Some(file),
+      Self::Soft(e) => e.file(),
+      _ => None,
+    }
+  }
+  const fn label(&self) -> &'static str {
+    if self.is_soft() { "WARNING" } else { "ERROR" }
+  }
 }
 
-pub(crate) struct DisplayErrs<'a, E: fmt::Display>(pub(crate) &'a Vec<E>);
-impl<E: fmt::Display> fmt::Display for DisplayErrs<'_, E> {
+/// All errors of a report, the reports themselves are bounded by [`bounded_errs`]
+#[cfg(test)]
+pub(crate) struct DisplayErrs<'a>(pub(crate) &'a Vec<AssertError>);
+#[cfg(test)]
+impl fmt::Display for DisplayErrs<'_> {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     for (n, err) in self.0.iter().enumerate() {
-      writeln!(f, "==== ERROR {} ===\n{err}", n + 1)?;
+      write!(f, "{}", NthErr { n, err, colored: false })?;
     }
     Ok(())
   }
 }
 
+/// The n-th error of a report, colorize the text diffs when `colored` and the
+/// color output is enabled, only for the terminal, the reports are kept plain
+struct NthErr<'a> {
+  n: usize,
+  err: &'a AssertError,
+  colored: bool,
+}
+impl fmt::Display for NthErr<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(f, "==== {} {} ===", self.err.label(), self.n + 1)?;
+    let err = if let AssertError::Soft(err) = self.err { err } else { self.err };
+    match err {
+      AssertError::Eq { file_name, diffs } if self.colored => {
+        writeln!(f, "file \"{file_name}\" not equal")?;
+        diffs.render(f, true)?;
+      }
+      AssertError::Tree(dir, report) if self.colored => {
+        writeln!(f, "dir \"{dir}\" not equal")?;
+        report.render(f, true)?;
+      }
+      AssertError::SameAs { file_name, other, diffs } if self.colored => {
+        writeln!(f, "file \"{file_name}\" not equal to \"{other}\"")?;
+        diffs.render(f, true)?;
+      }
+      AssertError::InputModified { path, diffs: Some(diffs) } if self.colored => {
+        writeln!(f, "input \"{path}\" modified by the task")?;
+        diffs.render(f, true)?;
+      }
+      err => write!(f, "{err}")?,
+    }
+    writeln!(f)
+  }
+}
+
+/// Keep at most `max` bytes, and fail the writes after that,
+/// so that the rendering of a huge error stops early
+pub(crate) struct BoundedWriter {
+  buf: String,
+  max: usize,
+  truncated: bool,
+}
+impl BoundedWriter {
+  pub(crate) const fn new(max: usize) -> Self {
+    Self { buf: String::new(), max, truncated: false }
+  }
+}
+impl fmt::Write for BoundedWriter {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    let left = self.max.saturating_sub(self.buf.len());
+    if s.len() <= left {
+      self.buf.push_str(s);
+      Ok(())
+    } else {
+      let end = (0..=left).rev().find(|&idx| s.is_char_boundary(idx)).unwrap_or(0);
+      self.buf.push_str(&s[..end]);
+      self.truncated = true;
+      Err(fmt::Error)
+    }
+  }
+}
+
+/// Render the errors and the tails of `report-files` within `max` bytes, the rest
+/// are truncated with a trailer, so that a huge diff can not fill the disk
+pub(crate) fn bounded_errs(
+  errs: &[AssertError],
+  tails: &str,
+  max: usize,
+  colored: bool,
+) -> String {
+  use fmt::Write as _;
+  let mut writer = BoundedWriter::new(max);
+  let written = errs
+    .iter()
+    .enumerate()
+    .take_while(|&(n, err)| write!(writer, "{}", NthErr { n, err, colored }).is_ok())
+    .count();
+  if written == errs.len() {
+    _ = writer.write_str(tails);
+  }
+  let mut s = writer.buf;
+  if writer.truncated {
+    s.push_str(&format!(
+      "\n==== TRUNCATED ===\nreport truncated at {max} bytes; {} error(s) omitted\n",
+      errs.len() - written
+    ));
+  }
+  s
+}
+
+/// The options of the golden asserts, see [`compare`]
 #[derive(Debug, Clone, Copy)]
-pub(crate) struct AssertConfig {
-  pub(crate) epsilon: f32,
+pub struct AssertConfig {
+  /// The tolerance of `value`
+  pub epsilon: f32,
+  /// Print the notes of the tolerated asserts
+  pub verbose: bool,
+  /// Stream the larger files line by line
+  pub stream_threshold: u64,
+  /// Only report the first difference for the larger text
+  pub diff_threshold: u64,
+  /// Truncate the text diffs after that many hunks
+  pub diff_max_hunks: usize,
+  /// Also pass `epsilon` to the custom asserts, see `legacy-env`
+  pub legacy_env: bool,
+}
+impl Default for AssertConfig {
+  /// The same as the defaults of the config files
+  #[inline]
+  fn default() -> Self {
+    Self {
+      epsilon: EPSILON,
+      verbose: false,
+      stream_threshold: STREAM_THRESHOLD,
+      diff_threshold: DIFF_THRESHOLD,
+      diff_max_hunks: DIFF_MAX_HUNKS,
+      legacy_env: false,
+    }
+  }
 }
 impl Assert {
   #[inline]
+  #[expect(clippy::too_many_arguments)]
   pub async fn assert(
     self,
     config: AssertConfig,
     workdir: PathBuf,
     golden_dir: PathBuf,
     status: ExitStatus,
+    peak_rss: Option<u64>,
+    cmd: String,
+    refs: Option<&GoldenRefs>,
   ) -> Vec<AssertError> {
     let mut errs = Vec::new();
-    // exit_code
-    let exit_code_want = self.exit_code.unwrap_or(0);
-    if let Some(exit_code_got) = status.code() {
-      if exit_code_want != exit_code_got {
-        errs.push(AssertError::ExitCode { want: exit_code_want, got: exit_code_got });
+    if let Some(want) = self.max_rss {
+      match peak_rss {
+        Some(got) if got > want => {
+          errs.push(AssertError::MaxRss { want, got, cmd: cmd.clone() });
+        }
+        Some(got) => {
+          if config.verbose {
+            eprintln!("note: peak RSS {got} bytes, want at most {want} bytes\n{cmd}");
+          }
+        }
+        None => errs.push(AssertError::MaxRssUnsupported),
       }
     }
-    // golden
-    let futures = if let Some(goldens) = self.golden {
-      goldens
-        .into_iter()
-        .map(|golden| {
-          let workdir = workdir.clone();
-          let golden_dir = golden_dir.clone();
-          tokio::spawn(golden.process_assert(config, workdir, golden_dir))
-        })
-        .collect()
+    if let Some(want) = self.signal {
+      // signal, the exit code is meaningless when it is killed
+      let sig_int = exit_signal(status);
+      if sig_int != Some(want.raw()) {
+        errs.push(AssertError::Signal {
+          want: want.0.as_str(),
+          got: match status.code() {
+            Some(code) => format!("no signal (exit code {code})"),
+            None => signal_name(sig_int).to_owned(),
+          },
+          raw: SigIntDisplay(sig_int),
+          cmd,
+        });
+      }
     } else {
-      Vec::new()
+      // exit_code
+      let exit_code_want = self.exit_code.unwrap_or(0);
+      if let Some(exit_code_got) = status.code()
+        && exit_code_want != exit_code_got
+      {
+        if self
+          .ignore_exit_codes
+          .as_ref()
+          .is_some_and(|codes| codes.contains(&exit_code_got))
+        {
+          if config.verbose {
+            eprintln!(
+              "note: tolerated exit code {exit_code_got}, want {exit_code_want}\n{cmd}"
+            );
+          }
+        } else {
+          errs.push(AssertError::ExitCode {
+            want: exit_code_want,
+            got: exit_code_got,
+            cmd,
+          });
+        }
+      }
+    }
+    // golden, run concurrently within the task, and report in the declared order,
+    // only they are retried, since the output files may not be settled yet
+    let goldens = self.golden.unwrap_or_default();
+    let attempts = self.assert_retry.map_or(1, |retry| retry.attempts.max(1));
+    let mut attempt = 1;
+    let golden_errs = loop {
+      // not cached across the attempts, the files may be rewritten
+      let cache = ReadCache::default();
+      let futures = goldens.iter().cloned().map(|golden| {
+        golden.process_assert(
+          config,
+          workdir.clone(),
+          golden_dir.clone(),
+          cache.clone(),
+          refs,
+        )
+      });
+      let golden_errs: Vec<_> = futures::future::join_all(futures)
+        .await
+        .into_iter()
+        .flatten()
+        .collect();
+      if attempt == attempts || golden_errs.iter().all(AssertError::is_soft) {
+        break golden_errs;
+      }
+      attempt += 1;
+      let delay_ms = self.assert_retry.map_or(0, |retry| retry.delay_ms);
+      tokio::time::sleep(Duration::from_millis(delay_ms)).await;
     };
-    // await
-    for f in futures.into_iter() {
-      errs.extend(f.await.expect("join handle"));
+    if golden_errs.iter().all(AssertError::is_soft) {
+      if config.verbose && attempt != 1 {
+        eprintln!("note: the goldens passed at attempt {attempt} of `assert-retry`");
+      }
+    } else if attempt != 1 {
+      errs.push(AssertError::AssertRetried(attempt));
     }
+    errs.extend(golden_errs);
     errs
   }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 #[serde(rename_all = "kebab-case")]
 pub struct Golden {
+  #[serde(default, skip_serializing_if = "String::is_empty")]
   pub file: String,
+  /// Multiple output files, sharing the same asserts
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub files: Vec<String>,
   equal: Option<bool>,
+  /// The output file should equal to another output file in workdir
+  pub same_as: Option<String>,
+  /// The `file` is a directory, which should equal to the golden directory,
+  /// i.e., the same relative paths, and each file `equal`
+  tree: Option<bool>,
+  /// Ignore the trailing whitespaces (including newlines) in `equal` and `same-as`
+  ignore_trailing_whitespace: Option<bool>,
+  /// Ignore the trailing newlines in `equal` and `same-as`
+  ignore_trailing_newlines: Option<bool>,
   r#match: Option<Vec<Match>>,
-  value: Option<Vec<Value>>,
+  /// The number of the output lines, a trailing newline does not start a new line
+  lines: Option<Lines>,
+  pub value: Option<Vec<Value>>,
+  /// The output lines should be in order
+  sorted: Option<SortSpec>,
+  /// No output line should appear more than once
+  unique: Option<bool>,
+  /// The output JSON should contain these keys with the same values, deeply,
+  /// the extra keys are ignored
+  json_contains: Option<serde_json::Map<String, serde_json::Value>>,
+  /// The output YAML should equal to the golden, ignoring the key order and formatting
+  yaml_equal: Option<bool>,
   pub custom: Option<Vec<Custom>>,
+  /// Report the errors as warnings, which do not fail the task
+  soft: Option<bool>,
 }
 
 impl Golden {
+  /// All glob patterns of `file` and `files`
+  pub(crate) fn patterns(&self) -> impl Iterator<Item = &str> {
+    (!self.file.is_empty())
+      .then_some(self.file.as_str())
+      .into_iter()
+      .chain(self.files.iter().map(String::as_str))
+  }
+  /// Whether it reads that output file, by `file`, `files`, or `same-as`
+  pub(crate) fn reads(&self, file: &str) -> bool {
+    self.patterns().chain(self.same_as.as_deref()).any(|pattern| {
+      glob::Pattern::new(pattern).map_or(pattern == file, |pattern| pattern.matches(file))
+    })
+  }
+  /// The resolved output files and what they are asserted against,
+  /// one pattern per line, for `__debug__.{name}.cmd`
+  pub(crate) fn targets(&self, workdir: &Path, golden_dir: &Path) -> String {
+    let mut s = String::new();
+    for pattern in self.patterns() {
+      s.push_str(&format!("{}\n", glob_pattern(workdir, pattern)));
+      if let Some(true) = self.equal {
+        s.push_str(&format!("  equal: {}\n", golden_dir.join(pattern).display()));
+      }
+      if let Some(true) = self.tree {
+        s.push_str(&format!("  tree: {}\n", golden_dir.join(pattern).display()));
+      }
+      if let Some(true) = self.yaml_equal {
+        s.push_str(&format!("  yaml-equal: {}\n", golden_dir.join(pattern).display()));
+      }
+      if let Some(other) = &self.same_as {
+        s.push_str(&format!("  same-as: {}\n", workdir.join(other).display()));
+      }
+    }
+    s
+  }
+  /// Trim the text before `equal` and `same-as`
+  fn trim<'a>(&self, s: &'a str) -> &'a str {
+    if let Some(true) = self.ignore_trailing_whitespace {
+      s.trim_end()
+    } else if let Some(true) = self.ignore_trailing_newlines {
+      s.trim_end_matches(['\n', '\r'])
+    } else {
+      s
+    }
+  }
   fn _validate(&self) -> Result<(), impl Display> {
-    if self.equal.is_none() && self.r#match.is_none() && self.value.is_none() {
-      return Err(format!("no assert for file \"{}\"", self.file));
+    if self.equal.is_none()
+      && self.tree.is_none()
+      && self.same_as.is_none()
+      && self.r#match.is_none()
+      && self.lines.is_none()
+      && self.value.is_none()
+      && self.sorted.is_none()
+      && self.unique.is_none()
+      && self.json_contains.is_none()
+      && self.yaml_equal.is_none()
+    {
+      return Err(format!("no assert for file \"{}\"", self.patterns().join(", ")));
     }
     Ok(())
   }
@@ -183,15 +700,129 @@ impl Serialize for PatternMatch {
   }
 }
 
+#[cfg(unix)]
+use nix::sys::signal::Signal;
+/// The signals are unix only, it can not be constructed on other platforms
+#[cfg(not(unix))]
+#[derive(Debug, Clone, Copy)]
+enum Signal {}
+#[cfg(not(unix))]
+impl Signal {
+  fn as_str(self) -> &'static str {
+    match self {}
+  }
+}
+#[cfg(not(unix))]
+impl core::str::FromStr for Signal {
+  type Err = &'static str;
+  fn from_str(_: &str) -> Result<Self, Self::Err> {
+    Err("only supported on unix")
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SignalName(Signal);
+
+impl SignalName {
+  fn raw(self) -> i32 {
+    #[cfg(unix)]
+    {
+      self.0 as i32
+    }
+    #[cfg(not(unix))]
+    match self.0 {}
+  }
+}
+
+impl<'de> Deserialize<'de> for SignalName {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let s = String::deserialize(deserializer)?;
+    match s.parse() {
+      Ok(sig) => Ok(SignalName(sig)),
+      Err(e) => Err(serde::de::Error::custom(format!("signal '{s}': {e}"))),
+    }
+  }
+}
+impl Serialize for SignalName {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: serde::Serializer,
+  {
+    serializer.serialize_str(self.0.as_str())
+  }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct Value {
   pattern_before: Option<PatternMatch>,
   pattern_after: Option<PatternMatch>,
   value: Option<f32>,
+  /// The file in the golden dir, whose single float is the exact value
+  pub value_file: Option<String>,
   value_at_most: Option<f32>,
   value_at_least: Option<f32>,
+  /// The captured values should be monotonic, tolerated by `epsilon`
+  monotonic: Option<Direction>,
   epsilon: Option<f32>,
+  /// The table form, capture the key of each row, e.g., `phase=(\w+)`
+  key_pattern: Option<PatternMatch>,
+  /// The table form, capture the value on the same row as the key
+  value_pattern: Option<PatternMatch>,
+  /// The table form, the expected value of each key
+  expect: Option<IndexMap<String, Expect>>,
+  /// The table form, allow the keys not in `expect`
+  allow_extra: Option<bool>,
+}
+
+/// The expected value of a key in the table form, e.g., `parse = 1.5` or
+/// `parse = { value = 1.5, epsilon = 0.1 }`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(untagged)]
+pub enum Expect {
+  Value(f32),
+  WithEpsilon { value: f32, epsilon: Option<f32> },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+  NonDecreasing,
+  NonIncreasing,
+}
+
+impl fmt::Display for Direction {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::NonDecreasing => write!(f, "non-decreasing"),
+      Self::NonIncreasing => write!(f, "non-increasing"),
+    }
+  }
+}
+
+/// How the lines of `sorted` are compared
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortSpec {
+  /// By the bytes
+  Lines,
+  /// By the leading numbers, the lines without a number are 0, as `sort -n`
+  Numeric,
+  /// By the lowercase lines
+  CaseInsensitive,
+}
+
+impl fmt::Display for SortSpec {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Lines => write!(f, "lines"),
+      Self::Numeric => write!(f, "numeric"),
+      Self::CaseInsensitive => write!(f, "case-insensitive"),
+    }
+  }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -203,139 +834,772 @@ pub struct Match {
   count_at_least: Option<usize>,
 }
 
+/// The number of the output lines, with the same count semantics as [`Match`]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub struct Lines {
+  count: Option<usize>,
+  count_at_most: Option<usize>,
+  count_at_least: Option<usize>,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct Custom {
   pub cmd: String,
   pub envs: Option<IndexMap<String, String>>,
+  /// The working directory of `cmd`, relative to the task's workdir,
+  /// default is the task's workdir
+  pub workdir: Option<String>,
+}
+
+/// The path relative to workdir, e.g., `sub/out.txt`, ignore the leading `./`
+pub(crate) fn relative_to<'a>(path: &'a Path, workdir: &Path) -> &'a Path {
+  let path = path.strip_prefix(".").unwrap_or(path);
+  path
+    .strip_prefix(workdir.strip_prefix(".").unwrap_or(workdir))
+    .unwrap_or(path)
+}
+
+/// Only the `file` is a pattern, escape the workdir in case it contains `[`, `?`, or `*`
+pub(crate) fn glob_pattern(workdir: &Path, file: &str) -> String {
+  let workdir = glob::Pattern::escape(&workdir.display().to_string());
+  Path::new(&workdir).join(file).display().to_string()
+}
+
+fn sort_entry(
+  e1: &Result<PathBuf, glob::GlobError>,
+  e2: &Result<PathBuf, glob::GlobError>,
+) -> Ordering {
+  match (e1, e2) {
+    (Ok(p1), Ok(p2)) => p1.cmp(p2),
+    (Ok(_), Err(_)) => Ordering::Less,
+    (Err(_), Ok(_)) => Ordering::Greater,
+    (Err(e1), Err(e2)) => e1.path().cmp(e2.path()),
+  }
+}
+
+/// The pattern which contains newline can not be streamed line by line
+fn is_multi_line(pattern: &str) -> bool {
+  pattern.contains('\n') || pattern.contains("\\n")
+}
+
+/// Read the file lossily, `true` when it contains invalid UTF-8
+async fn read_lossy(path: impl AsRef<Path>) -> io::Result<(String, bool)> {
+  Ok(match String::from_utf8(read(path).await?) {
+    Ok(s) => (s, false),
+    Err(e) => (String::from_utf8_lossy(e.as_bytes()).into_owned(), true),
+  })
+}
+
+type ReadCell = OnceCell<io::Result<(String, bool)>>;
+
+/// The read files of a task, shared by its golden tasks, so that each file
+/// (output or golden) is read once even if multiple goldens target it.
+/// Only weakly referenced, a file is dropped after its last use
+#[derive(Debug, Clone, Default)]
+struct ReadCache(Arc<Mutex<HashMap<PathBuf, Weak<ReadCell>>>>);
+
+/// The content of a file read by [`ReadCache`], and whether it is lossy
+#[derive(Debug)]
+struct Cached {
+  cell: Arc<ReadCell>,
+}
+
+impl Deref for Cached {
+  type Target = (String, bool);
+  fn deref(&self) -> &Self::Target {
+    // only the successful reads are wrapped
+    self.cell.get().and_then(|res| res.as_ref().ok()).expect("read")
+  }
+}
+
+/// The golden files referenced by the asserts of all tasks, for
+/// `--check-unused-goldens`, a directory of `tree` references all files under it
+#[derive(Debug, Default)]
+pub(crate) struct GoldenRefs(Mutex<HashSet<PathBuf>>);
+
+impl GoldenRefs {
+  fn insert(&self, golden: PathBuf) {
+    self.0.lock().unwrap().insert(golden);
+  }
+  /// Whether the golden file is referenced, by itself or by its ancestor directory
+  pub(crate) fn references(&self, golden: &Path) -> bool {
+    self
+      .0
+      .lock()
+      .unwrap()
+      .iter()
+      .any(|referenced| golden.starts_with(referenced))
+  }
+}
+
+impl ReadCache {
+  /// The same as [`read_lossy`], but cached
+  async fn read(&self, path: impl AsRef<Path>) -> io::Result<Cached> {
+    let path = path.as_ref();
+    let cell = {
+      let mut cells = self.0.lock().unwrap();
+      match cells.get(path).and_then(Weak::upgrade) {
+        Some(cell) => cell,
+        None => {
+          cells.retain(|_, cell| cell.strong_count() != 0);
+          let cell = Arc::default();
+          cells.insert(path.to_path_buf(), Arc::downgrade(&cell));
+          cell
+        }
+      }
+    };
+    if let Err(e) = cell.get_or_init(|| read_lossy(path)).await {
+      return Err(io::Error::new(e.kind(), e.to_string()));
+    }
+    Ok(Cached { cell })
+  }
 }
 
 impl Golden {
-  #[expect(clippy::manual_strip)]
   #[inline]
   async fn process_assert(
     self,
     config: AssertConfig,
     workdir: PathBuf,
     golden_dir: PathBuf,
+    cache: ReadCache,
+    refs: Option<&GoldenRefs>,
   ) -> Vec<AssertError> {
-    async fn read(path: impl AsRef<Path>) -> Option<String> {
-      read_to_string(&path).await.ok()
-    }
     let mut errs = Vec::new();
-    match glob::glob(&workdir.join(&self.file).display().to_string()) {
-      Ok(paths) => {
-        let mut count = 0;
-        for entry in paths {
-          count += 1;
-          match entry {
-            Ok(path) => {
-              let path = path.display().to_string();
-              match read(&path).await {
-                Some(output) => {
-                  let workdir_str = workdir.display().to_string();
-                  let file_name = path.replace(
-                    if workdir_str.starts_with("./") {
-                      &workdir_str[2..]
-                    } else {
-                      &workdir_str
-                    },
-                    "",
-                  );
-                  let file_name =
-                    if file_name.starts_with("/") { &file_name[1..] } else { &file_name };
-                  let golden = read(golden_dir.join(file_name)).await;
-                  let golden_str = golden.as_deref();
+    // only these asserts compare the output with the golden file
+    let compared = self.equal == Some(true)
+      || self.tree == Some(true)
+      || self.yaml_equal == Some(true)
+      || self.custom.is_some();
+    if let Some(refs) = refs {
+      for value_file in self.value.iter().flatten().filter_map(|v| v.value_file.as_ref())
+      {
+        refs.insert(golden_dir.join(value_file));
+      }
+    }
+    for pattern in self.patterns() {
+      match glob::glob(&glob_pattern(&workdir, pattern)) {
+        Ok(paths) => {
+          let mut count = 0;
+          // sorted, so that the multi-file reports are stable
+          for entry in paths.sorted_by(sort_entry) {
+            count += 1;
+            match entry {
+              Ok(path) => {
+                let file_name = relative_to(&path, &workdir).display().to_string();
+                let golden = golden_dir.join(&file_name);
+                if let Some(refs) = refs.filter(|_| compared) {
+                  refs.insert(golden.clone());
+                }
+                if let Some(true) = self.tree {
+                  self
+                    .assert_tree(config, &workdir, &path, &file_name, &golden, &mut errs)
+                    .await;
+                } else {
                   self
-                    .assert(config, &workdir, file_name, golden_str, &output, &mut errs)
-                    .await
+                    .assert_file(
+                      config, &workdir, &path, &file_name, &golden, &cache, &mut errs,
+                    )
+                    .await;
                 }
-                None => errs.push(AssertError::UnableToRead(path)),
               }
+              Err(e) => errs.push(AssertError::GlobError(e)),
             }
-            Err(e) => errs.push(AssertError::GlobError(e)),
+          }
+          if count == 0 {
+            errs.push(AssertError::NoSuchFile(pattern.to_owned()))
           }
         }
-        if count == 0 {
-          errs.push(AssertError::UnableToRead(self.file))
+        Err(e) => errs.push(AssertError::PatternError(e)),
+      }
+    }
+    self.soften(errs)
+  }
+  /// Assert one output file against its golden file
+  #[expect(clippy::too_many_arguments)]
+  async fn assert_file(
+    &self,
+    config: AssertConfig,
+    workdir: &Path,
+    path: &Path,
+    file_name: &str,
+    golden: &Path,
+    cache: &ReadCache,
+    errs: &mut Vec<AssertError>,
+  ) {
+    if let Ok(metadata) = tokio::fs::metadata(path).await
+      && metadata.len() > config.stream_threshold
+    {
+      self
+        .assert_stream(config, workdir, path, file_name, metadata.len(), errs)
+        .await;
+      return;
+    }
+    match cache.read(path).await {
+      Ok(output) => {
+        let (output, output_lossy) = &*output;
+        let golden = cache.read(golden).await;
+        let golden_lossy = golden.as_ref().is_ok_and(|golden| golden.1);
+        let golden_str = golden.as_ref().map(|golden| golden.0.as_str());
+        let errs_len = errs.len();
+        self
+          .assert(config, workdir, file_name, golden_str, output, cache, errs)
+          .await;
+        if (*output_lossy || golden_lossy) && errs.len() > errs_len {
+          errs.push(AssertError::Lossy(file_name.to_owned()));
         }
       }
-      Err(e) => errs.push(AssertError::PatternError(e)),
+      Err(e) => errs.push(AssertError::UnableToRead(path.display().to_string(), e)),
     }
-    errs
   }
+  fn soften(&self, errs: Vec<AssertError>) -> Vec<AssertError> {
+    if let Some(true) = self.soft {
+      errs.into_iter().map(|e| AssertError::Soft(Box::new(e))).collect()
+    } else {
+      errs
+    }
+  }
+}
+
+/// Run the golden asserts of `spec` on a single pair of files,
+/// without the discovery and execution of the tasks.
+///
+/// The `file` and `files` of `spec` are ignored, `actual` is asserted against `golden`,
+/// and `same-as`, `value-file`, and `custom` are resolved relative to the parent of `actual`.
+///
+/// ```no_run
+/// # async fn f() -> Result<(), toml::de::Error> {
+/// use cargo_regression::{AssertConfig, Golden, compare};
+/// use std::path::Path;
+/// let spec: Golden = toml::from_str("equal = true")?;
+/// let errs = compare(
+///   Path::new("golden/out.log"),
+///   Path::new("out.log"),
+///   &spec,
+///   AssertConfig::default(),
+/// )
+/// .await;
+/// assert!(errs.is_empty());
+/// # Ok(())
+/// # }
+/// ```
+pub async fn compare(
+  golden: &Path,
+  actual: &Path,
+  spec: &Golden,
+  config: AssertConfig,
+) -> Vec<AssertError> {
+  let workdir = actual.parent().unwrap_or(Path::new(""));
+  let file_name = actual.file_name().map_or_else(
+    || actual.display().to_string(),
+    |name| name.to_string_lossy().into_owned(),
+  );
+  let mut errs = Vec::new();
+  if let Some(true) = spec.tree {
+    spec
+      .assert_tree(config, workdir, actual, &file_name, golden, &mut errs)
+      .await;
+  } else {
+    spec
+      .assert_file(
+        config,
+        workdir,
+        actual,
+        &file_name,
+        golden,
+        &ReadCache::default(),
+        &mut errs,
+      )
+      .await;
+  }
+  spec.soften(errs)
 }
 
+/// The max different files of `tree` with the diffs, the others are only listed
+const TREE_DIFFS: usize = 8;
+
+/// The differences of a `tree` golden, the relative paths in order
 #[derive(Debug)]
-pub(crate) struct TextDiffs(String, String);
-// https://github.com/mitsuhiko/similar/blob/main/examples/terminal-inline.rs
-impl fmt::Display for TextDiffs {
-  #[inline]
-  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    use similar::ChangeTag;
-    struct Line(Option<usize>);
-    impl fmt::Display for Line {
-      fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self.0 {
-          None => write!(f, "    "),
-          Some(idx) => write!(f, "{:<4}", idx + 1),
-        }
+pub struct TreeReport {
+  missing: Vec<String>,
+  unexpected: Vec<String>,
+  /// The different files, only the first `TREE_DIFFS` ones have the diffs
+  different: Vec<(String, Option<TextDiffs>)>,
+}
+
+impl TreeReport {
+  fn is_empty(&self) -> bool {
+    self.missing.is_empty() && self.unexpected.is_empty() && self.different.is_empty()
+  }
+  /// Render the report, colorize the diffs for the terminal
+  pub(crate) fn render(&self, f: &mut fmt::Formatter<'_>, color: bool) -> fmt::Result {
+    if !self.missing.is_empty() {
+      writeln!(f, "{} missing file(s):", self.missing.len())?;
+      for file in &self.missing {
+        writeln!(f, "  {file}")?;
       }
     }
-    let diff = similar::TextDiff::from_lines(&self.0, &self.1);
-    for (idx, group) in diff.grouped_ops(3).iter().enumerate() {
-      if idx > 0 {
-        writeln!(f, "{:-^1$}", "-", 80)?;
+    if !self.unexpected.is_empty() {
+      writeln!(f, "{} unexpected file(s):", self.unexpected.len())?;
+      for file in &self.unexpected {
+        writeln!(f, "  {file}")?;
       }
-      writeln!(f, "old new")?;
-      for op in group {
-        for change in diff.iter_inline_changes(op) {
-          let sign = match change.tag() {
-            ChangeTag::Delete => "-",
-            ChangeTag::Insert => "+",
-            ChangeTag::Equal => " ",
-          };
-          write!(
-            f,
-            "{}{} |{}",
-            Line(change.old_index()),
-            Line(change.new_index()),
-            sign,
-          )?;
-          for (emphasized, value) in change.iter_strings_lossy() {
-            _ = emphasized;
-            write!(f, "{}", value)?;
-          }
-          if change.missing_newline() {
-            writeln!(f)?;
-          }
+    }
+    if !self.different.is_empty() {
+      writeln!(f, "{} different file(s):", self.different.len())?;
+      for (file, diffs) in &self.different {
+        if let Some(diffs) = diffs {
+          writeln!(f, "---- {file} ---")?;
+          diffs.render(f, color)?;
+        }
+      }
+      let listed = self.different.iter().filter(|(_, diffs)| diffs.is_none());
+      for (idx, (file, _)) in listed.enumerate() {
+        if idx == 0 {
+          writeln!(f, "---- and the diffs of the others are omitted ---")?;
         }
+        writeln!(f, "  {file}")?;
       }
     }
     Ok(())
   }
 }
 
-impl AssertT for Golden {
-  async fn assert(
+impl fmt::Display for TreeReport {
+  #[inline]
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.render(f, false)
+  }
+}
+
+/// The relative paths of the files under `dir`, in order. With `workdir`,
+/// the symlinks to the outside of it are skipped, which are linked by the harness,
+/// e.g., `__golden__` and the inputs
+pub(crate) fn tree_files(
+  dir: &Path,
+  workdir: Option<&Path>,
+) -> io::Result<BTreeSet<String>> {
+  let workdir = workdir.map(std::path::absolute).transpose()?;
+  let linked = |path: &Path| {
+    workdir.as_ref().is_some_and(|workdir| {
+      std::fs::read_link(path)
+        .is_ok_and(|target| target.is_absolute() && !target.starts_with(workdir))
+    })
+  };
+  let mut files = BTreeSet::new();
+  let mut dirs = vec![dir.to_path_buf()];
+  while let Some(current) = dirs.pop() {
+    for entry in std::fs::read_dir(&current)? {
+      let entry = entry?;
+      let path = entry.path();
+      let file_type = entry.file_type()?;
+      if file_type.is_symlink() && linked(&path) {
+        continue;
+      }
+      if file_type.is_dir() {
+        dirs.push(path);
+      } else {
+        files.insert(relative_to(&path, dir).display().to_string());
+      }
+    }
+  }
+  Ok(files)
+}
+
+impl Golden {
+  /// Assert the output directory against the golden directory, file by file
+  async fn assert_tree(
     &self,
     config: AssertConfig,
     workdir: &Path,
+    path: &Path,
     file_name: &str,
-    golden: Option<&str>,
-    output: &str,
+    golden: &Path,
+    errs: &mut Vec<AssertError>,
+  ) {
+    let outputs = match tree_files(path, Some(workdir)) {
+      Ok(outputs) => outputs,
+      Err(e) => return errs.push(AssertError::UnableToReadDir(file_name.to_owned(), e)),
+    };
+    let goldens = match tree_files(golden, None) {
+      Ok(goldens) => goldens,
+      Err(e) => {
+        let golden = Path::new(GOLDEN_DIR).join(file_name).display().to_string();
+        return errs.push(AssertError::UnableToReadDir(golden, e));
+      }
+    };
+    let mut report = TreeReport {
+      missing: goldens.difference(&outputs).cloned().collect(),
+      unexpected: outputs.difference(&goldens).cloned().collect(),
+      different: Vec::new(),
+    };
+    let mut notes = Vec::new();
+    for file in outputs.intersection(&goldens) {
+      let (output, golden) = (path.join(file), golden.join(file));
+      let ((output, output_lossy), (golden, golden_lossy)) =
+        match tokio::join!(read_lossy(&output), read_lossy(&golden)) {
+          (Ok(output), Ok(golden)) => (output, golden),
+          (Err(e), _) => {
+            notes.push(AssertError::UnableToRead(output.display().to_string(), e));
+            continue;
+          }
+          (_, Err(e)) => {
+            notes.push(AssertError::UnableToRead(golden.display().to_string(), e));
+            continue;
+          }
+        };
+      let (golden, output) = (self.trim(&golden), self.trim(&output));
+      if output != golden {
+        let diffs = (report.different.len() < TREE_DIFFS)
+          .then(|| TextDiffs::new(golden, output, config));
+        report.different.push((file.clone(), diffs));
+        if output_lossy || golden_lossy {
+          notes.push(AssertError::Lossy(format!("{file_name}/{file}")));
+        }
+      }
+    }
+    if !report.is_empty() {
+      errs.push(AssertError::Tree(file_name.to_owned(), report));
+    }
+    errs.extend(notes);
+  }
+  /// Stream the huge file line by line, so that the memory use stays flat,
+  /// only `match`, `lines`, `value`, `sorted`, and `custom` are supported
+  async fn assert_stream(
+    &self,
+    config: AssertConfig,
+    workdir: &Path,
+    path: &Path,
+    file_name: &str,
+    size: u64,
+    errs: &mut Vec<AssertError>,
+  ) {
+    if self.equal == Some(true)
+      || self.same_as.is_some()
+      || self.unique == Some(true)
+      || self.json_contains.is_some()
+      || self.yaml_equal == Some(true)
+    {
+      errs.push(AssertError::TooLarge(
+        file_name.to_owned(),
+        size,
+        config.stream_threshold,
+      ));
+    }
+    let matches = self.r#match.as_deref().unwrap_or_default();
+    let values = self.value.as_deref().unwrap_or_default();
+    let patterns =
+      matches.iter().map(|m| &m.pattern).chain(values.iter().flat_map(|v| {
+        [&v.pattern_before, &v.pattern_after, &v.key_pattern, &v.value_pattern]
+          .into_iter()
+          .flatten()
+      }));
+    for pattern in patterns {
+      if is_multi_line(pattern.as_str()) {
+        errs.push(AssertError::MultiLine(file_name.to_owned(), pattern.0.clone()));
+        return;
+      }
+    }
+    let mut checkers: Vec<_> = values
+      .iter()
+      .filter_map(|v| v.checker(config, workdir, file_name, errs))
+      .map(|checker| (checker, false))
+      .collect();
+    let mut sort_checker = self.sorted.map(SortChecker::new);
+    if !matches.is_empty()
+      || self.lines.is_some()
+      || !checkers.is_empty()
+      || sort_checker.is_some()
+    {
+      let errs_len = errs.len();
+      let mut founds = vec![Found::default(); matches.len()];
+      let mut lossy = false;
+      let res = async {
+        let mut reader = BufReader::new(File::open(path).await?);
+        let mut buf = Vec::new();
+        let mut line = 0;
+        while reader.read_until(b'\n', &mut buf).await? != 0 {
+          line += 1;
+          let text = String::from_utf8_lossy(buf.strip_suffix(b"\n").unwrap_or(&buf));
+          lossy |= matches!(text, Cow::Owned(_));
+          for (m, found) in matches.iter().zip(founds.iter_mut()) {
+            m.find(&text, line, found);
+          }
+          for (checker, captured) in checkers.iter_mut() {
+            *captured |= checker.check(file_name, &text, line, errs);
+          }
+          if let Some(checker) = sort_checker.as_mut()
+            && let Some(report) = checker.check(line, &text)
+          {
+            errs.push(AssertError::Sorted(file_name.to_owned(), report));
+            // only the first out-of-order pair
+            sort_checker = None;
+          }
+          buf.clear();
+        }
+        io::Result::Ok(line)
+      }
+      .await;
+      let line_count = match res {
+        Ok(line_count) => line_count,
+        Err(e) => {
+          errs.push(AssertError::UnableToRead(path.display().to_string(), e));
+          return;
+        }
+      };
+      for (m, found) in matches.iter().zip(founds) {
+        m.check(file_name, found, errs);
+      }
+      if let Some(lines) = self.lines {
+        lines.check(file_name, line_count, errs);
+      }
+      for (checker, captured) in checkers {
+        checker.finish(file_name, captured, errs);
+      }
+      if lossy && errs.len() > errs_len {
+        errs.push(AssertError::Lossy(file_name.to_owned()));
+      }
+    }
+    if let Some(customs) = &self.custom {
+      let not_read = io::Error::other("the file is streamed");
+      for c in customs {
+        c.assert(config, workdir, file_name, Err(&not_read), "", errs).await;
+      }
+    }
+  }
+}
+
+/// The context lines around the first difference of [`TextDiffs::Brief`]
+const BRIEF_CONTEXT: usize = 3;
+/// The max width (chars) of each line of [`TextDiffs::Brief`]
+const BRIEF_WIDTH: usize = 200;
+
+#[derive(Debug)]
+pub enum TextDiffs {
+  /// Render the line diffs, at most `max_hunks` hunks
+  Full { old: String, new: String, max_hunks: usize },
+  /// Only the first difference, for the text larger than `diff-threshold`
+  Brief(Box<FirstDiff>),
+}
+
+#[derive(Debug)]
+pub struct FirstDiff {
+  old_size: usize,
+  new_size: usize,
+  /// The line number of the first difference, from 1
+  line: usize,
+  /// The byte offset of the first difference
+  offset: usize,
+  /// The common lines before the first difference
+  before: Vec<String>,
+  old: Vec<String>,
+  new: Vec<String>,
+}
+
+impl FirstDiff {
+  fn render(&self, f: &mut fmt::Formatter<'_>, color: bool) -> fmt::Result {
+    let Self { old_size, new_size, line, offset, before, old, new } = self;
+    writeln!(
+      f,
+      "old size {old_size}, new size {new_size} (bytes) exceed `diff-threshold`, only show the first difference at line {line} (byte offset {offset})"
+    )?;
+    let width = (line + BRIEF_CONTEXT).to_string().len();
+    let first = line - before.len();
+    for (idx, l) in before.iter().enumerate() {
+      writeln!(f, "{0:<width$} {0:<width$} | {l}", first + idx)?;
+    }
+    for (idx, l) in old.iter().enumerate() {
+      let l = paint(format!("-{l}"), ChangeTag::Delete, false, color);
+      writeln!(f, "{:<width$} {:<width$} |{l}", line + idx, "")?;
+    }
+    for (idx, l) in new.iter().enumerate() {
+      let l = paint(format!("+{l}"), ChangeTag::Insert, false, color);
+      writeln!(f, "{:<width$} {:<width$} |{l}", "", line + idx)?;
+    }
+    Ok(())
+  }
+}
+
+/// Red for deletions, green for insertions, and bold + underline for the
+/// emphasized spans, when the color output is enabled
+fn paint(s: String, tag: ChangeTag, emphasized: bool, color: bool) -> ColoredString {
+  let s = ColoredString::from(s);
+  if !color {
+    return s;
+  }
+  let s = match tag {
+    ChangeTag::Delete => s.red(),
+    ChangeTag::Insert => s.green(),
+    ChangeTag::Equal => return s,
+  };
+  if emphasized { s.bold().underline() } else { s }
+}
+
+impl TextDiffs {
+  pub(crate) fn new(old: &str, new: &str, config: AssertConfig) -> Self {
+    if (old.len().max(new.len()) as u64) <= config.diff_threshold {
+      return Self::Full {
+        old: old.to_owned(),
+        new: new.to_owned(),
+        max_hunks: config.diff_max_hunks,
+      };
+    }
+    let mut offset = old
+      .bytes()
+      .zip(new.bytes())
+      .position(|(o, n)| o != n)
+      .unwrap_or(old.len().min(new.len()));
+    // back to the start of the char, e.g., `é` and `è` only differ in the last byte
+    while !old.is_char_boundary(offset) {
+      offset -= 1;
+    }
+    // the line start is a char boundary of both, since the texts are the same before offset
+    let line_start = old[..offset].rfind('\n').map_or(0, |idx| idx + 1);
+    let line = old[..line_start].bytes().filter(|b| *b == b'\n').count() + 1;
+    let truncate = |l: &str| match l.char_indices().nth(BRIEF_WIDTH) {
+      Some((idx, _)) => format!("{}...", &l[..idx]),
+      None => l.to_owned(),
+    };
+    let lines =
+      |s: &str| s.lines().take(BRIEF_CONTEXT + 1).map(truncate).collect::<Vec<_>>();
+    let mut before = old[..line_start]
+      .lines()
+      .rev()
+      .take(BRIEF_CONTEXT)
+      .map(truncate)
+      .collect::<Vec<_>>();
+    before.reverse();
+    Self::Brief(Box::new(FirstDiff {
+      old_size: old.len(),
+      new_size: new.len(),
+      line,
+      offset,
+      before,
+      old: lines(&old[line_start..]),
+      new: lines(&new[line_start..]),
+    }))
+  }
+}
+
+impl fmt::Display for TextDiffs {
+  #[inline]
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.render(f, false)
+  }
+}
+
+// https://github.com/mitsuhiko/similar/blob/main/examples/terminal-inline.rs
+impl TextDiffs {
+  /// Render the diffs, colorize them for the terminal
+  pub(crate) fn render(&self, f: &mut fmt::Formatter<'_>, color: bool) -> fmt::Result {
+    struct Line(Option<usize>);
+    impl fmt::Display for Line {
+      fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+          None => write!(f, "    "),
+          Some(idx) => write!(f, "{:<4}", idx + 1),
+        }
+      }
+    }
+    let (old, new, max_hunks) = match self {
+      Self::Full { old, new, max_hunks } => (old, new, *max_hunks),
+      Self::Brief(first_diff) => return first_diff.render(f, color),
+    };
+    let diff = similar::TextDiff::from_lines(old, new);
+    let groups = diff.grouped_ops(3);
+    for (idx, group) in groups.iter().enumerate() {
+      if idx > 0 {
+        let separator = format!("{:-^1$}", "-", 80);
+        if color {
+          writeln!(f, "{}", separator.dimmed())?;
+        } else {
+          writeln!(f, "{separator}")?;
+        }
+      }
+      if idx == max_hunks {
+        writeln!(f, "... {} more hunks truncated", groups.len() - max_hunks)?;
+        break;
+      }
+      writeln!(f, "old new")?;
+      for op in group {
+        for change in diff.iter_inline_changes(op) {
+          let sign = match change.tag() {
+            ChangeTag::Delete => "-",
+            ChangeTag::Insert => "+",
+            ChangeTag::Equal => " ",
+          };
+          write!(
+            f,
+            "{}{} |{}",
+            Line(change.old_index()),
+            Line(change.new_index()),
+            paint(sign.to_owned(), change.tag(), false, color),
+          )?;
+          for (emphasized, value) in change.iter_strings_lossy() {
+            // keep the newline out of the styles
+            let (value, newline) = match value.strip_suffix('\n') {
+              Some(value) => (value, "\n"),
+              None => (&*value, ""),
+            };
+            if !value.is_empty() {
+              write!(f, "{}", paint(value.to_owned(), change.tag(), emphasized, color))?;
+            }
+            write!(f, "{newline}")?;
+          }
+          if change.missing_newline() {
+            writeln!(f)?;
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Golden {
+  #[expect(clippy::too_many_arguments)]
+  async fn assert(
+    &self,
+    config: AssertConfig,
+    workdir: &Path,
+    file_name: &str,
+    golden: Result<&str, &io::Error>,
+    output: &str,
+    cache: &ReadCache,
     errs: &mut Vec<AssertError>,
   ) {
     if let Some(true) = self.equal {
-      if let Some(golden) = golden {
-        if output != golden {
-          errs.push(AssertError::Eq {
-            file_name: file_name.to_owned(),
-            diffs: TextDiffs(golden.to_owned(), output.to_owned()),
-          });
+      match golden {
+        Ok(golden) => {
+          let (golden, output) = (self.trim(golden), self.trim(output));
+          if output != golden {
+            errs.push(AssertError::Eq {
+              file_name: file_name.to_owned(),
+              diffs: TextDiffs::new(golden, output, config),
+            });
+          }
         }
-      } else {
-        errs.push(AssertError::UnableToRead(file_name.into()))
+        Err(e) => errs.push(AssertError::UnableToRead(
+          Path::new(GOLDEN_DIR).join(file_name).display().to_string(),
+          io::Error::new(e.kind(), e.to_string()),
+        )),
+      }
+    }
+    if let Some(other) = &self.same_as {
+      match cache.read(workdir.join(other)).await {
+        Ok(other_output) => {
+          let (other_output, output) = (self.trim(&other_output.0), self.trim(output));
+          if output != other_output {
+            errs.push(AssertError::SameAs {
+              file_name: file_name.to_owned(),
+              other: other.clone(),
+              diffs: TextDiffs::new(other_output, output, config),
+            });
+          }
+        }
+        Err(e) => errs.push(AssertError::UnableToRead(other.clone(), e)),
       }
     }
     if let Some(vec) = &self.r#match {
@@ -343,11 +1607,70 @@ impl AssertT for Golden {
         m.assert(config, workdir, file_name, golden, output, errs).await;
       }
     }
+    if let Some(lines) = &self.lines {
+      lines.assert(config, workdir, file_name, golden, output, errs).await;
+    }
     if let Some(vec) = &self.value {
       for v in vec {
         v.assert(config, workdir, file_name, golden, output, errs).await;
       }
     }
+    if let Some(true) = self.unique {
+      Unique.assert(config, workdir, file_name, golden, output, errs).await;
+    }
+    if let Some(want) = &self.json_contains {
+      let report = match serde_json::from_str(output) {
+        Ok(got) => {
+          let mut diffs = StructDiffs::new(true);
+          diffs.visit(
+            &mut String::from("$"),
+            &serde_json::Value::Object(want.clone()),
+            &got,
+          );
+          diffs.report()
+        }
+        Err(e) => Some(StructReport::Invalid(format!("invalid JSON: {e}"))),
+      };
+      if let Some(report) = report {
+        errs.push(AssertError::JsonContains(file_name.to_owned(), report));
+      }
+    }
+    if let Some(true) = self.yaml_equal {
+      match golden {
+        Ok(golden) => {
+          let parse = |s: &str, which: &str| {
+            serde_yaml::from_str::<serde_json::Value>(s).map_err(|e| {
+              StructReport::Invalid(format!("invalid YAML of the {which}: {e}"))
+            })
+          };
+          let report = match (parse(golden, "golden"), parse(output, "output")) {
+            (Ok(want), Ok(got)) => {
+              let mut diffs = StructDiffs::new(false);
+              diffs.visit(&mut String::from("$"), &want, &got);
+              diffs.report()
+            }
+            (Err(report), _) | (_, Err(report)) => Some(report),
+          };
+          if let Some(report) = report {
+            errs.push(AssertError::YamlEqual(file_name.to_owned(), report));
+          }
+        }
+        Err(e) => errs.push(AssertError::UnableToRead(
+          Path::new(GOLDEN_DIR).join(file_name).display().to_string(),
+          io::Error::new(e.kind(), e.to_string()),
+        )),
+      }
+    }
+    if let Some(spec) = self.sorted {
+      let mut checker = SortChecker::new(spec);
+      if let Some(report) = output
+        .lines()
+        .enumerate()
+        .find_map(|(idx, l)| checker.check(idx + 1, l))
+      {
+        errs.push(AssertError::Sorted(file_name.to_owned(), report));
+      }
+    }
     if let Some(vec) = &self.custom {
       for c in vec {
         c.assert(config, workdir, file_name, golden, output, errs).await;
@@ -361,13 +1684,17 @@ pub struct CustomReport {
   workdir: PathBuf,
   custom: Custom,
   epsilon: f32,
+  legacy_env: bool,
   paths: [PathBuf; 2],
   output: Output,
 }
 impl fmt::Display for CustomReport {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     let mut envs = IndexMap::new();
-    envs.insert("epsilon", self.epsilon.to_string());
+    envs.insert("REGRESSION_EPSILON", self.epsilon.to_string());
+    if self.legacy_env {
+      envs.insert("epsilon", self.epsilon.to_string());
+    }
     if let Some(_envs) = self.custom.envs.as_ref() {
       envs.extend(_envs.iter().map(|(k, v)| (k.as_str(), v.clone())));
     }
@@ -378,7 +1705,9 @@ impl fmt::Display for CustomReport {
         cmd: &self.custom.cmd,
         args: &[self.paths[0].display().to_string(), self.paths[1].display().to_string()],
         workdir: &self.workdir,
-        envs: Some(&envs)
+        envs: Some(&envs),
+        cleared: None,
+        unset: &[],
       },
       self.output.status,
       core::str::from_utf8(&self.output.stdout).unwrap_or("Fail to convert to UTF-8"),
@@ -393,17 +1722,28 @@ impl AssertT for Custom {
     config: AssertConfig,
     workdir: &Path,
     file_name: &str,
-    _: Option<&str>,
+    _: Result<&str, &io::Error>,
     _: &str,
     errs: &mut Vec<AssertError>,
   ) {
-    let paths = [PathBuf::from(file_name), Path::new("__golden__").join(file_name)];
+    let paths = [PathBuf::from(file_name), Path::new(GOLDEN_DIR).join(file_name)];
+    // the paths are absolutized to remain valid from the other directory
+    let (cwd, paths) = match &self.workdir {
+      None => (workdir.to_path_buf(), paths),
+      Some(cwd) => {
+        let workdir_abs = std::path::absolute(workdir).unwrap_or(workdir.to_path_buf());
+        (workdir.join(cwd), paths.map(|path| workdir_abs.join(path)))
+      }
+    };
     let mut command = Command::new(&self.cmd);
-    command.env("epsilon", config.epsilon.to_string());
+    command.env("REGRESSION_EPSILON", config.epsilon.to_string());
+    if config.legacy_env {
+      command.env("epsilon", config.epsilon.to_string());
+    }
     if let Some(envs) = self.envs.as_ref() {
       command.envs(envs);
     }
-    match command.current_dir(workdir).args(&paths).output().await {
+    match command.current_dir(&cwd).args(&paths).output().await {
       Ok(output) => {
         if !output.status.success() {
           errs.push(AssertError::Custom(
@@ -411,9 +1751,10 @@ impl AssertT for Custom {
             Box::new(CustomReport {
               custom: self.clone(),
               epsilon: config.epsilon,
+              legacy_env: config.legacy_env,
               paths,
               output,
-              workdir: workdir.to_path_buf(),
+              workdir: cwd,
             }),
           ))
         }
@@ -431,6 +1772,7 @@ impl AssertT for Custom {
 #[derive(Debug)]
 pub enum ValueReport {
   Config,
+  TableConfig,
   NegativeEpsilon(f32),
   AssertFail {
     line: usize,
@@ -449,6 +1791,82 @@ pub enum ValueReport {
     pattern: regex::Regex,
     matched: String,
   },
+  Monotonic(Box<MonotonicReport>),
+  ValueFile {
+    path: String,
+    reason: String,
+  },
+  Table(Box<TableReport>),
+}
+
+/// The max mismatched rows listed in [`TableReport`]
+const TABLE_LISTED: usize = 10;
+
+/// The failures of the table form, the keys are in the order of `expect`
+/// or their first occurrences
+#[derive(Debug)]
+pub struct TableReport {
+  key_pattern: regex::Regex,
+  missing: Vec<String>,
+  /// The unexpected keys and their first lines
+  unexpected: Vec<(String, usize)>,
+  /// The first `TABLE_LISTED` mismatched rows
+  mismatched: Vec<TableMismatch>,
+  /// The number of all mismatched rows
+  count: usize,
+}
+
+/// A row whose value is not expected, `want` is `None` if it has no value
+#[derive(Debug)]
+struct TableMismatch {
+  key: String,
+  line: usize,
+  want: (f32, f32),
+  got: Option<String>,
+}
+
+impl fmt::Display for TableReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "table of key pattern '{}'", self.key_pattern)?;
+    for key in &self.missing {
+      write!(f, "\n  missing key '{key}'")?;
+    }
+    for (key, line) in &self.unexpected {
+      write!(f, "\n  unexpected key '{key}' at line {line}")?;
+    }
+    for TableMismatch { key, line, want: (value, epsilon), got } in &self.mismatched {
+      write!(f, "\n  key '{key}' at line {line}, want {value}±{epsilon}, got: ")?;
+      match got {
+        Some(got) => write!(f, "{got}")?,
+        None => write!(f, "no value")?,
+      }
+    }
+    if self.count > self.mismatched.len() {
+      write!(f, "\n  ... {} more mismatched row(s)", self.count - self.mismatched.len())?;
+    }
+    Ok(())
+  }
+}
+
+/// The first violating pair of `monotonic`
+#[derive(Debug)]
+pub struct MonotonicReport {
+  pattern: regex::Regex,
+  direction: Direction,
+  /// `(line, matched, value)`
+  prev: (usize, String, f32),
+  got: (usize, String, f32),
+}
+
+impl fmt::Display for MonotonicReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let Self { pattern, direction, prev, got } = self;
+    write!(
+      f,
+      "pattern '{pattern}' want {direction}, caputred '{}' = {} at line {}, then '{}' = {} at line {}",
+      prev.1, prev.2, prev.0, got.1, got.2, got.0
+    )
+  }
 }
 
 impl fmt::Display for ValueReport {
@@ -456,7 +1874,11 @@ impl fmt::Display for ValueReport {
     match self {
       ValueReport::Config => write!(
         f,
-        "You should specify one and only one of `value`, `value-at-least`, `value-at-most`"
+        "You should specify one and only one of `value`, `value-file`, `value-at-least`, `value-at-most`, or only `monotonic`"
+      ),
+      ValueReport::TableConfig => write!(
+        f,
+        "The table form needs `key-pattern`, `value-pattern`, and `expect`, without `pattern-before`, `pattern-after`, `value`, `value-file`, `value-at-least`, `value-at-most`, or `monotonic`"
       ),
       ValueReport::AssertFail {
         line,
@@ -484,27 +1906,69 @@ impl fmt::Display for ValueReport {
       ValueReport::NegativeEpsilon(epsilon) => {
         write!(f, "the epsilon = {epsilon} is negative")
       }
+      ValueReport::Monotonic(report) => write!(f, "{report}"),
+      ValueReport::ValueFile { path, reason } => {
+        write!(f, "unable to get the value from '{path}': {reason}")
+      }
+      ValueReport::Table(report) => write!(f, "{report}"),
     }
   }
 }
 
-impl AssertT for Value {
-  async fn assert(
+/// The compiled `Value`, which can check the text line by line
+enum ValueChecker {
+  Scalar(ScalarChecker),
+  Table(TableChecker),
+}
+
+/// The compiled `Value` with `pattern-before` and/or `pattern-after`
+struct ScalarChecker {
+  re: regex::Regex,
+  /// The want value and its condition, `None` for only `monotonic`
+  want: Option<(f32, Option<MatchCond>)>,
+  epsilon: f32,
+  monotonic: Option<Direction>,
+  /// The last captured `(line, matched, value)`, `None` after a violation,
+  /// so that only the first violating pair is reported
+  last: Option<(usize, String, f32)>,
+}
+
+impl Value {
+  fn checker(
     &self,
     config: AssertConfig,
-    _: &Path,
+    workdir: &Path,
     file_name: &str,
-    _: Option<&str>,
-    output: &str,
     errs: &mut Vec<AssertError>,
-  ) {
-    let (want_value, cond) = match (self.value, self.value_at_least, self.value_at_most) {
-      (None, None, Some(value)) => (value, Some(MatchCond::AtMost)),
-      (None, Some(value), None) => (value, Some(MatchCond::AtLeast)),
-      (Some(value), None, None) => (value, None),
+  ) -> Option<ValueChecker> {
+    if self.key_pattern.is_some() || self.value_pattern.is_some() || self.expect.is_some()
+    {
+      return self.table_checker(config, file_name, errs).map(ValueChecker::Table);
+    }
+    let value = match (self.value, &self.value_file) {
+      (value, None) => value,
+      (None, Some(value_file)) => {
+        match read_value_file(&Path::new(GOLDEN_DIR).join(value_file), workdir) {
+          Ok(value) => Some(value),
+          Err(report) => {
+            errs.push(AssertError::Value(file_name.into(), report));
+            return None;
+          }
+        }
+      }
+      (Some(_), Some(_)) => {
+        errs.push(AssertError::Value(file_name.into(), ValueReport::Config));
+        return None;
+      }
+    };
+    let want = match (value, self.value_at_least, self.value_at_most) {
+      (None, None, Some(value)) => Some((value, Some(MatchCond::AtMost))),
+      (None, Some(value), None) => Some((value, Some(MatchCond::AtLeast))),
+      (Some(value), None, None) => Some((value, None)),
+      (None, None, None) if self.monotonic.is_some() => None,
       _ => {
         errs.push(AssertError::Value(file_name.into(), ValueReport::Config));
-        return;
+        return None;
       }
     };
     let re = match match (&self.pattern_before, &self.pattern_after) {
@@ -528,7 +1992,7 @@ impl AssertT for Value {
       Ok(re) => re,
       Err(e) => {
         errs.push(AssertError::Regex(e));
-        return;
+        return None;
       }
     };
     let epsilon = self.epsilon.unwrap_or(config.epsilon);
@@ -537,25 +2001,217 @@ impl AssertT for Value {
         file_name.into(),
         ValueReport::NegativeEpsilon(epsilon),
       ));
-      return;
+      return None;
+    }
+    Some(ValueChecker::Scalar(ScalarChecker {
+      re,
+      want,
+      epsilon,
+      monotonic: self.monotonic,
+      last: None,
+    }))
+  }
+  fn table_checker(
+    &self,
+    config: AssertConfig,
+    file_name: &str,
+    errs: &mut Vec<AssertError>,
+  ) -> Option<TableChecker> {
+    let report = |report| AssertError::Value(file_name.into(), report);
+    let (Some(key_re), Some(value_re), Some(expect)) =
+      (&self.key_pattern, &self.value_pattern, &self.expect)
+    else {
+      errs.push(report(ValueReport::TableConfig));
+      return None;
+    };
+    if self.pattern_before.is_some()
+      || self.pattern_after.is_some()
+      || self.value.is_some()
+      || self.value_file.is_some()
+      || self.value_at_least.is_some()
+      || self.value_at_most.is_some()
+      || self.monotonic.is_some()
+    {
+      errs.push(report(ValueReport::TableConfig));
+      return None;
+    }
+    let default_epsilon = self.epsilon.unwrap_or(config.epsilon);
+    let mut wants = IndexMap::with_capacity(expect.len());
+    for (key, expect) in expect {
+      let want = match *expect {
+        Expect::Value(value) => (value, default_epsilon),
+        Expect::WithEpsilon { value, epsilon } => {
+          (value, epsilon.unwrap_or(default_epsilon))
+        }
+      };
+      if want.1.is_sign_negative() {
+        errs.push(report(ValueReport::NegativeEpsilon(want.1)));
+        return None;
+      }
+      wants.insert(key.clone(), (want, false));
+    }
+    Some(TableChecker {
+      key_re: key_re.0.clone(),
+      value_re: value_re.0.clone(),
+      wants,
+      allow_extra: self.allow_extra.unwrap_or(false),
+      unexpected: IndexMap::new(),
+      mismatched: Vec::new(),
+      count: 0,
+    })
+  }
+}
+
+/// The compiled table form of `Value`, which pairs the key and the value on each line
+struct TableChecker {
+  key_re: regex::Regex,
+  value_re: regex::Regex,
+  /// The want value and epsilon of each key, and whether it is found
+  wants: IndexMap<String, ((f32, f32), bool)>,
+  allow_extra: bool,
+  /// The unexpected keys and their first lines
+  unexpected: IndexMap<String, usize>,
+  mismatched: Vec<TableMismatch>,
+  count: usize,
+}
+
+/// The first capture group, or the whole match if there is no group
+fn first_capture<'t>(re: &regex::Regex, text: &'t str) -> Option<&'t str> {
+  let cap = re.captures(text)?;
+  cap.get(1).or_else(|| cap.get(0)).map(|mat| mat.as_str())
+}
+
+impl TableChecker {
+  /// Check the rows in the text, which starts at `first_line`,
+  /// return whether any key is captured
+  fn check(&mut self, text: &str, first_line: usize) -> bool {
+    let mut captured = false;
+    for (idx, row) in text.split('\n').enumerate() {
+      let Some(key) = first_capture(&self.key_re, row) else {
+        continue;
+      };
+      captured = true;
+      let line = first_line + idx;
+      let Some((want, found)) = self.wants.get_mut(key) else {
+        if !self.allow_extra && !self.unexpected.contains_key(key) {
+          self.unexpected.insert(key.to_owned(), line);
+        }
+        continue;
+      };
+      *found = true;
+      let want = *want;
+      let got = first_capture(&self.value_re, row);
+      let ok = got
+        .and_then(|got| got.parse::<f32>().ok())
+        .is_some_and(|got| got <= want.0 + want.1 && got >= want.0 - want.1);
+      if !ok {
+        self.count += 1;
+        if self.mismatched.len() < TABLE_LISTED {
+          let got = got.map(str::to_owned);
+          self
+            .mismatched
+            .push(TableMismatch { key: key.to_owned(), line, want, got });
+        }
+      }
+    }
+    captured
+  }
+  fn finish(self, file_name: &str, errs: &mut Vec<AssertError>) {
+    let missing: Vec<_> = self
+      .wants
+      .into_iter()
+      .filter(|(_, (_, found))| !found)
+      .map(|(key, _)| key)
+      .collect();
+    if !missing.is_empty() || !self.unexpected.is_empty() || self.count != 0 {
+      let report = TableReport {
+        key_pattern: self.key_re,
+        missing,
+        unexpected: self.unexpected.into_iter().collect(),
+        mismatched: self.mismatched,
+        count: self.count,
+      };
+      errs.push(AssertError::Value(
+        file_name.to_owned(),
+        ValueReport::Table(Box::new(report)),
+      ));
+    }
+  }
+}
+
+/// Read the single float of the `value-file`, which is relative to `workdir`
+fn read_value_file(path: &Path, workdir: &Path) -> Result<f32, ValueReport> {
+  let report =
+    |reason: String| ValueReport::ValueFile { path: path.display().to_string(), reason };
+  let text =
+    std::fs::read_to_string(workdir.join(path)).map_err(|e| report(e.to_string()))?;
+  text
+    .trim()
+    .parse()
+    .map_err(|e| report(format!("{e}, got '{}'", text.trim())))
+}
+
+impl ValueChecker {
+  /// Check the text, which starts at `first_line`, return whether any value is captured
+  fn check(
+    &mut self,
+    file_name: &str,
+    text: &str,
+    first_line: usize,
+    errs: &mut Vec<AssertError>,
+  ) -> bool {
+    match self {
+      Self::Scalar(checker) => checker.check(file_name, text, first_line, errs),
+      Self::Table(checker) => checker.check(text, first_line),
+    }
+  }
+  /// Report the errors after all text is checked, `captured` is whether
+  /// any value is captured
+  fn finish(self, file_name: &str, captured: bool, errs: &mut Vec<AssertError>) {
+    match self {
+      Self::Scalar(checker) if !captured => errs.push(AssertError::Value(
+        file_name.to_owned(),
+        ValueReport::NoMatch { pattern: checker.re },
+      )),
+      Self::Scalar(_) => {}
+      Self::Table(checker) => checker.finish(file_name, errs),
     }
+  }
+}
+
+impl ScalarChecker {
+  /// Check all captured values in the text, which starts at `first_line`,
+  /// return whether any value is captured
+  fn check(
+    &mut self,
+    file_name: &str,
+    text: &str,
+    first_line: usize,
+    errs: &mut Vec<AssertError>,
+  ) -> bool {
+    let Self { re, want, epsilon, monotonic, last } = self;
+    let epsilon = *epsilon;
     let mut last_bgn = 0;
-    let mut line = 1;
+    let mut line = first_line;
     let mut captured = false;
-    for cap in re.captures_iter(output) {
+    for cap in re.captures_iter(text) {
       captured = true;
       let overall_mat = cap.get(0).unwrap();
       let capture_mat = cap.get(1).unwrap();
       let bgn = overall_mat.start();
-      line += output[last_bgn..bgn].matches('\n').count();
+      line += text[last_bgn..bgn].matches('\n').count();
       last_bgn = bgn;
       match capture_mat.as_str().parse::<f32>() {
         Ok(got_value) => {
-          if match cond {
-            Some(MatchCond::AtLeast) => got_value + epsilon < want_value,
-            Some(MatchCond::AtMost) => got_value > want_value + epsilon,
-            None => got_value > want_value + epsilon || got_value < want_value - epsilon,
-          } {
+          if let Some((want_value, cond)) = *want
+            && match cond {
+              Some(MatchCond::AtLeast) => got_value + epsilon < want_value,
+              Some(MatchCond::AtMost) => got_value > want_value + epsilon,
+              None => {
+                got_value > want_value + epsilon || got_value < want_value - epsilon
+              }
+            }
+          {
             errs.push(AssertError::Value(
               file_name.to_owned(),
               ValueReport::AssertFail {
@@ -569,6 +2225,30 @@ impl AssertT for Value {
               },
             ));
           }
+          if let Some(direction) = *monotonic {
+            let got = (line, overall_mat.as_str().to_owned(), got_value);
+            match last.take() {
+              Some(prev)
+                if match direction {
+                  Direction::NonDecreasing => got_value + epsilon < prev.2,
+                  Direction::NonIncreasing => got_value > prev.2 + epsilon,
+                } =>
+              {
+                errs.push(AssertError::Value(
+                  file_name.to_owned(),
+                  ValueReport::Monotonic(Box::new(MonotonicReport {
+                    pattern: re.clone(),
+                    direction,
+                    prev,
+                    got,
+                  })),
+                ));
+                // stop checking after the first violation
+                *monotonic = None;
+              }
+              _ => *last = Some(got),
+            }
+          }
         }
         Err(_) => {
           errs.push(AssertError::Value(
@@ -582,27 +2262,293 @@ impl AssertT for Value {
         }
       }
     }
-    if !captured {
-      errs.push(AssertError::Value(
+    captured
+  }
+}
+
+impl AssertT for Value {
+  async fn assert(
+    &self,
+    config: AssertConfig,
+    workdir: &Path,
+    file_name: &str,
+    _: Result<&str, &io::Error>,
+    output: &str,
+    errs: &mut Vec<AssertError>,
+  ) {
+    if let Some(mut checker) = self.checker(config, workdir, file_name, errs) {
+      let captured = checker.check(file_name, output, 1, errs);
+      checker.finish(file_name, captured, errs);
+    }
+  }
+}
+
+/// The max duplicated lines listed in [`UniqueReport`]
+const UNIQUE_LISTED: usize = 10;
+
+/// The assert of `unique = true`
+struct Unique;
+
+/// The duplicated lines of `unique`, in the order of their first occurrences
+#[derive(Debug)]
+pub struct UniqueReport {
+  /// The line and its line numbers, at most `UNIQUE_LISTED` ones
+  duplicates: Vec<(String, Vec<usize>)>,
+  /// The number of all duplicated lines
+  count: usize,
+}
+
+impl fmt::Display for UniqueReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(f, "{} duplicated line(s):", self.count)?;
+    for (line, numbers) in &self.duplicates {
+      writeln!(f, "  {line:?} at lines {}", numbers.iter().join(", "))?;
+    }
+    if self.count > self.duplicates.len() {
+      writeln!(
+        f,
+        "  ... {} more duplicated line(s)",
+        self.count - self.duplicates.len()
+      )?;
+    }
+    Ok(())
+  }
+}
+
+impl AssertT for Unique {
+  async fn assert(
+    &self,
+    _: AssertConfig,
+    _: &Path,
+    file_name: &str,
+    _: Result<&str, &io::Error>,
+    output: &str,
+    errs: &mut Vec<AssertError>,
+  ) {
+    let mut lines = IndexMap::<&str, Vec<usize>>::new();
+    for (idx, line) in output.lines().enumerate() {
+      lines.entry(line).or_default().push(idx + 1);
+    }
+    let mut duplicates = lines.into_iter().filter(|(_, numbers)| numbers.len() > 1);
+    let listed: Vec<_> = duplicates
+      .by_ref()
+      .take(UNIQUE_LISTED)
+      .map(|(line, numbers)| (line.to_owned(), numbers))
+      .collect();
+    if !listed.is_empty() {
+      let count = listed.len() + duplicates.count();
+      errs.push(AssertError::Unique(
         file_name.to_owned(),
-        ValueReport::NoMatch { pattern: re },
+        UniqueReport { duplicates: listed, count },
       ));
     }
   }
 }
 
+/// The first out-of-order pair of `sorted`
+#[derive(Debug)]
+pub struct SortedReport {
+  spec: SortSpec,
+  /// `(line, text)`
+  prev: (usize, String),
+  got: (usize, String),
+}
+
+impl fmt::Display for SortedReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let Self { spec, prev, got } = self;
+    writeln!(f, "want sorted by {spec}, line {} is before line {}:", prev.0, got.0)?;
+    writeln!(f, "  {:?}\n  {:?}", prev.1, got.1)
+  }
+}
+
+/// The max differences listed in [`StructReport`]
+const STRUCT_LISTED: usize = 10;
+
+/// A difference of the structured outputs at its JSONPath, e.g., `$.data.items[0].id`
+#[derive(Debug)]
+pub enum StructDiff {
+  Missing(String),
+  Unexpected(String),
+  Mismatch { path: String, want: serde_json::Value, got: serde_json::Value },
+}
+
+/// The differences of `json-contains` and `yaml-equal`
+#[derive(Debug)]
+pub enum StructReport {
+  /// The output or the golden can not be parsed
+  Invalid(String),
+  /// At most `STRUCT_LISTED` differences, and the number of all differences
+  Diffs(Vec<StructDiff>, usize),
+}
+
+impl fmt::Display for StructReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let (diffs, count) = match self {
+      Self::Invalid(e) => return writeln!(f, "{e}"),
+      Self::Diffs(diffs, count) => (diffs, *count),
+    };
+    for diff in diffs {
+      match diff {
+        StructDiff::Missing(path) => writeln!(f, "missing {path}")?,
+        StructDiff::Unexpected(path) => writeln!(f, "unexpected {path}")?,
+        StructDiff::Mismatch { path, want, got } => {
+          writeln!(f, "mismatched {path}, want: {want}, got: {got}")?
+        }
+      }
+    }
+    if count > diffs.len() {
+      writeln!(f, "... {} more difference(s)", count - diffs.len())?;
+    }
+    Ok(())
+  }
+}
+
+/// Compare the structured values deeply, ignoring the key order, and the number
+/// types, e.g., `1` equals to `1.0`. With `subset`, the extra keys are ignored,
+/// and the arrays should have the same length
+struct StructDiffs {
+  subset: bool,
+  diffs: Vec<StructDiff>,
+  count: usize,
+}
+
+impl StructDiffs {
+  const fn new(subset: bool) -> Self {
+    Self { subset, diffs: Vec::new(), count: 0 }
+  }
+  fn push(&mut self, diff: StructDiff) {
+    self.count += 1;
+    if self.diffs.len() < STRUCT_LISTED {
+      self.diffs.push(diff);
+    }
+  }
+  /// `path` is the JSONPath of `got`
+  fn visit(
+    &mut self,
+    path: &mut String,
+    want: &serde_json::Value,
+    got: &serde_json::Value,
+  ) {
+    use serde_json::Value;
+    let len = path.len();
+    match (want, got) {
+      (Value::Object(want_map), Value::Object(got_map)) => {
+        for (key, want) in want_map {
+          path.push_str(&format!(".{key}"));
+          match got_map.get(key) {
+            Some(got) => self.visit(path, want, got),
+            None => self.push(StructDiff::Missing(path.clone())),
+          }
+          path.truncate(len);
+        }
+        if !self.subset {
+          for key in got_map.keys().filter(|key| !want_map.contains_key(*key)) {
+            self.push(StructDiff::Unexpected(format!("{path}.{key}")));
+          }
+        }
+      }
+      (Value::Array(want_items), Value::Array(got_items))
+        if !self.subset || want_items.len() == got_items.len() =>
+      {
+        for (idx, (want, got)) in want_items.iter().zip(got_items).enumerate() {
+          path.push_str(&format!("[{idx}]"));
+          self.visit(path, want, got);
+          path.truncate(len);
+        }
+        for idx in got_items.len()..want_items.len() {
+          self.push(StructDiff::Missing(format!("{path}[{idx}]")));
+        }
+        for idx in want_items.len()..got_items.len() {
+          self.push(StructDiff::Unexpected(format!("{path}[{idx}]")));
+        }
+      }
+      (Value::Number(want_number), Value::Number(got_number))
+        if want_number.as_f64() == got_number.as_f64() => {}
+      _ if want == got => {}
+      _ => self.push(StructDiff::Mismatch {
+        path: path.clone(),
+        want: want.clone(),
+        got: got.clone(),
+      }),
+    }
+  }
+  fn report(self) -> Option<StructReport> {
+    (self.count > 0).then_some(StructReport::Diffs(self.diffs, self.count))
+  }
+}
+
+/// Check the lines of `sorted` one by one
+struct SortChecker {
+  spec: SortSpec,
+  /// The last `(line, text)`
+  last: Option<(usize, String)>,
+}
+
+impl SortChecker {
+  fn new(spec: SortSpec) -> Self {
+    Self { spec, last: None }
+  }
+  /// Check the line against the last one, return the out-of-order pair
+  fn check(&mut self, line: usize, text: &str) -> Option<SortedReport> {
+    let ordered = self.last.as_ref().is_none_or(|(_, last)| match self.spec {
+      SortSpec::Lines => last.as_str() <= text,
+      SortSpec::Numeric => leading_number(last) <= leading_number(text),
+      SortSpec::CaseInsensitive => last.to_lowercase() <= text.to_lowercase(),
+    });
+    let got = (line, text.to_owned());
+    if ordered {
+      self.last = Some(got);
+      None
+    } else {
+      let prev = self.last.take().expect("checked");
+      Some(SortedReport { spec: self.spec, prev, got })
+    }
+  }
+}
+
+/// The leading number of the line, 0 when there is none, as `sort -n`
+fn leading_number(text: &str) -> f64 {
+  let text = text.trim_start();
+  let mut dot = false;
+  let end = text
+    .char_indices()
+    .take_while(|&(idx, c)| match c {
+      '0'..='9' => true,
+      '-' => idx == 0,
+      '.' => !core::mem::replace(&mut dot, true),
+      _ => false,
+    })
+    .last()
+    .map_or(0, |(idx, c)| idx + c.len_utf8());
+  text[..end].parse().unwrap_or(0.0)
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum MatchCond {
   AtMost,
   AtLeast,
 }
 
+/// The max matches listed in [`MatchReport`]
+const MATCH_LISTED: usize = 10;
+
+/// The matches of a pattern, only count them beyond the first `MATCH_LISTED` ones,
+/// so that the memory stays flat for the streamed file
+#[derive(Debug, Clone, Default)]
+struct Found {
+  /// The line and the matched text, at most `MATCH_LISTED` ones
+  listed: Vec<(usize, String)>,
+  /// The number of all matches
+  count: usize,
+}
+
 #[derive(Debug)]
 pub struct MatchReport {
   pattern: regex::Regex,
   count: usize,
   cond: Option<MatchCond>,
-  matches: Vec<(usize, String)>,
+  found: Found,
 }
 
 fn cond_str(cond: Option<MatchCond>) -> &'static str {
@@ -620,76 +2566,1131 @@ impl fmt::Display for MatchReport {
       self.pattern,
       cond_str(self.cond),
       self.count,
-      self.matches.len()
+      self.found.count
     )?;
-    for (idx, (line, res)) in self.matches.iter().enumerate() {
+    for (idx, (line, res)) in self.found.listed.iter().enumerate() {
       writeln!(f, "  #{} at line {line}: {res:?}", idx + 1)?;
     }
+    if self.found.count > self.found.listed.len() {
+      writeln!(f, "  ... {} more match(es)", self.found.count - self.found.listed.len())?;
+    }
     Ok(())
   }
 }
 
-impl AssertT for Match {
-  async fn assert(
-    &self,
-    _: AssertConfig,
-    _: &Path,
-    file_name: &str,
-    _: Option<&str>,
-    output: &str,
-    errs: &mut Vec<AssertError>,
-  ) {
+impl Match {
+  /// Find all matches in the text, which starts at `first_line`
+  fn find(&self, text: &str, first_line: usize, found: &mut Found) {
     let mut last_bgn = 0;
-    let mut last_line = 1;
-    let matches: Vec<(usize, String)> = self
-      .pattern
-      .find_iter(output)
-      .map(|mat| {
+    let mut last_line = first_line;
+    for mat in self.pattern.find_iter(text) {
+      found.count += 1;
+      if found.listed.len() < MATCH_LISTED {
         let bgn = mat.start();
-        last_line += output[last_bgn..bgn].matches('\n').count();
+        last_line += text[last_bgn..bgn].matches('\n').count();
         last_bgn = bgn;
-        (last_line, mat.as_str().to_owned())
-      })
-      .collect();
-    let (count, cond) = match (self.count, self.count_at_most, self.count_at_least) {
-      (Some(count), None, None) => {
-        if count != matches.len() {
-          (count, None)
-        } else {
-          return;
-        }
-      }
-      (None, Some(count), None) => {
-        if count < matches.len() {
-          (count, Some(MatchCond::AtMost))
-        } else {
-          return;
-        }
+        found.listed.push((last_line, mat.as_str().to_owned()));
       }
-      (None, None, Some(count)) => {
-        if count > matches.len() {
-          (count, Some(MatchCond::AtLeast))
-        } else {
+    }
+  }
+  fn check(&self, file_name: &str, found: Found, errs: &mut Vec<AssertError>) {
+    let (count, cond) =
+      match violation(self.count, self.count_at_most, self.count_at_least, found.count) {
+        Ok(Some(violation)) => violation,
+        Ok(None) => return,
+        Err(e) => {
+          errs.push(e);
           return;
         }
-      }
-      _ => {
-        errs.push(AssertError::CountConfig);
-        return;
-      }
-    };
+      };
     errs.push(AssertError::Match(
       file_name.to_owned(),
       MatchReport {
         pattern: self.pattern.0.clone(),
         count,
         cond,
-        matches,
+        found,
       },
     ));
   }
 }
 
+/// The wanted count and its condition that `got` violates, by one and only one of
+/// `count`, `count-at-most`, and `count-at-least`
+fn violation(
+  count: Option<usize>,
+  count_at_most: Option<usize>,
+  count_at_least: Option<usize>,
+  got: usize,
+) -> Result<Option<(usize, Option<MatchCond>)>, AssertError> {
+  match (count, count_at_most, count_at_least) {
+    (Some(count), None, None) => Ok((count != got).then_some((count, None))),
+    (None, Some(count), None) => {
+      Ok((count < got).then_some((count, Some(MatchCond::AtMost))))
+    }
+    (None, None, Some(count)) => {
+      Ok((count > got).then_some((count, Some(MatchCond::AtLeast))))
+    }
+    _ => Err(AssertError::CountConfig),
+  }
+}
+
+impl Lines {
+  fn check(self, file_name: &str, got: usize, errs: &mut Vec<AssertError>) {
+    match violation(self.count, self.count_at_most, self.count_at_least, got) {
+      Ok(Some((count, cond))) => errs.push(AssertError::Lines {
+        file_name: file_name.to_owned(),
+        count,
+        cond,
+        got,
+      }),
+      Ok(None) => {}
+      Err(e) => errs.push(e),
+    }
+  }
+}
+
+impl AssertT for Lines {
+  async fn assert(
+    &self,
+    _: AssertConfig,
+    _: &Path,
+    file_name: &str,
+    _: Result<&str, &io::Error>,
+    output: &str,
+    errs: &mut Vec<AssertError>,
+  ) {
+    // i.e., `a\nb` and `a\nb\n` both have 2 lines, and an empty file has none
+    self.check(file_name, output.lines().count(), errs);
+  }
+}
+
+impl AssertT for Match {
+  async fn assert(
+    &self,
+    _: AssertConfig,
+    _: &Path,
+    file_name: &str,
+    _: Result<&str, &io::Error>,
+    output: &str,
+    errs: &mut Vec<AssertError>,
+  ) {
+    let mut found = Found::default();
+    self.find(output, 1, &mut found);
+    self.check(file_name, found, errs);
+  }
+}
+
+#[test]
+fn test_relative_to() {
+  let workdir = Path::new("./tmp/test-sh/test.sh");
+  for path in
+    ["./tmp/test-sh/test.sh/sub/dir/out.txt", "tmp/test-sh/test.sh/sub/dir/out.txt"]
+  {
+    assert_eq!(relative_to(Path::new(path), workdir), Path::new("sub/dir/out.txt"));
+  }
+}
+
+#[test]
+fn test_glob_pattern() {
+  let tmpdir = crate::TempDir::new("glob-pattern");
+  let workdir = tmpdir.join("feature[x]");
+  std::fs::create_dir_all(workdir.join("sub")).unwrap();
+  for file in ["b.out", "a.out", "sub/c.out"] {
+    std::fs::write(workdir.join(file), "").unwrap();
+  }
+  let paths: Vec<_> = glob::glob(&glob_pattern(&workdir, "*.out"))
+    .unwrap()
+    .sorted_by(sort_entry)
+    .map(Result::unwrap)
+    .collect();
+  assert_eq!(paths, [workdir.join("a.out"), workdir.join("b.out")]);
+  let paths: Vec<_> = glob::glob(&glob_pattern(&workdir, "sub/*"))
+    .unwrap()
+    .map(Result::unwrap)
+    .collect();
+  assert_eq!(paths, [workdir.join("sub/c.out")]);
+}
+
+#[tokio::test]
+async fn test_invalid_utf8() {
+  let tmpdir = crate::TempDir::new("invalid-utf8");
+  let workdir = tmpdir.join("workdir");
+  let golden_dir = workdir.join(GOLDEN_DIR);
+  std::fs::create_dir_all(&golden_dir).unwrap();
+  std::fs::write(workdir.join("out.log"), b"ok \xC3\x28 ok\n").unwrap();
+  std::fs::write(golden_dir.join("out.log"), b"ok \xC3\x28 ok\n").unwrap();
+  let golden: Golden = toml::from_str(
+    r#"
+file = "out.log"
+equal = true
+match = [{ pattern = 'ok', count = 2 }]
+"#,
+  )
+  .unwrap();
+  let config = AssertConfig {
+    epsilon: 1e-10,
+    verbose: false,
+    stream_threshold: u64::MAX,
+    diff_threshold: u64::MAX,
+    diff_max_hunks: usize::MAX,
+    legacy_env: false,
+  };
+  let errs = golden
+    .clone()
+    .process_assert(
+      config,
+      workdir.clone(),
+      golden_dir.clone(),
+      ReadCache::default(),
+      None,
+    )
+    .await;
+  assert!(errs.is_empty(), "{}", DisplayErrs(&errs));
+  std::fs::write(golden_dir.join("out.log"), b"ok \xC3\x28\n").unwrap();
+  let errs = golden
+    .process_assert(config, workdir.clone(), golden_dir, ReadCache::default(), None)
+    .await;
+  assert!(matches!(errs.as_slice(), [AssertError::Eq { .. }, AssertError::Lossy(_)]));
+}
+
+#[test]
+fn test_text_diffs() {
+  let config = AssertConfig {
+    epsilon: 1e-10,
+    verbose: false,
+    stream_threshold: u64::MAX,
+    diff_threshold: 1 << 20,
+    diff_max_hunks: 2,
+    legacy_env: false,
+  };
+  // 2 MB, differ at line 50000
+  let old = (0..100_000)
+    .map(|idx| format!("line {idx:013}\n"))
+    .collect::<String>();
+  let new = old.replacen("line 0000000049999", "line 0000000049999 changed", 1);
+  let diffs = TextDiffs::new(&old, &new, config);
+  assert!(matches!(&diffs, TextDiffs::Brief(first_diff) if first_diff.line == 50000));
+  let report = diffs.to_string();
+  assert!(report.len() < 4096, "{report}");
+  assert!(report.contains("50000       |-line 0000000049999\n"), "{report}");
+  assert!(report.contains("      50000 |+line 0000000049999 changed\n"), "{report}");
+  assert!(report.contains("49999 49999 | line 0000000049998\n"), "{report}");
+  // different lengths, the first difference is at the end
+  let diffs = TextDiffs::new(&old, &old[..old.len() - 10], config);
+  assert!(matches!(&diffs, TextDiffs::Brief(first_diff) if first_diff.line == 100_000));
+  // the first difference is inside a multi-byte char
+  let config_1 = AssertConfig { diff_threshold: 1, ..config };
+  let diffs = TextDiffs::new("a\ncafé\n", "a\ncafè\n", config_1);
+  assert!(
+    matches!(&diffs, TextDiffs::Brief(first_diff) if first_diff.line == 2 && first_diff.offset == 5)
+  );
+  let report = diffs.to_string();
+  assert!(report.contains("|-café\n") && report.contains("|+cafè\n"), "{report}");
+  // small, but with many hunks
+  let old = (0..100).map(|idx| format!("{idx}\n")).collect::<String>();
+  let new = old.replace('5', "x");
+  let report = TextDiffs::new(&old, &new, config).to_string();
+  assert!(report.contains("more hunks truncated"), "{report}");
+  assert!(report.len() < 4096, "{report}");
+}
+
+#[test]
+fn test_text_diffs_render() {
+  struct Render<'a>(&'a TextDiffs, bool);
+  impl fmt::Display for Render<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      self.0.render(f, self.1)
+    }
+  }
+  let config = AssertConfig {
+    epsilon: 1e-10,
+    verbose: false,
+    stream_threshold: u64::MAX,
+    diff_threshold: u64::MAX,
+    diff_max_hunks: usize::MAX,
+    legacy_env: false,
+  };
+  let diffs = TextDiffs::new("a\nfoo bar\nc\n", "a\nfoo baz\nc\n", config);
+  let plain =
+    "old new\n1   1    | a\n2        |-foo bar\n    2    |+foo baz\n3   3    | c\n";
+  assert_eq!(diffs.to_string(), plain);
+  colored::control::set_override(true);
+  // the reports are kept plain even when the color output is enabled
+  assert_eq!(Render(&diffs, false).to_string(), plain);
+  assert_eq!(
+    Render(&diffs, true).to_string(),
+    "old new\n1   1    | a\n\
+     2        |\x1b[31m-\x1b[0m\x1b[31mfoo \x1b[0m\x1b[1;4;31mbar\x1b[0m\n\
+     \x20   2    |\x1b[32m+\x1b[0m\x1b[32mfoo \x1b[0m\x1b[1;4;32mbaz\x1b[0m\n\
+     3   3    | c\n"
+  );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_golden_order() {
+  use std::os::unix::fs::PermissionsExt as _;
+  let status = std::os::unix::process::ExitStatusExt::from_raw(0);
+  let tmpdir = crate::TempDir::new("golden-order");
+  let workdir = tmpdir.join("workdir");
+  std::fs::create_dir_all(&workdir).unwrap();
+  let fail = std::path::absolute(tmpdir.join("fail.sh")).unwrap();
+  std::fs::write(&fail, "#!/bin/sh\nsleep \"$DELAY\"\nexit 1\n").unwrap();
+  std::fs::set_permissions(&fail, std::fs::Permissions::from_mode(0o755)).unwrap();
+  // the declared first golden completes the last
+  let delays = [("a.log", "0.6"), ("b.log", "0.4"), ("c.log", "0.2")];
+  let goldens = delays
+    .iter()
+    .map(|(file, delay)| {
+      std::fs::write(workdir.join(file), "").unwrap();
+      format!(
+        "[[golden]]\nfile = \"{file}\"\ncustom = [{{ cmd = \"{}\", envs = {{ DELAY = \"{delay}\" }} }}]\n",
+        fail.display()
+      )
+    })
+    .join("");
+  let assert: Assert = toml::from_str(&goldens).unwrap();
+  let config = AssertConfig {
+    epsilon: 1e-10,
+    verbose: false,
+    stream_threshold: u64::MAX,
+    diff_threshold: u64::MAX,
+    diff_max_hunks: usize::MAX,
+    legacy_env: false,
+  };
+  let (errs, alive_tasks) = tokio::join!(
+    assert.assert(
+      config,
+      workdir.clone(),
+      workdir.join(GOLDEN_DIR),
+      status,
+      None,
+      String::new(),
+      None,
+    ),
+    async {
+      tokio::time::sleep(Duration::from_millis(100)).await;
+      tokio::runtime::Handle::current().metrics().num_alive_tasks()
+    }
+  );
+  // the goldens run within the test's own task
+  assert_eq!(alive_tasks, 0);
+  assert!(
+    matches!(
+      errs.as_slice(),
+      [AssertError::Custom(a, _), AssertError::Custom(b, _), AssertError::Custom(c, _)]
+        if a == "a.log" && b == "b.log" && c == "c.log"
+    ),
+    "{}",
+    DisplayErrs(&errs)
+  );
+}
+
+#[tokio::test]
+async fn test_files() {
+  let tmpdir = crate::TempDir::new("files");
+  let workdir = tmpdir.join("workdir");
+  std::fs::create_dir_all(&workdir).unwrap();
+  std::fs::write(workdir.join("out.stdout"), "ok\n").unwrap();
+  std::fs::write(workdir.join("out.stderr"), "ok\nerr\n").unwrap();
+  let golden: Golden = toml::from_str(
+    r#"
+files = ["out.stdout", "out.stderr", "out.log"]
+match = [{ pattern = 'ok', count = 1 }]
+"#,
+  )
+  .unwrap();
+  let config = AssertConfig {
+    epsilon: 1e-10,
+    verbose: false,
+    stream_threshold: u64::MAX,
+    diff_threshold: u64::MAX,
+    diff_max_hunks: usize::MAX,
+    legacy_env: false,
+  };
+  let errs = golden
+    .process_assert(
+      config,
+      workdir.clone(),
+      workdir.join(GOLDEN_DIR),
+      ReadCache::default(),
+      None,
+    )
+    .await;
+  assert!(
+    matches!(errs.as_slice(), [AssertError::NoSuchFile(file)] if file == "out.log"),
+    "{}",
+    DisplayErrs(&errs)
+  );
+}
+
+#[tokio::test]
+async fn test_stream() {
+  use std::io::Write as _;
+  let tmpdir = crate::TempDir::new("stream");
+  let workdir = tmpdir.join("workdir");
+  std::fs::create_dir_all(&workdir).unwrap();
+  let mut writer =
+    std::io::BufWriter::new(std::fs::File::create(workdir.join("huge.log")).unwrap());
+  // 100 MB
+  for idx in 0..1_000_000 {
+    writeln!(writer, "iter {idx:>8} residual 0.5 {:.<70}", "").unwrap();
+  }
+  writeln!(writer, "final residual 0.25\nwarning\ndone").unwrap();
+  drop(writer);
+  let config = AssertConfig {
+    epsilon: 1e-10,
+    verbose: false,
+    stream_threshold: 1 << 20,
+    diff_threshold: u64::MAX,
+    diff_max_hunks: usize::MAX,
+    legacy_env: false,
+  };
+  let golden: Golden = toml::from_str(
+    r#"
+file = "huge.log"
+match = [{ pattern = '^warning$', count = 1 }, { pattern = 'residual', count = 1_000_001 }]
+lines = { count = 1_000_003 }
+value = [{ pattern-before = 'residual', value-at-most = 0.5 }]
+"#,
+  )
+  .unwrap();
+  let errs = golden
+    .process_assert(
+      config,
+      workdir.clone(),
+      workdir.join(GOLDEN_DIR),
+      ReadCache::default(),
+      None,
+    )
+    .await;
+  assert!(errs.is_empty(), "{}", DisplayErrs(&errs));
+  let golden: Golden = toml::from_str(
+    r#"
+file = "huge.log"
+equal = true
+match = [{ pattern = 'warning\ndone', count = 1 }]
+"#,
+  )
+  .unwrap();
+  let errs = golden
+    .process_assert(
+      config,
+      workdir.clone(),
+      workdir.join(GOLDEN_DIR),
+      ReadCache::default(),
+      None,
+    )
+    .await;
+  assert!(matches!(
+    errs.as_slice(),
+    [AssertError::TooLarge(..), AssertError::MultiLine(..)]
+  ));
+  let golden: Golden = toml::from_str(
+    r#"
+file = "huge.log"
+match = [{ pattern = '^final residual', count = 1 }]
+value = [{ pattern-before = 'final residual', value = 0.5 }]
+"#,
+  )
+  .unwrap();
+  let errs = golden
+    .process_assert(
+      config,
+      workdir.clone(),
+      workdir.join(GOLDEN_DIR),
+      ReadCache::default(),
+      None,
+    )
+    .await;
+  assert!(matches!(
+    errs.as_slice(),
+    [AssertError::Value(_, ValueReport::AssertFail { line: 1_000_001, .. })]
+  ));
+  // only the first matches are kept, the others are counted
+  let golden: Golden = toml::from_str(
+    r#"
+file = "huge.log"
+match = [{ pattern = 'residual', count = 1 }]
+"#,
+  )
+  .unwrap();
+  let errs = golden
+    .process_assert(
+      config,
+      workdir.clone(),
+      workdir.join(GOLDEN_DIR),
+      ReadCache::default(),
+      None,
+    )
+    .await;
+  assert!(
+    matches!(
+      errs.as_slice(),
+      [AssertError::Match(_, report)]
+        if report.found.count == 1_000_001
+          && report.found.listed.len() == MATCH_LISTED
+          && report.to_string().ends_with("  #10 at line 10: \"residual\"\n  ... 999991 more match(es)\n")
+    ),
+    "{}",
+    DisplayErrs(&errs)
+  );
+  // the last capture is kept across the streamed lines
+  let golden: Golden = toml::from_str(
+    r#"
+file = "huge.log"
+value = [
+  { pattern-before = '^iter', monotonic = "non-decreasing" },
+  { pattern-before = '^iter', monotonic = "non-increasing" },
+]
+"#,
+  )
+  .unwrap();
+  let errs = golden
+    .process_assert(
+      config,
+      workdir.clone(),
+      workdir.join(GOLDEN_DIR),
+      ReadCache::default(),
+      None,
+    )
+    .await;
+  assert!(
+    matches!(
+      errs.as_slice(),
+      [AssertError::Value(_, ValueReport::Monotonic(report))]
+        if report.prev.0 == 1 && report.got.0 == 2
+    ),
+    "{}",
+    DisplayErrs(&errs)
+  );
+}
+
+#[tokio::test]
+async fn test_monotonic() {
+  let config = AssertConfig {
+    epsilon: 1e-10,
+    verbose: false,
+    stream_threshold: u64::MAX,
+    diff_threshold: u64::MAX,
+    diff_max_hunks: usize::MAX,
+    legacy_env: false,
+  };
+  let output = "loss 3\nloss 2\nloss 2.5\nloss 1\nloss 1.5\n";
+  let assert = |toml_str: &str| {
+    let value: Value = toml::from_str(toml_str).unwrap();
+    async move {
+      let mut errs = Vec::new();
+      value
+        .assert(config, Path::new(""), "out", Ok(""), output, &mut errs)
+        .await;
+      errs
+    }
+  };
+  let errs = assert("pattern-before = 'loss'\nmonotonic = 'non-increasing'").await;
+  // only the first violating pair is reported
+  assert!(
+    matches!(
+      errs.as_slice(),
+      [AssertError::Value(_, ValueReport::Monotonic(report))]
+        if report.prev == (2, "loss 2".into(), 2.0) && report.got == (3, "loss 2.5".into(), 2.5)
+    ),
+    "{}",
+    DisplayErrs(&errs)
+  );
+  let errs =
+    assert("pattern-before = 'loss'\nmonotonic = 'non-increasing'\nepsilon = 0.5").await;
+  assert!(errs.is_empty(), "{}", DisplayErrs(&errs));
+  let errs =
+    assert("pattern-before = 'loss'\nmonotonic = 'non-decreasing'\nvalue-at-most = 2.5")
+      .await;
+  assert!(matches!(
+    errs.as_slice(),
+    [
+      AssertError::Value(_, ValueReport::AssertFail { line: 1, .. }),
+      AssertError::Value(_, ValueReport::Monotonic(_))
+    ]
+  ));
+  let errs = assert("pattern-before = 'loss'").await;
+  assert!(matches!(errs.as_slice(), [AssertError::Value(_, ValueReport::Config)]));
+}
+
+#[tokio::test]
+async fn test_value_file() {
+  let config = AssertConfig {
+    epsilon: 1e-10,
+    verbose: false,
+    stream_threshold: u64::MAX,
+    diff_threshold: u64::MAX,
+    diff_max_hunks: usize::MAX,
+    legacy_env: false,
+  };
+  let tmpdir = crate::TempDir::new("value-file");
+  let workdir = tmpdir.join("workdir");
+  std::fs::create_dir_all(workdir.join(GOLDEN_DIR)).unwrap();
+  std::fs::write(workdir.join(GOLDEN_DIR).join("loss.txt"), "1.5\n").unwrap();
+  std::fs::write(workdir.join(GOLDEN_DIR).join("bad.txt"), "NaN?").unwrap();
+  let assert = |toml_str: &str| {
+    let value: Value = toml::from_str(toml_str).unwrap();
+    let workdir = workdir.clone();
+    async move {
+      let mut errs = Vec::new();
+      value
+        .assert(config, &workdir, "out", Ok(""), "loss 1.5\n", &mut errs)
+        .await;
+      errs
+    }
+  };
+  let errs = assert("pattern-before = 'loss'\nvalue-file = 'loss.txt'").await;
+  assert!(errs.is_empty(), "{}", DisplayErrs(&errs));
+  let errs = assert("pattern-before = 'loss'\nvalue-file = 'bad.txt'").await;
+  assert!(matches!(
+    errs.as_slice(),
+    [AssertError::Value(_, ValueReport::ValueFile { .. })]
+  ));
+  let errs = assert("pattern-before = 'loss'\nvalue-file = 'none.txt'").await;
+  assert!(matches!(
+    errs.as_slice(),
+    [AssertError::Value(_, ValueReport::ValueFile { .. })]
+  ));
+  let errs =
+    assert("pattern-before = 'loss'\nvalue-file = 'loss.txt'\nvalue = 1.5").await;
+  assert!(matches!(errs.as_slice(), [AssertError::Value(_, ValueReport::Config)]));
+}
+
+#[tokio::test]
+async fn test_value_table() {
+  let config = AssertConfig {
+    epsilon: 1e-10,
+    verbose: false,
+    stream_threshold: u64::MAX,
+    diff_threshold: u64::MAX,
+    diff_max_hunks: usize::MAX,
+    legacy_env: false,
+  };
+  let output = "\
+header
+phase=parse time=1.4
+phase=codegen time=3.5
+phase=link time=0.2
+phase=parse time=1.6
+footer
+";
+  let assert = |toml_str: String| async move {
+    let value: Value = toml::from_str(&toml_str).unwrap();
+    let mut errs = Vec::new();
+    value
+      .assert(config, Path::new("."), "out", Ok(""), output, &mut errs)
+      .await;
+    errs
+  };
+  let table = |rest: &str| {
+    format!("key-pattern = 'phase=(\\w+)'\nvalue-pattern = 'time=([-+0-9.eE]+)'\n{rest}")
+  };
+  let table_report = |errs: &Vec<AssertError>| match errs.as_slice() {
+    [AssertError::Value(_, ValueReport::Table(report))] => report.to_string(),
+    _ => panic!("{}", DisplayErrs(errs)),
+  };
+  // per-key epsilon, and `allow-extra` ignores `link`
+  let errs = assert(table(
+    "epsilon = 0.2\nallow-extra = true\nexpect = { parse = 1.5, codegen = { value = 3.0, epsilon = 0.5 } }",
+  ))
+  .await;
+  assert!(errs.is_empty(), "{}", DisplayErrs(&errs));
+  // out of tolerance, with the line numbers of each occurrence
+  let errs = assert(table(
+    "epsilon = 0.05\nallow-extra = true\nexpect = { parse = 1.5, codegen = 3.5 }",
+  ))
+  .await;
+  let report = table_report(&errs);
+  assert!(report.contains("key 'parse' at line 2, want 1.5±0.05, got: 1.4"), "{report}");
+  assert!(report.contains("key 'parse' at line 5, want 1.5±0.05, got: 1.6"), "{report}");
+  assert!(!report.contains("codegen"), "{report}");
+  // missing and unexpected keys
+  let errs = assert(table(
+    "epsilon = 0.2\nexpect = { parse = 1.5, codegen = 3.5, optimize = 2.0 }",
+  ))
+  .await;
+  let report = table_report(&errs);
+  assert!(report.contains("missing key 'optimize'"), "{report}");
+  assert!(report.contains("unexpected key 'link' at line 4"), "{report}");
+  assert!(!report.contains("want"), "{report}");
+  // invalid combinations
+  let errs = assert(table("value = 1.0\nexpect = { parse = 1.5 }")).await;
+  assert!(matches!(errs.as_slice(), [AssertError::Value(_, ValueReport::TableConfig)]));
+  let errs = assert(table("")).await;
+  assert!(matches!(errs.as_slice(), [AssertError::Value(_, ValueReport::TableConfig)]));
+  let errs = assert(table("expect = { parse = { value = 1.5, epsilon = -1.0 } }")).await;
+  assert!(matches!(
+    errs.as_slice(),
+    [AssertError::Value(_, ValueReport::NegativeEpsilon(_))]
+  ));
+}
+
+#[test]
+fn test_trim() {
+  let golden: Golden = toml::from_str("file = 'out'\nequal = true").unwrap();
+  assert_eq!(golden.trim("a \n\n"), "a \n\n");
+  let golden: Golden =
+    toml::from_str("file = 'out'\nequal = true\nignore-trailing-newlines = true")
+      .unwrap();
+  assert_eq!(golden.trim("a \r\n\n"), "a ");
+  let golden: Golden =
+    toml::from_str("file = 'out'\nequal = true\nignore-trailing-whitespace = true")
+      .unwrap();
+  assert_eq!(golden.trim("a \t\n \n"), "a");
+}
+
+#[tokio::test]
+async fn test_read_cache() {
+  let tmpdir = crate::TempDir::new("read-cache");
+  let path = tmpdir.join("read-cache.log");
+  std::fs::write(&path, "content").unwrap();
+  let cache = ReadCache::default();
+  let (read1, read2) = tokio::join!(cache.read(&path), cache.read(&path));
+  let (read1, read2) = (read1.unwrap(), read2.unwrap());
+  assert_eq!(read1.0, "content");
+  // read once, then shared
+  assert!(Arc::ptr_eq(&read1.cell, &read2.cell));
+  std::fs::write(&path, "changed").unwrap();
+  assert!(Arc::ptr_eq(&read1.cell, &cache.read(&path).await.unwrap().cell));
+  assert_eq!(ReadCache::default().read(&path).await.unwrap().0, "changed");
+  // dropped after the last use
+  drop((read1, read2));
+  assert_eq!(cache.read(&path).await.unwrap().0, "changed");
+  assert_eq!(cache.0.lock().unwrap().len(), 1);
+  _ = std::fs::remove_file(&path);
+  assert!(cache.read(path.with_extension("none")).await.is_err());
+}
+
+#[test]
+fn test_sorted() {
+  let check = |spec, text: &str| {
+    let mut checker = SortChecker::new(spec);
+    text
+      .lines()
+      .enumerate()
+      .find_map(|(idx, l)| checker.check(idx + 1, l))
+  };
+  assert!(check(SortSpec::Lines, "a\nb\nb\nc").is_none());
+  let report = check(SortSpec::Lines, "a\nc\nb\nd").unwrap();
+  assert_eq!((report.prev.0, report.got.0), (2, 3));
+  assert_eq!(
+    report.to_string(),
+    "want sorted by lines, line 2 is before line 3:\n  \"c\"\n  \"b\"\n"
+  );
+  // "10" < "9" by the bytes
+  assert!(check(SortSpec::Lines, "9 a\n10 b").is_some());
+  assert!(check(SortSpec::Numeric, "x\n-1.5 a\n9 b\n10 c\n10.5").is_some());
+  assert!(check(SortSpec::Numeric, "-1.5 a\nx\n9 b\n10 c\n10.5").is_none());
+  assert!(check(SortSpec::Lines, "B\na").is_none());
+  assert!(check(SortSpec::CaseInsensitive, "a\nB\nc").is_none());
+  assert!(check(SortSpec::CaseInsensitive, "B\na").is_some());
+  assert_eq!(leading_number("  42abc"), 42.0);
+  assert_eq!(leading_number("1.5.2"), 1.5);
+  assert_eq!(leading_number("-"), 0.0);
+}
+
+#[tokio::test]
+async fn test_unique() {
+  let config = AssertConfig {
+    epsilon: 1e-10,
+    verbose: false,
+    stream_threshold: u64::MAX,
+    diff_threshold: u64::MAX,
+    diff_max_hunks: usize::MAX,
+    legacy_env: false,
+  };
+  let assert = |output: String| async move {
+    let mut errs = Vec::new();
+    Unique
+      .assert(config, Path::new(""), "out", Ok(""), &output, &mut errs)
+      .await;
+    errs
+  };
+  assert!(assert("a\nb\nc\n".into()).await.is_empty());
+  let errs = assert("a\nb\na\nc\nb\na\n".into()).await;
+  assert!(
+    matches!(
+      errs.as_slice(),
+      [AssertError::Unique(_, report)]
+        if report.to_string() == "2 duplicated line(s):\n  \"a\" at lines 1, 3, 6\n  \"b\" at lines 2, 5\n"
+    ),
+    "{}",
+    DisplayErrs(&errs)
+  );
+  // only the first ones are listed
+  let output = (0..UNIQUE_LISTED + 3).map(|idx| format!("{idx}\n{idx}\n")).collect();
+  let errs = assert(output).await;
+  assert!(matches!(
+    errs.as_slice(),
+    [AssertError::Unique(_, report)]
+      if report.duplicates.len() == UNIQUE_LISTED && report.count == UNIQUE_LISTED + 3
+  ));
+}
+
+#[tokio::test]
+async fn test_lines() {
+  let tmpdir = crate::TempDir::new("lines");
+  let workdir = tmpdir.join("workdir");
+  std::fs::create_dir_all(&workdir).unwrap();
+  std::fs::write(workdir.join("newline.log"), "a\nb\n").unwrap();
+  std::fs::write(workdir.join("no-newline.log"), "a\nb").unwrap();
+  std::fs::write(workdir.join("empty.log"), "").unwrap();
+  let assert = |stream_threshold, golden: &str| {
+    let config = AssertConfig {
+      epsilon: 1e-10,
+      verbose: false,
+      stream_threshold,
+      diff_threshold: u64::MAX,
+      diff_max_hunks: usize::MAX,
+      legacy_env: false,
+    };
+    let golden: Golden = toml::from_str(golden).unwrap();
+    let workdir = workdir.clone();
+    async move {
+      golden
+        .process_assert(
+          config,
+          workdir.clone(),
+          workdir.join(GOLDEN_DIR),
+          ReadCache::default(),
+          None,
+        )
+        .await
+    }
+  };
+  // the same counts in memory and streamed
+  for stream_threshold in [u64::MAX, 0] {
+    for golden in [
+      "file = \"newline.log\"\nlines = { count = 2 }",
+      "file = \"no-newline.log\"\nlines = { count-at-most = 2 }",
+      "file = \"empty.log\"\nlines = { count = 0 }",
+    ] {
+      let errs = assert(stream_threshold, golden).await;
+      assert!(errs.is_empty(), "{golden}: {}", DisplayErrs(&errs));
+    }
+    let errs =
+      assert(stream_threshold, "file = \"newline.log\"\nlines = { count-at-least = 3 }")
+        .await;
+    assert!(
+      matches!(
+        errs.as_slice(),
+        [err @ AssertError::Lines { got: 2, .. }]
+          if err.to_string() == "file \"newline.log\" want at least 3 lines, got: 2"
+      ),
+      "{}",
+      DisplayErrs(&errs)
+    );
+    let errs = assert(
+      stream_threshold,
+      "file = \"newline.log\"\nlines = { count = 2, count-at-most = 2 }",
+    )
+    .await;
+    assert!(matches!(errs.as_slice(), [AssertError::CountConfig]));
+  }
+}
+
+#[tokio::test]
+async fn test_compare() {
+  let tmpdir = crate::TempDir::new("compare");
+  let dir = tmpdir.join("dir");
+  std::fs::create_dir_all(&dir).unwrap();
+  std::fs::write(dir.join("golden.log"), "ok\nvalue 1.0\n").unwrap();
+  std::fs::write(dir.join("out.log"), "ok\nvalue 1.5\n").unwrap();
+  let spec: Golden = toml::from_str(
+    r#"
+file = "ignored.log"
+equal = true
+match = [{ pattern = 'ok', count = 1 }]
+"#,
+  )
+  .unwrap();
+  let golden = dir.join("golden.log");
+  let errs = compare(&golden, &golden, &spec, AssertConfig::default()).await;
+  assert!(errs.is_empty(), "{}", DisplayErrs(&errs));
+  let errs = compare(&golden, &dir.join("out.log"), &spec, AssertConfig::default()).await;
+  assert!(
+    matches!(errs.as_slice(), [AssertError::Eq { file_name, .. }] if file_name == "out.log"),
+    "{}",
+    DisplayErrs(&errs)
+  );
+  let soft: Golden = toml::from_str("equal = true\nsoft = true").unwrap();
+  let errs = compare(&golden, &dir.join("out.log"), &soft, AssertConfig::default()).await;
+  assert!(errs.iter().all(AssertError::is_soft) && errs.len() == 1);
+  let errs =
+    compare(&golden, &dir.join("none.log"), &spec, AssertConfig::default()).await;
+  assert!(matches!(errs.as_slice(), [AssertError::UnableToRead(..)]));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_tree() {
+  let tmpdir = crate::TempDir::new("tree");
+  let dir = tmpdir.join("dir");
+  let (workdir, golden_dir, inputs) =
+    (dir.join("work"), dir.join("golden"), dir.join("in"));
+  for sub in ["out/a/b", "out/same"] {
+    std::fs::create_dir_all(workdir.join(sub)).unwrap();
+    std::fs::create_dir_all(golden_dir.join(sub)).unwrap();
+  }
+  std::fs::create_dir_all(&inputs).unwrap();
+  for (file, golden, output) in [
+    ("same/x.txt", Some("x\n"), Some("x\n")),
+    ("a/b/changed.txt", Some("1\n2\n3\n"), Some("1\ntwo\n3\n")),
+    ("a/trailing.txt", Some("t"), Some("t\n\n")),
+    ("a/b/removed.txt", Some("r\n"), None),
+    ("a/added.txt", None, Some("new\n")),
+  ] {
+    if let Some(golden) = golden {
+      std::fs::write(golden_dir.join("out").join(file), golden).unwrap();
+    }
+    if let Some(output) = output {
+      std::fs::write(workdir.join("out").join(file), output).unwrap();
+    }
+  }
+  // linked by the harness, which is not an output
+  std::fs::write(inputs.join("input.txt"), "input").unwrap();
+  std::os::unix::fs::symlink(inputs.join("input.txt"), workdir.join("out/input.txt"))
+    .unwrap();
+  let golden: Golden =
+    toml::from_str("file = \"out\"\ntree = true\nignore-trailing-newlines = true")
+      .unwrap();
+  let errs = golden
+    .clone()
+    .process_assert(
+      AssertConfig::default(),
+      workdir.clone(),
+      golden_dir.clone(),
+      ReadCache::default(),
+      None,
+    )
+    .await;
+  let [AssertError::Tree(file, report)] = errs.as_slice() else {
+    panic!("{}", DisplayErrs(&errs))
+  };
+  assert_eq!(file, "out");
+  assert_eq!(report.missing, ["a/b/removed.txt"]);
+  assert_eq!(report.unexpected, ["a/added.txt"]);
+  assert_eq!(report.different.len(), 1);
+  let text = errs[0].to_string();
+  assert!(text.contains("1 missing file(s):\n  a/b/removed.txt\n"), "{text}");
+  assert!(text.contains("---- a/b/changed.txt ---\n"), "{text}");
+  assert!(text.contains("-2") && text.contains("+two"), "{text}");
+  // the same trees, and the missing golden tree
+  let errs = compare(
+    &golden_dir.join("out/same"),
+    &workdir.join("out/same"),
+    &golden,
+    AssertConfig::default(),
+  )
+  .await;
+  assert!(errs.is_empty(), "{}", DisplayErrs(&errs));
+  let errs = compare(
+    &golden_dir.join("none"),
+    &workdir.join("out/same"),
+    &golden,
+    AssertConfig::default(),
+  )
+  .await;
+  assert!(matches!(errs.as_slice(), [AssertError::UnableToReadDir(..)]));
+  // only the first different files have the diffs
+  for idx in 0..TREE_DIFFS + 2 {
+    std::fs::write(workdir.join(format!("out/same/{idx:02}.txt")), "new").unwrap();
+    std::fs::write(golden_dir.join(format!("out/same/{idx:02}.txt")), "old").unwrap();
+  }
+  let errs = compare(
+    &golden_dir.join("out/same"),
+    &workdir.join("out/same"),
+    &golden,
+    AssertConfig::default(),
+  )
+  .await;
+  let [AssertError::Tree(_, report)] = errs.as_slice() else { panic!() };
+  assert_eq!(report.different.len(), TREE_DIFFS + 2);
+  let text = errs[0].to_string();
+  assert_eq!(text.matches("\n---- ").count(), TREE_DIFFS + 1, "{text}");
+  assert!(text.ends_with("omitted ---\n  08.txt\n  09.txt\n"), "{text}");
+}
+
+#[test]
+fn test_json_contains() {
+  let want: serde_json::Map<_, _> = toml::from_str(
+    r#"
+status = "ok"
+data = { count = 2, items = [{ id = 1 }, { id = 2, tags = ["a"] }] }
+"#,
+  )
+  .unwrap();
+  let want = serde_json::Value::Object(want);
+  let check = |got: &str| {
+    let got: serde_json::Value = serde_json::from_str(got).unwrap();
+    let mut diffs = StructDiffs::new(true);
+    diffs.visit(&mut String::from("$"), &want, &got);
+    diffs.report().map(|report| report.to_string())
+  };
+  // the extra keys are ignored, the key order and the number types do not matter
+  assert!(
+    check(
+      r#"{"time": 1.5, "data": {"items": [{"id": 1.0, "x": 0}, {"tags": ["a"], "id": 2}],
+      "count": 2}, "status": "ok"}"#
+    )
+    .is_none()
+  );
+  let got = check(r#"{"status": "ok", "data": {"count": 2, "items": [{"id": 1}, {}]}}"#);
+  assert_eq!(got.unwrap(), "missing $.data.items[1].id\nmissing $.data.items[1].tags\n");
+  let got = check(
+    r#"{"status": "ok", "data": {"count": 2, "items": [{"id": 1}, {"id": 2, "tags": ["b"]}]}}"#,
+  );
+  assert_eq!(
+    got.unwrap(),
+    "mismatched $.data.items[1].tags[0], want: \"a\", got: \"b\"\n"
+  );
+  let got = check(r#"{"status": "ok", "data": {"count": 2, "items": [{"id": 1}]}}"#);
+  assert!(got.unwrap().starts_with("mismatched $.data.items, want: [{"));
+  let got = check(r#"{"status": "ok", "data": []}"#);
+  assert!(got.unwrap().starts_with("mismatched $.data, want: {"));
+  assert_eq!(check("[]").unwrap(), format!("mismatched $, want: {want}, got: []\n"));
+  // all the differences are listed
+  let got =
+    check(r#"{"data": {"count": 3, "items": [{"id": 1}, {"id": 2, "tags": []}]}}"#);
+  assert_eq!(
+    got.unwrap(),
+    "mismatched $.data.count, want: 2, got: 3\n\
+     mismatched $.data.items[1].tags, want: [\"a\"], got: []\n\
+     missing $.status\n"
+  );
+}
+
+#[tokio::test]
+async fn test_json_contains_golden() {
+  let tmpdir = crate::TempDir::new("json-contains-golden");
+  let dir = tmpdir.join("dir");
+  std::fs::create_dir_all(&dir).unwrap();
+  std::fs::write(dir.join("out.json"), r#"{"status": "error", "id": 42}"#).unwrap();
+  std::fs::write(dir.join("bad.json"), "{ not json").unwrap();
+  let spec: Golden = toml::from_str("json-contains = { status = \"ok\" }").unwrap();
+  let out = dir.join("out.json");
+  let errs = compare(&out, &out, &spec, AssertConfig::default()).await;
+  assert!(
+    matches!(
+      errs.as_slice(),
+      [AssertError::JsonContains(file, StructReport::Diffs(diffs, 1))]
+        if file == "out.json"
+          && matches!(&diffs[..], [StructDiff::Mismatch { path, .. }] if path == "$.status")
+    ),
+    "{}",
+    DisplayErrs(&errs)
+  );
+  assert_eq!(errs[0].code(), "json_contains");
+  let bad = dir.join("bad.json");
+  let errs = compare(&bad, &bad, &spec, AssertConfig::default()).await;
+  assert!(matches!(
+    errs.as_slice(),
+    [AssertError::JsonContains(_, StructReport::Invalid(_))]
+  ));
+}
+
+#[tokio::test]
+async fn test_yaml_equal() {
+  let tmpdir = crate::TempDir::new("yaml-equal");
+  let dir = tmpdir.join("dir");
+  std::fs::create_dir_all(&dir).unwrap();
+  let golden = dir.join("golden.yaml");
+  std::fs::write(
+    &golden,
+    "base: &base\n  retries: 3\n  timeout: 1.0\nname: 'svc'\nports: [80, 443]\nprod: *base\n",
+  )
+  .unwrap();
+  let spec: Golden = toml::from_str("yaml-equal = true").unwrap();
+  let compare_with = |output: &str| {
+    let out = dir.join("out.yaml");
+    std::fs::write(&out, output).unwrap();
+    let golden = golden.clone();
+    let spec = spec.clone();
+    async move { compare(&golden, &out, &spec, AssertConfig::default()).await }
+  };
+  // the key order, quoting, anchors, and number types do not matter
+  let errs = compare_with(
+    "prod:\n  timeout: 1\n  retries: 3\nname: \"svc\"\nports:\n  - 80\n  - 443\n\
+     base: {retries: 3, timeout: 1.0}\n",
+  )
+  .await;
+  assert!(errs.is_empty(), "{}", DisplayErrs(&errs));
+  let errs = compare_with(
+    "base: {retries: 5, timeout: 1.0}\nname: svc\nports: [80]\nprod: {retries: 3}\nextra: 1\n",
+  )
+  .await;
+  let [AssertError::YamlEqual(file, report)] = errs.as_slice() else {
+    panic!("{}", DisplayErrs(&errs))
+  };
+  assert_eq!(file, "out.yaml");
+  assert_eq!(
+    report.to_string(),
+    "mismatched $.base.retries, want: 3, got: 5\n\
+     missing $.ports[1]\n\
+     missing $.prod.timeout\n\
+     unexpected $.extra\n"
+  );
+  assert_eq!(errs[0].code(), "yaml_equal");
+  let errs = compare_with("name: [unclosed\n").await;
+  assert!(matches!(
+    errs.as_slice(),
+    [AssertError::YamlEqual(_, StructReport::Invalid(e))] if e.starts_with("invalid YAML of the output")
+  ));
+}
+
+#[tokio::test]
+async fn test_code() {
+  assert_eq!(AssertError::TimeOut(1).code(), "timeout");
+  assert_eq!(AssertError::TimeOut(1).file(), None);
+  let err = AssertError::ExitCode { want: 0, got: 1, cmd: String::new() };
+  assert_eq!((err.code(), err.file()), ("exit_code", None));
+  let err = AssertError::Soft(Box::new(AssertError::NoSuchFile("out.log".into())));
+  assert_eq!((err.code(), err.file()), ("no_such_file", Some("out.log")));
+  let tmpdir = crate::TempDir::new("code");
+  let dir = tmpdir.join("dir");
+  std::fs::create_dir_all(&dir).unwrap();
+  std::fs::write(dir.join("out.log"), "ok\nvalue 1.5\n").unwrap();
+  let spec: Golden = toml::from_str(
+    r#"
+equal = true
+match = [{ pattern = 'ok', count = 2 }]
+value = [{ pattern-before = 'value', value = 1.0 }]
+"#,
+  )
+  .unwrap();
+  let output = dir.join("out.log");
+  let errs = compare(&output, &output, &spec, AssertConfig::default()).await;
+  assert_eq!(errs.iter().map(AssertError::code).collect::<Vec<_>>(), ["match", "value"]);
+  assert!(errs.iter().all(|e| e.file() == Some("out.log")));
+}
+
+#[test]
+fn test_bounded_errs() {
+  use fmt::Write as _;
+  let mut writer = BoundedWriter::new(5);
+  assert!(writer.write_str("abc").is_ok());
+  // never split a char
+  assert!(writer.write_str("d\u{e9}").is_err());
+  assert_eq!((writer.buf.as_str(), writer.truncated), ("abcd", true));
+  let errs = vec![AssertError::NoSuchFile("a.log".into()), AssertError::TimeOut(1)];
+  let full = bounded_errs(&errs, "tails\n", usize::MAX, false);
+  assert_eq!(full, format!("{}tails\n", DisplayErrs(&errs)));
+  let report = bounded_errs(&errs, "tails\n", 50, false);
+  assert_eq!(
+    report,
+    "==== ERROR 1 ===\nfile \"a.log\": no such file\n==== E\n\
+     ==== TRUNCATED ===\nreport truncated at 50 bytes; 1 error(s) omitted\n"
+  );
+  // the tails are cut off, but no error is omitted
+  let max = full.len() - 2;
+  let report = bounded_errs(&errs, "tails\n", max, false);
+  assert!(report.starts_with(&full[..max]), "{report}");
+  assert!(report.ends_with(&format!("at {max} bytes; 0 error(s) omitted\n")));
+}
+
 #[test]
 fn valuematch() {
   let re = regex::Regex::new(&format!(
@@ -701,3 +3702,12 @@ fn valuematch() {
   dbg!(cap.get(0));
   dbg!(cap.get(1));
 }
+
+#[cfg(unix)]
+#[test]
+fn test_signal_names() {
+  for &name in crate::schema::SIGNALS {
+    let signal: SignalName = toml::Value::String(name.to_owned()).try_into().unwrap();
+    assert_eq!(signal.0.as_str(), name);
+  }
+}
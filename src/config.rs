@@ -1,27 +1,87 @@
+use clap::ValueEnum as _;
 use core::fmt;
 use indexmap::IndexMap;
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use std::{
   borrow::Cow,
-  collections::HashSet,
+  collections::{BTreeSet, HashSet},
   ffi::OsStr,
-  fs::{File, create_dir_all, read_to_string, remove_dir_all},
-  io::Write as _,
+  fs::{File, TryLockError, create_dir_all, read_to_string, remove_dir_all},
+  hash::{DefaultHasher, Hasher as _},
+  io,
+  net::TcpListener,
   ops::{Deref, DerefMut},
-  path::{Path, PathBuf},
+  path::{Component, Path, PathBuf},
   process::{ExitStatus, Stdio},
-  time::{Duration, Instant},
+  sync::{
+    Arc, Mutex,
+    atomic::{AtomicUsize, Ordering},
+  },
+  time::{Duration, Instant, SystemTime},
+};
+use tokio::{
+  io::{AsyncReadExt as _, AsyncSeekExt as _},
+  process::Command,
+  sync::Semaphore,
+  time::timeout,
 };
-use tokio::{process::Command, time::timeout};
 
 use crate::{
   Args, Assert,
-  assert::{AssertConfig, AssertError, DisplayErrs},
+  assert::{
+    AssertConfig, AssertError, GoldenRefs, TextDiffs, bounded_errs, glob_pattern,
+    relative_to,
+  },
   regression::{BuildError, FailedState, GOLDEN_DIR, State},
 };
 
+/// The default `epsilon`
+pub(crate) const EPSILON: f32 = 1e-10;
+/// The default `stream-threshold`, 256 MiB
+pub(crate) const STREAM_THRESHOLD: u64 = 256 << 20;
+/// The default `diff-threshold`, 16 MiB
+pub(crate) const DIFF_THRESHOLD: u64 = 16 << 20;
+/// The default `diff-max-hunks`
+pub(crate) const DIFF_MAX_HUNKS: usize = 32;
+/// The default `report-tail`
+const REPORT_TAIL: usize = 50;
+/// The default `env-allowlist`
+const ENV_ALLOWLIST: [&str; 3] = ["PATH", "HOME", "TMPDIR"];
+/// The max bytes of all `report-files` tails appended to a report, 64 KiB
+const REPORT_TAILS_MAX: u64 = 64 << 10;
+
 #[derive(Debug)]
-pub(crate) struct SigIntDisplay(Option<i32>);
+pub struct SigIntDisplay(pub(crate) Option<i32>);
+pub(crate) fn signal_name(sig_int: Option<i32>) -> &'static str {
+  #[cfg(unix)]
+  let name = sig_int
+    .and_then(|sig| nix::sys::signal::Signal::try_from(sig).ok())
+    .map(|sig| sig.as_str());
+  #[cfg(not(unix))]
+  let name: Option<&'static str> = sig_int.and(None);
+  name.unwrap_or("UNKOWN")
+}
+/// The exit code, or the signal name when it is killed
+fn status_str(status: ExitStatus) -> String {
+  match status.code() {
+    Some(code) => code.to_string(),
+    None => signal_name(exit_signal(status)).to_owned(),
+  }
+}
+/// The signal that terminated the process, always `None` on non-unix
+pub(crate) fn exit_signal(status: ExitStatus) -> Option<i32> {
+  #[cfg(unix)]
+  {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+  }
+  #[cfg(not(unix))]
+  {
+    _ = status;
+    None
+  }
+}
 impl fmt::Display for SigIntDisplay {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     if let Some(sig) = self.0 { write!(f, " (raw = {sig})") } else { Ok(()) }
@@ -32,18 +92,35 @@ pub(crate) struct CmdDisplay<'s, S: AsRef<str>> {
   pub(crate) args: &'s [String],
   pub(crate) workdir: &'s Path,
   pub(crate) envs: Option<&'s IndexMap<S, String>>,
+  /// With `clear-env`, the inherited envs, then it is shown as `env -i`
+  pub(crate) cleared: Option<&'s IndexMap<String, String>>,
+  /// The `unset-envs`, not shown with `cleared`, since they are not inherited
+  pub(crate) unset: &'s [String],
 }
 
 impl<S: AsRef<str>> fmt::Display for CmdDisplay<'_, S> {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     writeln!(f, "```bash")?;
-    if let Some(envs) = self.envs {
-      for (k, v) in envs {
-        writeln!(f, "export {:?}={v:?}", k.as_ref())?;
+    if let Some(cleared) = self.cleared {
+      writeln!(f, "cd {:?}", self.workdir)?;
+      write!(f, "env -i")?;
+      let envs = self.envs.into_iter().flatten().map(|(k, v)| (k.as_ref(), v));
+      for (k, v) in cleared.iter().map(|(k, v)| (k.as_str(), v)).chain(envs) {
+        write!(f, " \\\n\t{:?}", format!("{k}={v}"))?;
+      }
+      write!(f, " \\\n\t{:?}", self.cmd)?;
+    } else {
+      for k in self.unset {
+        writeln!(f, "unset {k:?}")?;
       }
+      if let Some(envs) = self.envs {
+        for (k, v) in envs {
+          writeln!(f, "export {:?}={v:?}", k.as_ref())?;
+        }
+      }
+      writeln!(f, "cd {:?}", self.workdir)?;
+      write!(f, "{:?}", self.cmd)?;
     }
-    writeln!(f, "cd {:?}", self.workdir)?;
-    write!(f, "{:?}", self.cmd)?;
     for arg in self.args {
       write!(f, " \\\n\t{arg:?}")?;
     }
@@ -51,13 +128,15 @@ impl<S: AsRef<str>> fmt::Display for CmdDisplay<'_, S> {
   }
 }
 
+/// The field and the config files that define it, both are shared with the
+/// inherited configs, and only copied when written
 #[derive(Default, Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 #[serde(transparent)]
 pub struct Source<T> {
   #[serde(skip)]
-  source: Vec<String>,
-  inner: T,
+  source: Arc<Vec<String>>,
+  inner: Arc<T>,
 }
 struct SourceDislay<'a>(&'a Vec<String>);
 impl fmt::Display for SourceDislay<'_> {
@@ -78,7 +157,7 @@ impl<T> Source<T> {
   }
   fn add_source<P: AsRef<Path>>(&mut self, p: P, debug: bool) {
     if debug {
-      self.source.push(Self::fmt_source(p));
+      Arc::make_mut(&mut self.source).push(Self::fmt_source(p));
     }
   }
 }
@@ -86,15 +165,15 @@ impl<T, P: AsRef<Path>> From<(T, P, bool)> for Source<T> {
   #[inline]
   fn from(value: (T, P, bool)) -> Self {
     Self {
-      source: if value.2 { vec![Self::fmt_source(value.1)] } else { vec![] },
-      inner: value.0,
+      source: Arc::new(if value.2 { vec![Self::fmt_source(value.1)] } else { vec![] }),
+      inner: Arc::new(value.0),
     }
   }
 }
 impl<T> From<T> for Source<T> {
   #[inline]
   fn from(inner: T) -> Self {
-    Self { source: vec![], inner }
+    Self { source: Arc::default(), inner: Arc::new(inner) }
   }
 }
 
@@ -106,10 +185,10 @@ impl<T> Deref for Source<T> {
   }
 }
 
-impl<T> DerefMut for Source<T> {
+impl<T: Clone> DerefMut for Source<T> {
   #[inline]
   fn deref_mut(&mut self) -> &mut Self::Target {
-    &mut self.inner
+    Arc::make_mut(&mut self.inner)
   }
 }
 #[derive(Debug, Default, Deserialize, Serialize, Clone)]
@@ -119,6 +198,72 @@ pub(crate) struct PrePostProcess {
   args: Option<Vec<String>>,
   workdir: Option<String>,
 }
+impl PrePostProcess {
+  /// The process runs in the task's workdir, unless its `workdir` is set
+  fn display<'s>(&'s self, workdir: &'s Path) -> CmdDisplay<'s, String> {
+    CmdDisplay {
+      cmd: &self.cmd,
+      args: self.args.as_ref().map_or(&[], Vec::as_slice),
+      workdir: self.workdir.as_ref().map_or(workdir, |workdir| Path::new(workdir)),
+      envs: None,
+      cleared: None,
+      unset: &[],
+    }
+  }
+  /// Run the directory-level golden generator, whose default workdir is `dir/__golden__`
+  pub(crate) async fn generate_golden(
+    mut self,
+    dir: &Path,
+    args: &'static Args,
+  ) -> Result<(), BuildError> {
+    let eval_str =
+      |s: &mut String| *s = s.replace("{{rootdir}}", args.rootdir_abs.to_str().unwrap());
+    eval_str(&mut self.cmd);
+    let args = self.args.unwrap_or_default().into_iter().map(|mut arg| {
+      eval_str(&mut arg);
+      arg
+    });
+    let args: Vec<_> = args.collect();
+    let workdir = match self.workdir {
+      Some(mut workdir) => {
+        eval_str(&mut workdir);
+        PathBuf::from(workdir)
+      }
+      None => dir.join(GOLDEN_DIR),
+    };
+    create_dir_all(&workdir)
+      .map_err(|e| BuildError::CreateDir(workdir.to_path_buf(), e))?;
+    let envs: Option<&IndexMap<String, String>> = None;
+    let wrapper = CmdDisplay {
+      cmd: &self.cmd,
+      args: &args,
+      workdir: &workdir,
+      envs,
+      cleared: None,
+      unset: &[],
+    };
+    match Command::new(wrapper.cmd)
+      .current_dir(wrapper.workdir)
+      .args(wrapper.args)
+      .output()
+      .await
+    {
+      Err(e) => {
+        Err(BuildError::GoldenGenerator(dir.to_path_buf(), format!("{wrapper}{e}")))
+      }
+      Ok(output) if output.status.success() => Ok(()),
+      Ok(output) => Err(BuildError::GoldenGenerator(
+        dir.to_path_buf(),
+        format!(
+          "{wrapper}-- status --\n{}\n-- stdout --\n{}\n-- stderr --\n{}",
+          output.status,
+          core::str::from_utf8(&output.stdout).unwrap_or("unable to decoder stdout"),
+          core::str::from_utf8(&output.stderr).unwrap_or("unable to decoder stderr")
+        ),
+      )),
+    }
+  }
+}
 #[derive(Debug, Default, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct FullConfig {
@@ -129,20 +274,96 @@ pub(crate) struct FullConfig {
   #[serde(skip)]
   filtered: bool,
   #[serde(skip)]
+  verbose: bool,
+  #[serde(skip)]
   ignore: Source<bool>,
+  /// Only run on these platforms, e.g., `["linux", "macos"]`
+  only_on: Source<Vec<String>>,
+  /// Skip on these platforms
+  skip_on: Source<Vec<String>>,
   pub(crate) preprocess: Source<Vec<PrePostProcess>>,
   pub(crate) postprocess: Source<Vec<PrePostProcess>>,
+  /// When the postprocess runs, after the assertions
+  postprocess_on: Source<PostprocessOn>,
   print_errs: Source<bool>,
+  /// Spawn the task and its pre/postprocess with a cleared environment
+  clear_env: Source<bool>,
+  /// The process's envs still passed with `clear-env`
+  env_allowlist: Source<Vec<String>>,
+  /// The process's envs removed before applying `envs`
+  unset_envs: Source<Vec<String>>,
+  /// The envs of `env-allowlist` from the process, set before the task runs
+  #[serde(skip)]
+  inherited_envs: Option<IndexMap<String, String>>,
+  /// Stream the task's stdout/stderr to the terminal, see `--no-capture`
+  #[serde(skip)]
+  no_capture: bool,
+  /// Export `REGRESSION_NAME`, `REGRESSION_EXTENSION`, and `REGRESSION_ROOTDIR`
+  inject_env: Source<bool>,
+  /// Also export the deprecated `name`, `extension`, and `rootdir`,
+  /// and pass `epsilon` to the custom asserts
+  legacy_env: Source<bool>,
+  /// Export a unique `TMPDIR` for the task
+  tmpdir: Source<bool>,
+  /// Fail the task if it modifies the linked inputs
+  protect_inputs: Source<bool>,
   timeout: Source<u64>,
-  pub(crate) permit: Source<u32>,
+  /// Rerun the failed task at most that many times
+  retries: Source<u32>,
+  /// The milliseconds to wait before each retry
+  retry_backoff_ms: Source<u64>,
+  /// Double the backoff after each retry
+  retry_backoff_exponential: Source<bool>,
+  /// Unix only, run the task in a new process group, which is killed after it
+  process_group: Source<bool>,
+  /// The advisory file lock held by the task, shared with other processes
+  flock: Source<Option<String>>,
+  /// The seconds to wait for `flock`, default is `timeout`
+  flock_timeout: Source<Option<u64>>,
+  /// Resolved as `Count` after `eval`
+  permit: Source<Permit>,
+  /// The counts of the named resources, declared by `--resource`
+  resources: Source<IndexMap<String, u32>>,
+  /// The tasks to run before, relative to the task file
+  depends_on: Source<Vec<String>>,
+  /// The resolved `depends-on`, the same paths as the ones walked
+  #[serde(skip)]
+  pub(crate) dependencies: Vec<PathBuf>,
   cmd: Source<String>,
   args: Source<Vec<String>>,
+  /// Resolved as `Cmd` or `Enable(false)` after `eval`
+  wrapper: Source<Wrapper>,
+  shell: Source<Shell>,
+  /// The executed command line, with the wrapper
+  #[serde(skip)]
+  exec_cmd: String,
+  #[serde(skip)]
+  exec_args: Vec<String>,
   envs: Source<IndexMap<String, String>>,
+  /// The file in workdir that the task's stdout is written into
+  stdout: Source<String>,
+  /// The file in workdir that the task's stderr is written into,
+  /// or `"stdout"` to merge it into stdout
+  stderr: Source<String>,
   epsilon: Source<f32>,
+  stream_threshold: Source<u64>,
+  diff_threshold: Source<u64>,
+  diff_max_hunks: Source<usize>,
   pub(crate) extensions: Source<HashSet<String>>,
-  /// In default, only link all `{{name}}*` files into workdir.
+  /// Only in `xx/__all__.toml`, run once to populate its `__golden__`
+  #[serde(skip)]
+  pub(crate) golden_generator: Option<PrePostProcess>,
+  /// In default, only link all `{{name}}` and `{{name}}.*` files into workdir.
   /// Use it to specify extern files.
   extern_files: Source<Vec<String>>,
+  /// Glob patterns of extra files in the task's dir, to link into workdir
+  inputs: Source<Vec<String>>,
+  /// Glob patterns of files in workdir, whose tails are appended to the report
+  report_files: Source<Vec<String>>,
+  /// The last lines of each `report-files`
+  report_tail: Source<usize>,
+  /// The max bytes of the report, the rest is truncated
+  max_report_bytes: Source<usize>,
   assert: Source<Assert>,
 }
 
@@ -150,21 +371,132 @@ pub(crate) struct FullConfig {
 #[serde(rename_all = "kebab-case")]
 struct Config {
   ignore: Option<bool>,
+  only_on: Option<Vec<String>>,
+  skip_on: Option<Vec<String>>,
   print_errs: Option<bool>,
-  permit: Option<u32>,
+  clear_env: Option<bool>,
+  env_allowlist: Option<Vec<String>>,
+  unset_envs: Option<Vec<String>>,
+  inject_env: Option<bool>,
+  legacy_env: Option<bool>,
+  tmpdir: Option<bool>,
+  protect_inputs: Option<bool>,
+  permit: Option<Permit>,
+  resources: Option<IndexMap<String, u32>>,
+  depends_on: Option<Vec<String>>,
   timeout: Option<u64>,
+  retries: Option<u32>,
+  retry_backoff_ms: Option<i64>,
+  retry_backoff_exponential: Option<bool>,
+  process_group: Option<bool>,
+  flock: Option<String>,
+  flock_timeout: Option<u64>,
   cmd: Option<String>,
+  wrapper: Option<Wrapper>,
+  shell: Option<Shell>,
   preprocess: Option<Vec<PrePostProcess>>,
   postprocess: Option<Vec<PrePostProcess>>,
+  postprocess_on: Option<PostprocessOn>,
   extensions: Option<HashSet<String>>,
+  golden_generator: Option<PrePostProcess>,
   epsilon: Option<f32>,
+  stream_threshold: Option<u64>,
+  diff_threshold: Option<u64>,
+  diff_max_hunks: Option<usize>,
   args: Option<Vec<String>>,
   envs: Option<IndexMap<String, String>>,
+  stdout: Option<String>,
+  stderr: Option<String>,
   extern_files: Option<Vec<String>>,
+  inputs: Option<Vec<String>>,
+  report_files: Option<Vec<String>>,
+  report_tail: Option<usize>,
+  max_report_bytes: Option<usize>,
   extend: Option<Extend>,
   assert: Option<Assert>,
 }
 
+/// `wrapper = "valgrind --error-exitcode=99"` or `wrapper = false`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub(crate) enum Wrapper {
+  Enable(bool),
+  Cmd(String),
+}
+
+impl Default for Wrapper {
+  fn default() -> Self {
+    Self::Enable(true)
+  }
+}
+
+/// `permit = 2` or `permit = "50%"` of the total permits
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub(crate) enum Permit {
+  Count(u32),
+  Percent(String),
+}
+
+impl Default for Permit {
+  fn default() -> Self {
+    Self::Count(0)
+  }
+}
+
+impl Permit {
+  /// Resolve the percentage against the total permits, round up
+  fn resolve(&self, file: &Path, permits: u32) -> Result<u32, BuildError> {
+    match self {
+      Self::Count(count) => Ok(*count),
+      Self::Percent(s) => {
+        let err = || BuildError::InvalidPermit(file.to_path_buf(), s.clone());
+        let percent = s
+          .trim()
+          .strip_suffix('%')
+          .and_then(|percent| percent.trim_end().parse::<f64>().ok())
+          .filter(|percent| (0.0..=100.0).contains(percent))
+          .ok_or_else(err)?;
+        Ok((f64::from(permits) * percent / 100.0).ceil() as u32)
+      }
+    }
+  }
+}
+
+/// `shell = true` (i.e., `sh -c`) or `shell = "bash -c"`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub(crate) enum Shell {
+  Enable(bool),
+  Cmd(String),
+}
+
+impl Default for Shell {
+  fn default() -> Self {
+    Self::Enable(false)
+  }
+}
+
+/// `postprocess-on = "always"`, `"pass"`, or `"fail"`
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum PostprocessOn {
+  #[default]
+  Always,
+  Pass,
+  Fail,
+}
+
+impl PostprocessOn {
+  const fn runs(self, passed: bool) -> bool {
+    match self {
+      Self::Always => true,
+      Self::Pass => passed,
+      Self::Fail => !passed,
+    }
+  }
+}
+
 impl FullConfig {
   pub(crate) fn new_filtered() -> Self {
     Self { filtered: true, ..Default::default() }
@@ -173,13 +505,43 @@ impl FullConfig {
     Self {
       cmd: args.cmd.clone().into(),
       print_errs: args.print_errs.into(),
+      clear_env: args.clear_env.into(),
+      protect_inputs: args.protect_inputs.into(),
+      process_group: args.process_group.into(),
+      env_allowlist: ENV_ALLOWLIST.map(str::to_owned).to_vec().into(),
+      inject_env: true.into(),
       timeout: args.timeout.into(),
-      epsilon: 1e-10.into(),
+      epsilon: args.epsilon.unwrap_or(EPSILON).into(),
+      stream_threshold: STREAM_THRESHOLD.into(),
+      diff_threshold: DIFF_THRESHOLD.into(),
+      diff_max_hunks: DIFF_MAX_HUNKS.into(),
+      report_tail: REPORT_TAIL.into(),
+      max_report_bytes: args.max_report_bytes.into(),
+      permit: Permit::Count(args.permit_default).into(),
+      stdout: "{{name}}.stdout".to_owned().into(),
+      stderr: "{{name}}.stderr".to_owned().into(),
+      verbose: args.verbose,
       args: args.args.clone().into(),
       extensions: args.extensions.iter().cloned().collect::<HashSet<_>>().into(),
       ..Default::default()
     }
   }
+  /// The filtered or ignored task, which has no workdir
+  pub(crate) fn skipped(&self) -> bool {
+    self.filtered || *self.ignore
+  }
+  /// Stream the task's stdout/stderr to the terminal, and skip the goldens
+  /// reading them, return the count of the skipped goldens
+  pub(crate) fn set_no_capture(&mut self) -> usize {
+    self.no_capture = true;
+    let Some(goldens) = self.assert.golden.as_mut() else {
+      return 0;
+    };
+    let len = goldens.len();
+    let captured = [&*self.stdout, &*self.stderr];
+    goldens.retain(|golden| !captured.iter().any(|file| golden.reads(file)));
+    len - goldens.len()
+  }
   pub(crate) fn match_extension(&self, file: &Path) -> bool {
     file
       .extension()
@@ -187,13 +549,44 @@ impl FullConfig {
       .and_then(|s| self.extensions.get(s))
       .is_some()
   }
-  fn check(&self, file: &Path, args: &'static Args) -> Result<(), BuildError> {
-    if *self.permit > args.permits {
-      return Err(BuildError::PermitEcxceed(
-        file.to_path_buf(),
-        *self.permit,
-        args.permits,
-      ));
+  /// The resolved permit
+  pub(crate) fn permit(&self) -> u32 {
+    match *self.permit {
+      Permit::Count(count) => count,
+      Permit::Percent(_) => unreachable!("permit is resolved in eval"),
+    }
+  }
+  /// The reason to skip the task on the platform, by `only-on` and `skip-on`
+  fn platform_mismatch(&self, os: &str) -> Option<String> {
+    let mismatch = (!self.only_on.is_empty() && !self.only_on.iter().any(|s| s == os))
+      || self.skip_on.iter().any(|s| s == os);
+    mismatch.then(|| format!("platform {os}"))
+  }
+  /// The requested count of the named resource
+  pub(crate) fn resource(&self, name: &str) -> Option<u32> {
+    self.resources.get(name).copied()
+  }
+  fn check(&mut self, file: &Path, args: &'static Args) -> Result<(), BuildError> {
+    let permit = self.permit.resolve(file, args.permits)?;
+    *self.permit = Permit::Count(permit);
+    if permit > args.permits {
+      return Err(BuildError::PermitEcxceed(file.to_path_buf(), permit, args.permits));
+    }
+    for (name, &count) in self.resources.iter() {
+      match args.resources.get(name) {
+        Some(&total) if count > total => {
+          return Err(BuildError::ResourceExceed(
+            file.to_path_buf(),
+            name.clone(),
+            count,
+            total,
+          ));
+        }
+        Some(_) => {}
+        None => {
+          return Err(BuildError::UnknownResource(file.to_path_buf(), name.clone()));
+        }
+      }
     }
     if self.cmd.is_empty() {
       return Err(BuildError::MissConfig(file.to_path_buf(), "cmd"));
@@ -212,13 +605,61 @@ impl FullConfig {
     self.extension = file.extension().unwrap().to_str().unwrap().to_owned();
     let name = file.with_extension("");
     self.name = name.file_name().unwrap().to_str().unwrap().to_owned();
-    let eval_str = |s: &mut String| -> Result<(), BuildError> {
+    // the envs from `--env-file` have the lowest precedence
+    if !args.file_envs.is_empty() {
+      let mut envs = args.file_envs.clone();
+      envs.extend(core::mem::take(&mut *self.envs));
+      *self.envs = envs;
+    }
+    // expand the `${VAR}` in envs, so that `file_envs` holds their final values
+    let file_envs = resolve_envs(file, &self.envs)?;
+    let unique = args.relative_path(file).replace(['/', '\\'], "-");
+    let seed = task_seed(file, args).to_string();
+    let relative_dir = Path::new(args.relative_path(file)).parent().unwrap();
+    self.dependencies = self
+      .depends_on
+      .iter()
+      .map(|dep| args.rootdir.join(normalize(&relative_dir.join(dep))))
+      .collect();
+    // the names and the absolute workdirs, for `{{dep.<name>.workdir}}`
+    let deps = self
+      .dependencies
+      .iter()
+      .map(|dep| {
+        let name = dep.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        let workdir = args.task_workdir(dep);
+        let workdir = std::path::absolute(&workdir).unwrap_or(workdir);
+        (name, workdir.display().to_string())
+      })
+      .collect::<Vec<_>>();
+    let eval_tmpl = |s: &mut String| -> Result<(), BuildError> {
       *s = s.replace("{{extension}}", &self.extension);
       *s = s.replace("{{name}}", &self.name);
+      *s = s.replace("{{unique}}", &unique);
+      *s = s.replace("{{seed}}", &seed);
       *s = s.replace("{{rootdir}}", args.rootdir_abs.to_str().unwrap());
-      Ok(())
+      eval_deps(s, file, &deps)?;
+      eval_envs(s, file, &file_envs)?;
+      eval_bins(s, file, args)
+    };
+    // `${VAR}` goes first, so the values of `{{...}}` are never expanded again
+    let eval_str = |s: &mut String| -> Result<(), BuildError> {
+      eval_vars(s, |key| match file_envs.get(key) {
+        Some(value) => Ok(value.clone()),
+        None => process_var(file, key),
+      })?;
+      eval_tmpl(s)
     };
     eval_str(&mut self.cmd)?;
+    if let Wrapper::Enable(true) = *self.wrapper {
+      *self.wrapper = args.wrapper.clone().map_or(Wrapper::Enable(false), Wrapper::Cmd);
+    }
+    if let Wrapper::Cmd(wrapper) = &mut *self.wrapper {
+      eval_str(wrapper)?;
+    }
+    if let Shell::Cmd(shell) = &mut *self.shell {
+      eval_str(shell)?;
+    }
     for preprocess in self.preprocess.iter_mut() {
       eval_str(&mut preprocess.cmd)?;
       if let Some(args) = preprocess.args.as_mut() {
@@ -247,24 +688,61 @@ impl FullConfig {
     for extern_file in self.extern_files.iter_mut() {
       eval_str(extern_file)?;
     }
-    for v in self.envs.values_mut() {
-      eval_str(v)?;
+    for input in self.inputs.iter_mut() {
+      eval_str(input)?;
+    }
+    for report_file in self.report_files.iter_mut() {
+      eval_str(report_file)?;
+    }
+    if let Some(flock) = self.flock.as_mut() {
+      eval_str(flock)?;
+    }
+    eval_str(&mut self.stdout)?;
+    eval_str(&mut self.stderr)?;
+    for (k, v) in self.envs.iter_mut() {
+      v.clone_from(&file_envs[k]);
+      eval_tmpl(v)?;
+    }
+    if *self.inject_env {
+      let injected = [
+        ("REGRESSION_NAME", "name", self.name.clone()),
+        ("REGRESSION_EXTENSION", "extension", self.extension.clone()),
+        ("REGRESSION_ROOTDIR", "rootdir", args.rootdir_abs.display().to_string()),
+      ];
+      for (key, legacy_key, value) in injected {
+        if *self.legacy_env {
+          self.envs.entry(legacy_key.to_owned()).insert_entry(value.clone());
+        }
+        self.envs.entry(key.to_owned()).insert_entry(value);
+      }
     }
-    self.envs.entry("name".to_owned()).insert_entry(self.name.clone());
-    self
-      .envs
-      .entry("extension".to_owned())
-      .insert_entry(self.extension.clone());
     self
       .envs
-      .entry("rootdir".to_owned())
-      .insert_entry(args.rootdir_abs.display().to_string());
+      .entry("REGRESSION_SEED".to_owned())
+      .insert_entry(seed.clone());
     if let Some(goldens) = self.assert.golden.as_deref_mut() {
       for golden in goldens.iter_mut() {
+        if golden.file.is_empty() && golden.files.is_empty() {
+          return Err(BuildError::MissConfig(file.to_path_buf(), "assert.golden.file"));
+        }
         eval_str(&mut golden.file)?;
+        for f in golden.files.iter_mut() {
+          eval_str(f)?;
+        }
+        if let Some(same_as) = golden.same_as.as_mut() {
+          eval_str(same_as)?;
+        }
+        if let Some(values) = golden.value.as_mut() {
+          for value_file in values.iter_mut().filter_map(|v| v.value_file.as_mut()) {
+            eval_str(value_file)?;
+          }
+        }
         if let Some(customs) = golden.custom.as_mut() {
           for custom in customs {
             eval_str(&mut custom.cmd)?;
+            if let Some(workdir) = custom.workdir.as_mut() {
+              eval_str(workdir)?;
+            }
             if let Some(envs) = custom.envs.as_mut() {
               for v in envs.values_mut() {
                 eval_str(v)?;
@@ -274,6 +752,41 @@ impl FullConfig {
         }
       }
     }
+    let stderr = (*self.stderr != "stdout" && *self.stderr != *self.stdout)
+      .then_some(self.stderr.as_str());
+    self
+      .assert
+      .desugar(&self.stdout, stderr)
+      .map_err(|e| BuildError::AssertSugar(file.to_path_buf(), e))?;
+    let split = |s: &str, key| -> Result<(String, Vec<String>), BuildError> {
+      let mut iter = s.split_whitespace().map(str::to_owned);
+      let cmd = iter.next().ok_or(BuildError::MissConfig(file.to_path_buf(), key))?;
+      Ok((cmd, iter.collect()))
+    };
+    let (cmd, args) = match &*self.shell {
+      Shell::Enable(false) => (String::clone(&self.cmd), Vec::clone(&self.args)),
+      shell => {
+        let (shell_cmd, mut shell_args) =
+          split(if let Shell::Cmd(shell) = shell { shell } else { "sh -c" }, "shell")?;
+        // the command line is interpreted by the shell, without quoting
+        let mut line = String::clone(&self.cmd);
+        for arg in self.args.iter() {
+          line.push(' ');
+          line.push_str(arg);
+        }
+        shell_args.push(line);
+        (shell_cmd, shell_args)
+      }
+    };
+    (self.exec_cmd, self.exec_args) = match &*self.wrapper {
+      Wrapper::Cmd(wrapper) => {
+        let (wrapper_cmd, mut wrapper_args) = split(wrapper, "wrapper")?;
+        wrapper_args.push(cmd);
+        wrapper_args.extend(args);
+        (wrapper_cmd, wrapper_args)
+      }
+      Wrapper::Enable(_) => (cmd, args),
+    };
     Ok(self)
   }
   #[inline]
@@ -292,36 +805,136 @@ impl FullConfig {
     if let Some(postprocess) = config.postprocess {
       self.postprocess = (postprocess, config_path, debug).into();
     }
+    if let Some(postprocess_on) = config.postprocess_on {
+      self.postprocess_on = (postprocess_on, config_path, debug).into();
+    }
     if let Some(ignore) = config.ignore {
       self.ignore = (ignore, config_path, debug).into();
     }
+    if let Some(only_on) = config.only_on {
+      self.only_on = (only_on, config_path, debug).into();
+    }
+    if let Some(skip_on) = config.skip_on {
+      self.skip_on = (skip_on, config_path, debug).into();
+    }
     if let Some(print_errs) = config.print_errs {
       self.print_errs = (print_errs, config_path, debug).into();
     }
+    if let Some(clear_env) = config.clear_env {
+      self.clear_env = (clear_env, config_path, debug).into();
+    }
+    if let Some(env_allowlist) = config.env_allowlist {
+      self.env_allowlist = (env_allowlist, config_path, debug).into();
+    }
+    if let Some(unset_envs) = config.unset_envs {
+      self.unset_envs = (unset_envs, config_path, debug).into();
+    }
+    if let Some(inject_env) = config.inject_env {
+      self.inject_env = (inject_env, config_path, debug).into();
+    }
+    if let Some(legacy_env) = config.legacy_env {
+      self.legacy_env = (legacy_env, config_path, debug).into();
+    }
+    if let Some(tmpdir) = config.tmpdir {
+      self.tmpdir = (tmpdir, config_path, debug).into();
+    }
+    if let Some(protect_inputs) = config.protect_inputs {
+      self.protect_inputs = (protect_inputs, config_path, debug).into();
+    }
     if let Some(epsilon) = config.epsilon {
       self.epsilon = (epsilon, config_path, debug).into();
     }
+    if let Some(stream_threshold) = config.stream_threshold {
+      self.stream_threshold = (stream_threshold, config_path, debug).into();
+    }
+    if let Some(diff_threshold) = config.diff_threshold {
+      self.diff_threshold = (diff_threshold, config_path, debug).into();
+    }
+    if let Some(diff_max_hunks) = config.diff_max_hunks {
+      self.diff_max_hunks = (diff_max_hunks, config_path, debug).into();
+    }
     if let Some(extensions) = config.extensions {
+      if extensions.contains("toml") {
+        return Err(BuildError::InputExtToml);
+      }
       self.extensions = (extensions, config_path, debug).into();
     }
+    // not inherited, it will be taken by `walk`
+    if config.golden_generator.is_some()
+      && config_path.file_name() != Some("__all__.toml".as_ref())
+    {
+      return Err(BuildError::GoldenGeneratorNotAll(config_path.to_path_buf()));
+    }
+    self.golden_generator = config.golden_generator;
     if let Some(permit) = config.permit {
       self.permit = (permit, config_path, debug).into();
     }
+    if let Some(resources) = config.resources {
+      self.resources = (resources, config_path, debug).into();
+    }
+    if let Some(depends_on) = config.depends_on {
+      self.depends_on = (depends_on, config_path, debug).into();
+    }
     if let Some(timeout) = config.timeout {
       self.timeout = (timeout, config_path, debug).into();
     }
+    if let Some(retries) = config.retries {
+      self.retries = (retries, config_path, debug).into();
+    }
+    if let Some(retry_backoff_ms) = config.retry_backoff_ms {
+      let retry_backoff_ms = u64::try_from(retry_backoff_ms).map_err(|_| {
+        BuildError::NegativeBackoff(config_path.to_path_buf(), retry_backoff_ms)
+      })?;
+      self.retry_backoff_ms = (retry_backoff_ms, config_path, debug).into();
+    }
+    if let Some(exponential) = config.retry_backoff_exponential {
+      self.retry_backoff_exponential = (exponential, config_path, debug).into();
+    }
+    if let Some(process_group) = config.process_group {
+      self.process_group = (process_group, config_path, debug).into();
+    }
+    if let Some(flock) = config.flock {
+      self.flock = (Some(flock), config_path, debug).into();
+    }
+    if let Some(flock_timeout) = config.flock_timeout {
+      self.flock_timeout = (Some(flock_timeout), config_path, debug).into();
+    }
     if let Some(cmd) = config.cmd {
       self.cmd = (cmd, config_path, debug).into();
     }
+    if let Some(wrapper) = config.wrapper {
+      self.wrapper = (wrapper, config_path, debug).into();
+    }
+    if let Some(shell) = config.shell {
+      self.shell = (shell, config_path, debug).into();
+    }
     if let Some(args) = config.args {
       self.args = (args, config_path, debug).into();
     }
     if let Some(envs) = config.envs {
       self.envs = (envs, config_path, debug).into();
     }
+    if let Some(stdout) = config.stdout {
+      self.stdout = (stdout, config_path, debug).into();
+    }
+    if let Some(stderr) = config.stderr {
+      self.stderr = (stderr, config_path, debug).into();
+    }
     if let Some(extern_files) = config.extern_files {
       self.extern_files = (extern_files, config_path, debug).into();
     }
+    if let Some(inputs) = config.inputs {
+      self.inputs = (inputs, config_path, debug).into();
+    }
+    if let Some(report_files) = config.report_files {
+      self.report_files = (report_files, config_path, debug).into();
+    }
+    if let Some(report_tail) = config.report_tail {
+      self.report_tail = (report_tail, config_path, debug).into();
+    }
+    if let Some(max_report_bytes) = config.max_report_bytes {
+      self.max_report_bytes = (max_report_bytes, config_path, debug).into();
+    }
     if let Some(assert) = config.assert {
       self.assert = (assert, config_path, debug).into();
     }
@@ -338,78 +951,240 @@ impl FullConfig {
         self.extern_files.extend(extern_files);
         self.extern_files.add_source(config_path, debug);
       }
+      if let Some(extensions) = extend.extensions {
+        if extensions.contains("toml") {
+          return Err(BuildError::InputExtToml);
+        }
+        self.extensions.extend(extensions);
+        self.extensions.add_source(config_path, debug);
+      }
     }
     Ok(self)
   }
 }
 
+/// The merged config of a task, dumped by `--dump-config`
+#[derive(Serialize)]
+struct DumpConfig<'a> {
+  path: &'a Path,
+  ignore: bool,
+  config: &'a FullConfig,
+  /// The config files that define or extend each field, empty with `--nodebug`
+  #[serde(skip_serializing_if = "IndexMap::is_empty")]
+  sources: IndexMap<&'static str, &'a [String]>,
+}
+
+impl FullConfig {
+  fn sources(&self) -> IndexMap<&'static str, &[String]> {
+    [
+      ("ignore", &self.ignore.source),
+      ("only-on", &self.only_on.source),
+      ("skip-on", &self.skip_on.source),
+      ("preprocess", &self.preprocess.source),
+      ("postprocess", &self.postprocess.source),
+      ("postprocess-on", &self.postprocess_on.source),
+      ("print-errs", &self.print_errs.source),
+      ("clear-env", &self.clear_env.source),
+      ("env-allowlist", &self.env_allowlist.source),
+      ("unset-envs", &self.unset_envs.source),
+      ("inject-env", &self.inject_env.source),
+      ("legacy-env", &self.legacy_env.source),
+      ("tmpdir", &self.tmpdir.source),
+      ("protect-inputs", &self.protect_inputs.source),
+      ("timeout", &self.timeout.source),
+      ("retries", &self.retries.source),
+      ("retry-backoff-ms", &self.retry_backoff_ms.source),
+      ("retry-backoff-exponential", &self.retry_backoff_exponential.source),
+      ("process-group", &self.process_group.source),
+      ("flock", &self.flock.source),
+      ("flock-timeout", &self.flock_timeout.source),
+      ("permit", &self.permit.source),
+      ("resources", &self.resources.source),
+      ("depends-on", &self.depends_on.source),
+      ("cmd", &self.cmd.source),
+      ("args", &self.args.source),
+      ("wrapper", &self.wrapper.source),
+      ("shell", &self.shell.source),
+      ("envs", &self.envs.source),
+      ("stdout", &self.stdout.source),
+      ("stderr", &self.stderr.source),
+      ("epsilon", &self.epsilon.source),
+      ("stream-threshold", &self.stream_threshold.source),
+      ("diff-threshold", &self.diff_threshold.source),
+      ("diff-max-hunks", &self.diff_max_hunks.source),
+      ("extensions", &self.extensions.source),
+      ("extern-files", &self.extern_files.source),
+      ("inputs", &self.inputs.source),
+      ("report-files", &self.report_files.source),
+      ("report-tail", &self.report_tail.source),
+      ("max-report-bytes", &self.max_report_bytes.source),
+      ("assert", &self.assert.source),
+    ]
+    .into_iter()
+    .filter(|(_, source)| !source.is_empty())
+    .map(|(name, source)| (name, source.as_slice()))
+    .collect()
+  }
+}
+
+/// Write the merged configs of the discovered tasks as a JSON array,
+/// the filtered tasks are skipped
+pub(crate) fn dump_configs(
+  file: &Path,
+  file_configs: &[(PathBuf, FullConfig)],
+) -> Result<(), BuildError> {
+  let dumps: Vec<_> = file_configs
+    .iter()
+    .filter(|(_, config)| !config.filtered)
+    .map(|(path, config)| DumpConfig {
+      path,
+      ignore: *config.ignore,
+      config,
+      sources: config.sources(),
+    })
+    .collect();
+  serde_json::to_string_pretty(&dumps)
+    .map_err(io::Error::other)
+    .and_then(|json| std::fs::write(file, json))
+    .map_err(|e| BuildError::Write(file.to_path_buf(), e))
+}
+
 impl FullConfig {
   #[inline]
-  pub(crate) async fn test(self, path: &Path, args: &'static Args) -> State {
+  pub(crate) async fn test(
+    mut self,
+    path: &Path,
+    args: &'static Args,
+    io_scheduler: Option<&Semaphore>,
+  ) -> State {
     if self.filtered {
       return State::FilteredOut;
     }
     if *self.ignore {
       return State::Ignored;
     }
+    if let Some(reason) = self.platform_mismatch(std::env::consts::OS) {
+      return State::Skipped(reason);
+    }
     let print_errs = *self.print_errs;
-    let rootdir = path.parent().unwrap();
-    let path_str = path.to_str().unwrap();
-    let workdir = args.workdir.join(
-      // remove the root of rootdir
-      {
-        let rootdir = args.rootdir.to_str().unwrap();
-        if path_str.starts_with(rootdir) {
-          let end_with_slash = rootdir.ends_with(if cfg!(windows) { '\\' } else { '/' });
-          &path_str[rootdir.len() + if end_with_slash { 0 } else { 1 }..]
-        } else {
-          path_str
-        }
-      },
-    );
+    let report_files = core::mem::take(&mut *self.report_files);
+    let report_tail = *self.report_tail;
+    let max_report_bytes = *self.max_report_bytes;
+    let workdir = args.task_workdir(path);
     let now = Instant::now();
     let name = self.name.clone();
-    let mut errs = if let Err(e) = self.prepare_dir(rootdir, &workdir).await {
-      vec![e]
-    } else {
-      let toml_str = if args.nodebug { String::new() } else { self.to_toml() };
-      let debug_config = workdir.join(format!("__debug__.{name}.toml"));
-      let time_secs = self.timeout.inner;
-      let task_future =
-        timeout(Duration::from_secs(time_secs), self.assert(rootdir, workdir.clone()));
-      let debug_future = async {
-        if args.nodebug {
-          Ok(())
-        } else {
-          tokio::fs::write(&debug_config, toml_str)
-            .await
-            .map_err(|e| AssertError::Write(debug_config.display().to_string(), e))
-        }
-      };
-      let (dbg_res, task_res) = tokio::join!(debug_future, task_future);
-      let mut errs = task_res.unwrap_or(vec![AssertError::TimeOut(time_secs)]);
-      if let Err(e) = dbg_res {
-        errs.push(e);
+    let verbose = self.verbose;
+    let retries = *self.retries;
+    let mut retried = 0;
+    // the elapsed time includes the failed attempts and the backoffs
+    let errs = loop {
+      if retried == retries {
+        break self.attempt(path, &workdir, args, io_scheduler).await;
       }
-      errs
+      let errs = self.clone().attempt(path, &workdir, args, io_scheduler).await;
+      if errs.iter().all(AssertError::is_soft) {
+        break errs;
+      }
+      retried += 1;
+      tokio::time::sleep(self.retry_backoff(retried)).await;
     };
+    if verbose && retried != 0 {
+      eprintln!("note: {} retried {retried} time(s)", path.display());
+    }
     if errs.is_empty() {
       State::Ok(Some(now.elapsed()))
     } else {
-      let failed_state = if print_errs {
-        FailedState::NoReport(path.to_path_buf(), errs)
+      let warned = errs.iter().all(AssertError::is_soft);
+      let tails = report_tails(&report_files, report_tail, &workdir).await;
+      let failed_state =
+        failed_state(path, &workdir, &name, errs, tails, print_errs, max_report_bytes)
+          .await;
+      if warned {
+        State::Warned(failed_state, now.elapsed())
       } else {
-        let err_report = workdir.join(format!("{name}.report"));
-        match tokio::fs::write(&err_report, DisplayErrs(&errs).to_string()).await {
-          Ok(_) => FailedState::ReportSaved(err_report),
-          Err(e) => FailedState::NoReport(path.to_path_buf(), {
-            errs.push(AssertError::Write(err_report.display().to_string(), e));
-            errs
-          }),
+        State::Failed(Some((failed_state, now.elapsed())))
+      }
+    }
+  }
+  /// The backoff before the n-th (from 1) retry
+  fn retry_backoff(&self, retried: u32) -> Duration {
+    let mut ms = *self.retry_backoff_ms;
+    if *self.retry_backoff_exponential {
+      ms = ms.saturating_mul(2_u64.saturating_pow(retried - 1));
+    }
+    Duration::from_millis(ms)
+  }
+  /// Prepare the workdir, run the task and assert it once
+  async fn attempt(
+    mut self,
+    path: &Path,
+    workdir: &Path,
+    args: &'static Args,
+    io_scheduler: Option<&Semaphore>,
+  ) -> Vec<AssertError> {
+    let rootdir = path.parent().unwrap();
+    let relative = args.relative_path(path);
+    let name = self.name.clone();
+    let (tmpdir, prepared) = match self
+      .set_coverage(relative, args)
+      .and_then(|()| self.set_tmpdir())
+      .and_then(|tmpdir| Ok((tmpdir, self.set_ports()?)))
+    {
+      Ok((tmpdir, ports)) => {
+        self.set_inherited_envs();
+        (tmpdir, self.prepare_dir(rootdir, workdir, args, io_scheduler, ports).await)
+      }
+      Err(e) => (None, Err(e)),
+    };
+    let mut errs = match prepared {
+      Err(e) => vec![e],
+      Ok((flock, stamps)) => {
+        let assert_config = self.assert_config();
+        let toml_str = if args.nodebug {
+          String::new()
+        } else {
+          let layout = args.workdir_layout.to_possible_value().expect("not skipped");
+          format!("# workdir-layout = \"{}\"\n{}", layout.get_name(), self.to_toml())
+        };
+        let cmd_str =
+          if args.nodebug { String::new() } else { self.to_cmd(rootdir, workdir) };
+        let debug_config = workdir.join(format!("__debug__.{name}.toml"));
+        let debug_cmd = workdir.join(format!("__debug__.{name}.cmd"));
+        let time_secs = *self.timeout;
+        let task_future = timeout(
+          Duration::from_secs(time_secs),
+          self.assert(rootdir, workdir.to_path_buf(), args.golden_refs.as_ref()),
+        );
+        let debug_future = async {
+          if args.nodebug {
+            Ok(())
+          } else {
+            tokio::fs::write(&debug_config, toml_str)
+              .await
+              .map_err(|e| AssertError::Write(debug_config.display().to_string(), e))?;
+            tokio::fs::write(&debug_cmd, cmd_str)
+              .await
+              .map_err(|e| AssertError::Write(debug_cmd.display().to_string(), e))
+          }
+        };
+        let (dbg_res, task_res) = tokio::join!(debug_future, task_future);
+        // release the lock once the task finished
+        drop(flock);
+        let mut errs = task_res.unwrap_or(vec![AssertError::TimeOut(time_secs)]);
+        if let Err(e) = dbg_res {
+          errs.push(e);
         }
-      };
-      State::Failed(Some((failed_state, now.elapsed())))
+        // after the task and its postprocess, even when timed out
+        errs.extend(stamps.iter().filter_map(|stamp| stamp.check(assert_config)));
+        errs
+      }
+    };
+    if let Some(tmpdir) = tmpdir
+      && let Err(e) = tokio::fs::remove_dir_all(&tmpdir).await
+    {
+      errs.push(AssertError::UnableToDeleteDir(tmpdir.display().to_string(), e));
     }
+    errs
   }
   #[inline]
   fn to_toml(&self) -> String {
@@ -439,50 +1214,72 @@ impl FullConfig {
       })
       .unwrap_or_default()
   }
+  /// The resolved command lines and the asserted files, for `__debug__.{name}.cmd`
+  fn to_cmd(&self, rootdir: &Path, workdir: &Path) -> String {
+    let mut s = format!("==== COMMAND ===\n{}", self.exec_display(workdir));
+    for (label, processes) in
+      [("PREPROCESS", &self.preprocess), ("POSTPROCESS", &self.postprocess)]
+    {
+      for (n, process) in processes.iter().enumerate() {
+        s.push_str(&format!("==== {label} {} ===\n{}", n + 1, process.display(workdir)));
+      }
+    }
+    if let Some(goldens) = self.assert.golden.as_deref()
+      && !goldens.is_empty()
+    {
+      s.push_str("==== GOLDEN ===\n");
+      for golden in goldens {
+        s.push_str(&golden.targets(workdir, &rootdir.join(GOLDEN_DIR)));
+      }
+    }
+    s
+  }
   #[inline]
+  /// Execute the pre/postprocess steps in order, the outputs and the exit status
+  /// of the n-th (from 1) step are always written into the workdir, e.g.,
+  /// `__debug__.preprocess.1.{stdout,stderr,status}`, which can be asserted
   async fn exec_process(
     &self,
     workdir: &Path,
     is_preprocess: bool,
+    extra_envs: &[(&str, String)],
   ) -> Result<(), AssertError> {
-    let (processes, log_file_name) = if is_preprocess {
-      (&self.preprocess, "__debug__.preprocess.log")
+    let (processes, label) = if is_preprocess {
+      (&self.preprocess, "preprocess")
     } else {
-      (&self.postprocess, "__debug__.postprocess.log")
+      (&self.postprocess, "postprocess")
     };
-    if processes.is_empty() {
-      return Ok(());
-    }
-    let log_file = workdir.join(log_file_name);
-    let out_file = std::fs::File::create(&log_file)
-      .map_err(|e| AssertError::UnableToCreateDir(log_file.display().to_string(), e))?;
-    let mut writer = std::io::BufWriter::new(out_file);
-    // exec all prepares
-    for process in processes.iter() {
-      let envs: Option<&IndexMap<String, String>> = None;
-      let wrapper = CmdDisplay {
-        cmd: &process.cmd,
-        args: process.args.as_ref().map_or(&[], Vec::as_slice),
-        workdir: process.workdir.as_ref().map_or(workdir, |workdir| Path::new(workdir)),
-        envs,
-      };
-      match Command::new(wrapper.cmd)
+    for (n, process) in processes.iter().enumerate() {
+      let wrapper = process.display(workdir);
+      let mut command = Command::new(wrapper.cmd);
+      if let Some(inherited_envs) = &self.inherited_envs {
+        command.env_clear().envs(inherited_envs);
+      }
+      for key in self.unset_envs.iter() {
+        command.env_remove(key);
+      }
+      match command
         .current_dir(wrapper.workdir)
         .args(wrapper.args)
         .envs(&*self.envs)
+        .envs(extra_envs.iter().map(|(k, v)| (k, v)))
         .output()
         .await
       {
         Err(e) => return Err(AssertError::ProcessExec(wrapper.to_string(), e)),
         Ok(output) => {
-          if output.status.success() {
-            writeln!(&mut writer, "[INFO] {wrapper}").unwrap();
-          } else {
-            write!(&mut writer, "[ERROR] {wrapper}\nstdout:\n").unwrap();
-            writer.write_all(&output.stdout).unwrap();
-            write!(&mut writer, "\nstderr:\n").unwrap();
-            writer.write_all(&output.stderr).unwrap();
-            writeln!(&mut writer).unwrap();
+          let status = status_str(output.status);
+          for (extension, content) in [
+            ("stdout", &output.stdout[..]),
+            ("stderr", &output.stderr[..]),
+            ("status", format!("{status}\n").as_bytes()),
+          ] {
+            let file = workdir.join(format!("__debug__.{label}.{}.{extension}", n + 1));
+            tokio::fs::write(&file, content)
+              .await
+              .map_err(|e| AssertError::Write(file.display().to_string(), e))?;
+          }
+          if !output.status.success() {
             return Err(AssertError::ProcessStatus(
               format!("{wrapper}"),
               format!(
@@ -500,8 +1297,124 @@ impl FullConfig {
     }
     Ok(())
   }
+  /// Unique `LLVM_PROFILE_FILE` for each task, so that parallel tasks will not
+  /// clobber each other's `default.profraw`
+  fn set_coverage(
+    &mut self,
+    relative: &str,
+    args: &'static Args,
+  ) -> Result<(), AssertError> {
+    if let Some(coverage_dir) = &args.coverage_dir {
+      let profile_file = profile_file(coverage_dir, relative);
+      if let Some(dir) = profile_file.parent() {
+        create_dir_all(dir)
+          .map_err(|e| AssertError::UnableToCreateDir(dir.display().to_string(), e))?;
+      }
+      self
+        .envs
+        .insert("LLVM_PROFILE_FILE".into(), profile_file.display().to_string());
+    }
+    Ok(())
+  }
+  /// Create a unique temp dir and export it as `TMPDIR`/`TMP`/`TEMP`, so that
+  /// the tools writing fixed names in the system temp dir will not collide
+  fn set_tmpdir(&mut self) -> Result<Option<PathBuf>, AssertError> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    if !*self.tmpdir {
+      return Ok(None);
+    }
+    let tmpdir = std::env::temp_dir().join(format!(
+      "cargo-regression-{}-{}",
+      std::process::id(),
+      COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    create_dir_all(&tmpdir)
+      .map_err(|e| AssertError::UnableToCreateDir(tmpdir.display().to_string(), e))?;
+    for key in ["TMPDIR", "TMP", "TEMP"] {
+      self.envs.insert(key.into(), tmpdir.display().to_string());
+    }
+    Ok(Some(tmpdir))
+  }
+  /// With `clear-env`, take the process's envs in `env-allowlist`,
+  /// the ones overridden by `envs` are skipped
+  fn set_inherited_envs(&mut self) {
+    if *self.clear_env {
+      let inherited_envs = self
+        .env_allowlist
+        .iter()
+        .filter(|key| !self.envs.contains_key(*key) && !self.unset_envs.contains(key))
+        .filter_map(|key| Some((key.clone(), std::env::var(key).ok()?)))
+        .collect();
+      self.inherited_envs = Some(inherited_envs);
+    }
+  }
+  /// Reserve the ports of `{{port}}` and `{{port.<n>}}` by binding on port 0,
+  /// substitute and export them as `REGRESSION_PORT` and `REGRESSION_PORT_<n>`.
+  /// The listeners are held until the task spawns, and the ports are never
+  /// handed out twice in a run
+  fn set_ports(&mut self) -> Result<Vec<TcpListener>, AssertError> {
+    static RESERVED: Mutex<BTreeSet<u16>> = Mutex::new(BTreeSet::new());
+    let mut count = 0;
+    self.for_each_str(|s| count = count.max(port_count(s)));
+    let mut listeners = Vec::with_capacity(count);
+    while listeners.len() < count {
+      let listener =
+        TcpListener::bind(("127.0.0.1", 0)).map_err(AssertError::ReservePort)?;
+      let port = listener.local_addr().map_err(AssertError::ReservePort)?.port();
+      if RESERVED.lock().expect("reserved ports lock").insert(port) {
+        listeners.push(listener);
+      }
+    }
+    let ports: Vec<String> = listeners
+      .iter()
+      .map(|listener| Ok(listener.local_addr()?.port().to_string()))
+      .collect::<io::Result<_>>()
+      .map_err(AssertError::ReservePort)?;
+    self.for_each_str(|s| {
+      for (idx, port) in ports.iter().enumerate().rev() {
+        *s = s.replace(&format!("{{{{port.{}}}}}", idx + 1), port);
+      }
+      if let Some(port) = ports.first() {
+        *s = s.replace("{{port}}", port);
+      }
+    });
+    for (idx, port) in ports.iter().enumerate() {
+      let key = if idx == 0 {
+        "REGRESSION_PORT".to_owned()
+      } else {
+        format!("REGRESSION_PORT_{}", idx + 1)
+      };
+      self.envs.insert(key, port.clone());
+    }
+    Ok(listeners)
+  }
+  /// The executed strings that may contain the `{{port}}` variables
+  fn for_each_str(&mut self, mut f: impl FnMut(&mut String)) {
+    f(&mut self.exec_cmd);
+    self.exec_args.iter_mut().for_each(&mut f);
+    f(&mut self.cmd);
+    self.args.iter_mut().for_each(&mut f);
+    self.envs.values_mut().for_each(&mut f);
+    for process in self.preprocess.iter_mut().chain(self.postprocess.iter_mut()) {
+      f(&mut process.cmd);
+      process.args.iter_mut().flatten().for_each(&mut f);
+      process.workdir.iter_mut().for_each(&mut f);
+    }
+  }
   #[inline]
-  async fn prepare_dir(&self, rootdir: &Path, workdir: &Path) -> Result<(), AssertError> {
+  async fn prepare_dir(
+    &self,
+    rootdir: &Path,
+    workdir: &Path,
+    args: &'static Args,
+    io_scheduler: Option<&Semaphore>,
+    ports: Vec<TcpListener>,
+  ) -> Result<(Option<File>, Vec<InputStamp>), AssertError> {
+    // only the I/O below, released before the preprocess
+    let io_permit = match io_scheduler {
+      Some(io_scheduler) => Some(io_scheduler.acquire().await.expect("Semaphore closed")),
+      None => None,
+    };
     let rootdir = if rootdir.is_absolute() {
       Cow::Borrowed(rootdir)
     } else {
@@ -510,6 +1423,14 @@ impl FullConfig {
           .map_err(|e| AssertError::UnableToReadDir(rootdir.display().to_string(), e))?,
       )
     };
+    let copy = args.copy_inputs;
+    // the linked sources, the copied ones can not be modified by the task
+    let mut originals = Vec::new();
+    let mut protect = |original: &Path| {
+      if *self.protect_inputs && !copy {
+        originals.push(original.to_path_buf());
+      }
+    };
     // create
     if workdir.exists() {
       remove_dir_all(workdir)
@@ -521,115 +1442,688 @@ impl FullConfig {
     let golden_dir = rootdir.join(GOLDEN_DIR);
     if golden_dir.exists() {
       let link = workdir.join(GOLDEN_DIR);
-      std::os::unix::fs::symlink(&golden_dir, &link).map_err(|e| {
+      link_or_copy(&golden_dir, &link, copy).map_err(|e| {
         AssertError::LinkFile(
           golden_dir.display().to_string(),
           link.display().to_string(),
           e,
         )
       })?;
+      protect(&golden_dir);
     }
     // extern_file
     for extern_file in self.extern_files.iter() {
       let path = rootdir.join(extern_file);
       if path.exists() {
         let link = workdir.join(extern_file);
-        std::os::unix::fs::symlink(&path, &link).map_err(|e| {
+        // e.g., `data/config.json`
+        if let Some(parent) = link.parent() {
+          create_dir_all(parent).map_err(|e| {
+            AssertError::UnableToCreateDir(parent.display().to_string(), e)
+          })?;
+        }
+        link_or_copy(&path, &link, copy).map_err(|e| {
           AssertError::LinkFile(path.display().to_string(), link.display().to_string(), e)
         })?;
+        protect(&path);
       }
     }
+    // the links should not collide with the linked extern files
+    let mut link_file = |original: &Path, link: &Path| {
+      if link.symlink_metadata().is_ok() {
+        return Err(AssertError::LinkCollision(
+          original.display().to_string(),
+          link.display().to_string(),
+        ));
+      }
+      link_or_copy(original, link, copy).map_err(|e| {
+        AssertError::LinkFile(
+          original.display().to_string(),
+          link.display().to_string(),
+          e,
+        )
+      })?;
+      protect(original);
+      Ok(())
+    };
+    // only `{{name}}` and `{{name}}.*`
+    let name_prefix = format!("{}.", self.name);
     for entry in rootdir
       .read_dir()
       .map_err(|e| AssertError::UnableToReadDir(rootdir.display().to_string(), e))?
       .flatten()
     {
       let full_name = entry.file_name();
-      if full_name.to_str().unwrap_or("").starts_with(&self.name) {
-        let original = entry.path();
-        let link = workdir.join(full_name);
-        std::os::unix::fs::symlink(&original, &link).map_err(|e| {
-          AssertError::LinkFile(
-            original.display().to_string(),
-            link.display().to_string(),
-            e,
-          )
-        })?;
+      let full_name_str = full_name.to_str().unwrap_or("");
+      if full_name_str == self.name || full_name_str.starts_with(&name_prefix) {
+        link_file(&entry.path(), &workdir.join(full_name))?;
+      }
+    }
+    // inputs
+    for input in self.inputs.iter() {
+      let pattern =
+        format!("{}/{input}", glob::Pattern::escape(&rootdir.display().to_string()));
+      for original in glob::glob(&pattern).map_err(AssertError::PatternError)? {
+        let original = original.map_err(AssertError::GlobError)?;
+        let link = workdir.join(original.strip_prefix(&*rootdir).unwrap_or(&original));
+        link_file(&original, &link)?;
+      }
+    }
+    let diff_threshold = *self.diff_threshold;
+    let mut stamps = Vec::new();
+    for original in &originals {
+      InputStamp::record(original, diff_threshold, &mut stamps)
+        .map_err(|e| AssertError::UnableToRead(original.display().to_string(), e))?;
+    }
+    drop(io_permit);
+    let flock = self.acquire_flock().await?;
+    // release the reserved ports just before spawning, so the task can bind them
+    drop(ports);
+    self.exec_process(workdir, true, &[]).await?;
+    Ok((flock, stamps))
+  }
+  /// Wait for the `flock`, it is released when the returned file is dropped
+  async fn acquire_flock(&self) -> Result<Option<File>, AssertError> {
+    const INTERVAL: Duration = Duration::from_millis(50);
+    let Some(path) = self.flock.as_deref() else {
+      return Ok(None);
+    };
+    let err = |e| AssertError::Flock(path.to_owned(), e);
+    let file = File::options()
+      .create(true)
+      .truncate(false)
+      .write(true)
+      .open(path)
+      .map_err(err)?;
+    let secs = self.flock_timeout.unwrap_or(*self.timeout);
+    let now = Instant::now();
+    loop {
+      match file.try_lock() {
+        Ok(()) => return Ok(Some(file)),
+        Err(TryLockError::WouldBlock) if now.elapsed() < Duration::from_secs(secs) => {
+          tokio::time::sleep(INTERVAL).await;
+        }
+        Err(TryLockError::WouldBlock) => {
+          return Err(AssertError::FlockTimeOut(path.to_owned(), secs));
+        }
+        Err(TryLockError::Error(e)) => return Err(err(e)),
       }
     }
-    self.exec_process(workdir, true).await
   }
   #[inline]
-  async fn exe(&self, workdir: &Path) -> Result<ExitStatus, AssertError> {
-    let stdout = File::create(workdir.join(format!("{}.stdout", self.name)))?;
-    let stderr = File::create(workdir.join(format!("{}.stderr", self.name)))?;
-    let status = Command::new(&*self.cmd)
+  /// Execute the task, and measure its peak RSS when `max-rss` is asserted
+  /// and whether it left orphaned processes in its `process-group`
+  async fn exe(
+    &self,
+    workdir: &Path,
+  ) -> Result<(ExitStatus, Option<u64>, bool), AssertError> {
+    // streamed into the files, rather than buffered in memory
+    let (stdout, stderr) = if self.no_capture {
+      (Stdio::inherit(), Stdio::inherit())
+    } else {
+      let stdout = File::create(workdir.join(&*self.stdout))?;
+      let stderr = if *self.stderr == "stdout" || *self.stderr == *self.stdout {
+        stdout.try_clone()?
+      } else {
+        File::create(workdir.join(&*self.stderr))?
+      };
+      (Stdio::from(stdout), Stdio::from(stderr))
+    };
+    if self.verbose {
+      eprint!("note: execute\n{}", self.exec_display(workdir));
+    }
+    let mut command = std::process::Command::new(&self.exec_cmd);
+    if let Some(inherited_envs) = &self.inherited_envs {
+      command.env_clear().envs(inherited_envs);
+    }
+    for key in self.unset_envs.iter() {
+      command.env_remove(key);
+    }
+    command
       .current_dir(workdir)
-      .args(&*self.args)
+      .args(&self.exec_args)
       .envs(&*self.envs)
-      .stderr(Stdio::from(stderr))
-      .stdout(Stdio::from(stdout))
-      .spawn()?
-      .wait()
-      .await
-      .map_err(|e| {
-        AssertError::Executes(
-          CmdDisplay {
-            cmd: &self.cmd,
-            args: &self.args,
-            workdir,
-            envs: Some(&self.envs),
-          }
-          .to_string(),
-          e,
-        )
-      })?;
-    use std::os::unix::process::ExitStatusExt;
-    if status.code().is_none() {
-      let sig_int = status.signal();
-      let sig_str = sig_int
-        .and_then(|sig| nix::sys::signal::Signal::try_from(sig).ok())
-        .map_or("UNKOWN", |sig| sig.as_str());
-      return Err(AssertError::Terminated(
-        sig_str,
-        SigIntDisplay(sig_int),
-        CmdDisplay {
-          cmd: &self.cmd,
-          args: &self.args,
-          workdir,
-          envs: Some(&self.envs),
-        }
-        .to_string(),
-      ));
-    }
-    self.exec_process(workdir, false).await?;
-    Ok(status)
+      .stderr(stderr)
+      .stdout(stdout);
+    let executes = |e| AssertError::Executes(self.exec_display(workdir).to_string(), e);
+    #[cfg(unix)]
+    let (status, peak_rss, orphaned) = {
+      use std::os::unix::process::CommandExt as _;
+      if *self.process_group {
+        command.process_group(0);
+      }
+      // kill the group when it is dropped, e.g., the task times out
+      let group = |pid| self.process_group.then(|| ProcessGroup::new(pid));
+      if self.assert.max_rss.is_some() {
+        let child = command.spawn().map_err(executes)?;
+        let group = group(child.id());
+        let (status, peak_rss) =
+          tokio::task::spawn_blocking(move || wait_peak_rss(child))
+            .await
+            .expect("join handle")
+            .map_err(executes)?;
+        (status, Some(peak_rss), group.is_some_and(ProcessGroup::kill))
+      } else {
+        let mut child = Command::from(command).spawn()?;
+        let group = child.id().and_then(group);
+        let status = child.wait().await.map_err(executes)?;
+        (status, None, group.is_some_and(ProcessGroup::kill))
+      }
+    };
+    #[cfg(not(unix))]
+    let (status, peak_rss, orphaned) =
+      (Command::from(command).spawn()?.wait().await.map_err(executes)?, None, false);
+    Ok((status, peak_rss, orphaned))
   }
   #[inline]
-  async fn assert(self, rootdir: &Path, workdir: PathBuf) -> Vec<AssertError> {
-    match self.exe(&workdir).await {
-      Ok(status) => {
-        let assert_config = self.assert_config();
-        self
-          .assert
-          .inner
-          .assert(assert_config, workdir, rootdir.join(GOLDEN_DIR), status)
-          .await
+  /// Execute the task, assert it, and then run the postprocess with the
+  /// results exported as `REGRESSION_STATE`, `REGRESSION_DURATION_MS`,
+  /// and `REGRESSION_EXIT_CODE`
+  async fn assert(
+    mut self,
+    rootdir: &Path,
+    workdir: PathBuf,
+    refs: Option<&GoldenRefs>,
+  ) -> Vec<AssertError> {
+    let now = Instant::now();
+    let (status, peak_rss, orphaned) = match self.exe(&workdir).await {
+      Ok(executed) => executed,
+      Err(e) => return vec![e],
+    };
+    let duration = now.elapsed();
+    let cmd = self.exec_display(&workdir).to_string();
+    // the expected signal will be asserted
+    let mut errs = if status.code().is_none() && self.assert.signal.is_none() {
+      let sig_int = exit_signal(status);
+      vec![AssertError::Terminated(signal_name(sig_int), SigIntDisplay(sig_int), cmd)]
+    } else {
+      let assert_config = self.assert_config();
+      let orphaned = orphaned.then(|| AssertError::Orphaned(cmd.clone()));
+      let mut errs = Arc::unwrap_or_clone(core::mem::take(&mut self.assert.inner))
+        .assert(
+          assert_config,
+          workdir.clone(),
+          rootdir.join(GOLDEN_DIR),
+          status,
+          peak_rss,
+          cmd,
+          refs,
+        )
+        .await;
+      errs.extend(orphaned);
+      errs
+    };
+    let passed = errs.iter().all(AssertError::is_soft);
+    if self.postprocess_on.runs(passed) {
+      let results = [
+        ("REGRESSION_STATE", if passed { "pass" } else { "fail" }.to_owned()),
+        ("REGRESSION_DURATION_MS", duration.as_millis().to_string()),
+        ("REGRESSION_EXIT_CODE", status_str(status)),
+      ];
+      if let Err(e) = self.exec_process(&workdir, false, &results).await {
+        errs.push(e);
       }
-      Err(e) => vec![e],
+    }
+    errs
+  }
+  /// The executed command line, with the wrapper and the shell
+  fn exec_display<'s>(&'s self, workdir: &'s Path) -> CmdDisplay<'s, String> {
+    CmdDisplay {
+      cmd: &self.exec_cmd,
+      args: &self.exec_args,
+      workdir,
+      envs: Some(&self.envs),
+      cleared: self.inherited_envs.as_ref(),
+      unset: &self.unset_envs,
     }
   }
   fn assert_config(&self) -> AssertConfig {
-    AssertConfig { epsilon: *self.epsilon }
+    AssertConfig {
+      epsilon: *self.epsilon,
+      verbose: self.verbose,
+      stream_threshold: *self.stream_threshold,
+      diff_threshold: *self.diff_threshold,
+      diff_max_hunks: *self.diff_max_hunks,
+      legacy_env: *self.legacy_env,
+    }
   }
 }
 
+/// The size, mtime, and hash of a linked input file, see `protect-inputs`
+struct InputStamp {
+  path: PathBuf,
+  len: u64,
+  modified: Option<SystemTime>,
+  hash: u64,
+  /// The text to diff with, only when it is no larger than `diff-threshold`
+  text: Option<String>,
+}
+
+impl InputStamp {
+  /// Record the file, or all files in the directory
+  fn record(path: &Path, diff_threshold: u64, stamps: &mut Vec<Self>) -> io::Result<()> {
+    let metadata = std::fs::metadata(path)?;
+    if metadata.is_dir() {
+      for entry in path.read_dir()? {
+        Self::record(&entry?.path(), diff_threshold, stamps)?;
+      }
+      return Ok(());
+    }
+    let content = std::fs::read(path)?;
+    stamps.push(Self {
+      path: path.to_path_buf(),
+      len: metadata.len(),
+      modified: metadata.modified().ok(),
+      hash: content_hash(&content),
+      text: (metadata.len() <= diff_threshold)
+        .then(|| String::from_utf8(content).ok())
+        .flatten(),
+    });
+    Ok(())
+  }
+  /// The error if the file is modified or deleted, with the diffs of the text
+  fn check(&self, config: AssertConfig) -> Option<AssertError> {
+    let err =
+      |diffs| AssertError::InputModified { path: self.path.display().to_string(), diffs };
+    let (Ok(metadata), Ok(content)) =
+      (std::fs::metadata(&self.path), std::fs::read(&self.path))
+    else {
+      return Some(err(self.text.as_deref().map(|old| TextDiffs::new(old, "", config))));
+    };
+    if metadata.len() == self.len
+      && metadata.modified().ok() == self.modified
+      && content_hash(&content) == self.hash
+    {
+      return None;
+    }
+    let diffs = self
+      .text
+      .as_deref()
+      .zip(core::str::from_utf8(&content).ok())
+      .map(|(old, new)| TextDiffs::new(old, new, config));
+    Some(err(diffs))
+  }
+}
+
+fn content_hash(content: &[u8]) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  hasher.write(content);
+  hasher.finish()
+}
+
+/// The process group of the task with `process-group`, i.e., the task and the
+/// processes it spawned, unless they call `setsid`. It is killed on drop
+#[cfg(unix)]
+struct ProcessGroup(Option<nix::unistd::Pid>);
+
+#[cfg(unix)]
+impl ProcessGroup {
+  fn new(pid: u32) -> Self {
+    Self(Some(nix::unistd::Pid::from_raw(pid as i32)))
+  }
+  /// Kill the processes left after the task exited, return whether there is any
+  fn kill(mut self) -> bool {
+    use nix::sys::signal::{Signal, killpg};
+    let Some(pgid) = self.0.take() else {
+      return false;
+    };
+    let orphaned = killpg(pgid, None).is_ok();
+    if orphaned {
+      _ = killpg(pgid, Signal::SIGKILL);
+    }
+    orphaned
+  }
+}
+
+#[cfg(unix)]
+impl Drop for ProcessGroup {
+  fn drop(&mut self) {
+    if let Some(pgid) = self.0 {
+      _ = nix::sys::signal::killpg(pgid, nix::sys::signal::Signal::SIGKILL);
+    }
+  }
+}
+
+/// Reap the process by `wait4`, to get its peak RSS in bytes,
+/// which includes its waited descendants, e.g., the command run by a shell
+#[cfg(unix)]
+fn wait_peak_rss(child: std::process::Child) -> io::Result<(ExitStatus, u64)> {
+  use nix::libc;
+  use std::os::unix::process::ExitStatusExt;
+  let pid = child.id() as libc::pid_t;
+  let mut status = 0;
+  // SAFETY: `rusage` is plain old data
+  let mut rusage = unsafe { core::mem::zeroed::<libc::rusage>() };
+  loop {
+    // SAFETY: the child is only reaped here, `std` does not reap it on drop
+    if unsafe { libc::wait4(pid, &mut status, 0, &mut rusage) } == pid {
+      break;
+    }
+    let e = io::Error::last_os_error();
+    if e.kind() != io::ErrorKind::Interrupted {
+      return Err(e);
+    }
+  }
+  // `ru_maxrss` is in bytes on macOS, in kilobytes on the others
+  let scale = if cfg!(target_os = "macos") { 1 } else { 1024 };
+  Ok((ExitStatus::from_raw(status), rusage.ru_maxrss as u64 * scale))
+}
+
+/// The last `report-tail` lines of each `report-files`, appended to the report,
+/// at most `REPORT_TAILS_MAX` bytes in total
+async fn report_tails(patterns: &[String], lines: usize, workdir: &Path) -> String {
+  let mut s = String::new();
+  for pattern in patterns {
+    let paths = match glob::glob(&glob_pattern(workdir, pattern)) {
+      Ok(paths) => paths.filter_map(Result::ok).sorted().collect::<Vec<_>>(),
+      Err(e) => {
+        s.push_str(&format!("==== TAIL {pattern} ===\n{e}\n"));
+        continue;
+      }
+    };
+    if paths.is_empty() {
+      s.push_str(&format!("==== TAIL {pattern} ===\nno such file\n"));
+    }
+    for path in paths {
+      let file_name = relative_to(&path, workdir).display();
+      let budget = REPORT_TAILS_MAX.saturating_sub(s.len() as u64);
+      if budget == 0 {
+        s.push_str(&format!(
+          "==== TAIL {file_name} ===\nomitted, the tails exceed {REPORT_TAILS_MAX} bytes\n"
+        ));
+        continue;
+      }
+      match tail(&path, lines, budget).await {
+        Ok(tail) => s.push_str(&format!(
+          "==== TAIL {file_name} (last {} lines) ===\n{tail}\n",
+          lines
+        )),
+        Err(e) => s.push_str(&format!("==== TAIL {file_name} ===\n{e}\n")),
+      }
+    }
+  }
+  s
+}
+
+/// The last `lines` lines of the file, only its last `budget` bytes are read
+async fn tail(path: &Path, lines: usize, budget: u64) -> io::Result<String> {
+  let mut file = tokio::fs::File::open(path).await?;
+  let start = file.metadata().await?.len().saturating_sub(budget);
+  file.seek(io::SeekFrom::Start(start)).await?;
+  let mut buf = Vec::new();
+  file.read_to_end(&mut buf).await?;
+  let text = String::from_utf8_lossy(&buf);
+  // drop the partial first line, unless it is the file start
+  let text =
+    if start == 0 { &text } else { text.split_once('\n').map_or("", |(_, rest)| rest) };
+  let count = text.lines().count();
+  Ok(text.lines().skip(count.saturating_sub(lines)).join("\n"))
+}
+
+/// Symlink the original, or copy it when it is forced by `--copy-inputs`,
+/// or when the symlink fails on Windows (it needs privileges)
+fn link_or_copy(original: &Path, link: &Path, copy: bool) -> io::Result<()> {
+  if !copy {
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(original, link);
+    #[cfg(windows)]
+    {
+      let linked = if original.is_dir() {
+        std::os::windows::fs::symlink_dir(original, link)
+      } else {
+        std::os::windows::fs::symlink_file(original, link)
+      };
+      if linked.is_ok() {
+        return Ok(());
+      }
+    }
+  }
+  copy_all(original, link)
+}
+
+fn copy_all(original: &Path, to: &Path) -> io::Result<()> {
+  if original.is_dir() {
+    create_dir_all(to)?;
+    for entry in original.read_dir()? {
+      let entry = entry?;
+      copy_all(&entry.path(), &to.join(entry.file_name()))?;
+    }
+    Ok(())
+  } else {
+    std::fs::copy(original, to).map(drop)
+  }
+}
+
+/// The number of ports needed by `{{port}}` and `{{port.<n>}}` in the string
+fn port_count(s: &str) -> usize {
+  const BGN: &str = "{{port";
+  s.match_indices(BGN)
+    .filter_map(|(bgn, _)| {
+      let rest = &s[bgn + BGN.len()..];
+      if rest.starts_with("}}") {
+        Some(1)
+      } else {
+        let (n, _) = rest.strip_prefix('.')?.split_once("}}")?;
+        n.parse().ok().filter(|n| *n != 0)
+      }
+    })
+    .max()
+    .unwrap_or(0)
+}
+
+fn profile_file(coverage_dir: &Path, relative: &str) -> PathBuf {
+  coverage_dir.join(format!("{relative}-%p.profraw"))
+}
+
+/// Save the report into workdir, or keep the errors to print them
+async fn failed_state(
+  path: &Path,
+  workdir: &Path,
+  name: &str,
+  mut errs: Vec<AssertError>,
+  tails: String,
+  print_errs: bool,
+  max_report_bytes: usize,
+) -> FailedState {
+  if print_errs {
+    return FailedState::NoReport(path.to_path_buf(), errs, tails, max_report_bytes);
+  }
+  let err_report = workdir.join(format!("{name}.report"));
+  let report = bounded_errs(&errs, &tails, max_report_bytes, false);
+  match tokio::fs::write(&err_report, report).await {
+    Ok(_) => FailedState::ReportSaved(err_report),
+    Err(e) => {
+      errs.push(AssertError::Write(err_report.display().to_string(), e));
+      FailedState::NoReport(path.to_path_buf(), errs, tails, max_report_bytes)
+    }
+  }
+}
+
+/// The `{{seed}}` of the task, its stable hash XOR `--seed`
+pub(crate) fn task_seed(file: &Path, args: &Args) -> u64 {
+  seed_hash(args.relative_path(file)) ^ args.seed
+}
+
+/// The stable hash of the task path for `{{seed}}`, i.e., the 64-bit FNV-1a
+/// with `/` separators, so that it is the same across platforms and releases
+fn seed_hash(relative_path: &str) -> u64 {
+  relative_path
+    .bytes()
+    .map(|b| if b == b'\\' { b'/' } else { b })
+    .fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+      (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Resolve `.` and `..` lexically, the leading `..` are kept
+fn normalize(path: &Path) -> PathBuf {
+  let mut normalized = PathBuf::new();
+  for component in path.components() {
+    match component {
+      Component::CurDir => {}
+      Component::ParentDir
+        if matches!(normalized.components().next_back(), Some(Component::Normal(_))) =>
+      {
+        normalized.pop();
+      }
+      component => normalized.push(component),
+    }
+  }
+  normalized
+}
+
+/// Replace all `{{dep.<name>.workdir}}` with the workdirs of the dependencies
+fn eval_deps(
+  s: &mut String,
+  file: &Path,
+  deps: &[(String, String)],
+) -> Result<(), BuildError> {
+  const BGN: &str = "{{dep.";
+  let mut searched = 0;
+  while let Some(bgn) = s[searched..].find(BGN).map(|idx| idx + searched) {
+    let Some(len) = s[bgn..].find("}}") else {
+      break;
+    };
+    let key = &s[bgn + BGN.len()..bgn + len];
+    let workdir = key
+      .strip_suffix(".workdir")
+      .and_then(|name| deps.iter().find(|(dep, _)| dep == name))
+      .map(|(_, workdir)| workdir.clone())
+      .ok_or_else(|| BuildError::UnresolvedDep(file.to_path_buf(), key.to_owned()))?;
+    s.replace_range(bgn..bgn + len + 2, &workdir);
+    searched = bgn + workdir.len();
+  }
+  Ok(())
+}
+
+/// Replace all `{{env.<key>}}` with the task's envs, then the process's envs
+fn eval_envs(
+  s: &mut String,
+  file: &Path,
+  envs: &IndexMap<String, String>,
+) -> Result<(), BuildError> {
+  const BGN: &str = "{{env.";
+  let mut searched = 0;
+  while let Some(bgn) = s[searched..].find(BGN).map(|idx| idx + searched) {
+    let Some(len) = s[bgn..].find("}}") else {
+      break;
+    };
+    let key = &s[bgn + BGN.len()..bgn + len];
+    let value = match envs.get(key) {
+      Some(value) => value.clone(),
+      None => std::env::var(key)
+        .map_err(|_| BuildError::UnresolvedEnv(file.to_path_buf(), key.to_owned()))?,
+    };
+    s.replace_range(bgn..bgn + len + 2, &value);
+    searched = bgn + value.len();
+  }
+  Ok(())
+}
+
+/// Expand all `${VAR}` with `lookup`, and unescape `$${VAR}` into `${VAR}`,
+/// the ones that are not a plain name (e.g., `${VAR:-default}`) are kept
+fn eval_vars(
+  s: &mut String,
+  mut lookup: impl FnMut(&str) -> Result<String, BuildError>,
+) -> Result<(), BuildError> {
+  const BGN: &str = "${";
+  let mut searched = 0;
+  while let Some(bgn) = s[searched..].find(BGN).map(|idx| idx + searched) {
+    let Some(len) = s[bgn..].find('}') else {
+      break;
+    };
+    if s[..bgn].ends_with('$') {
+      s.remove(bgn - 1);
+      searched = bgn + len;
+      continue;
+    }
+    let key = &s[bgn + BGN.len()..bgn + len];
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+      searched = bgn + BGN.len();
+      continue;
+    }
+    let value = lookup(key)?;
+    s.replace_range(bgn..bgn + len + 1, &value);
+    searched = bgn + value.len();
+  }
+  Ok(())
+}
+
+fn process_var(file: &Path, key: &str) -> Result<String, BuildError> {
+  std::env::var(key)
+    .map_err(|_| BuildError::UnresolvedVar(file.to_path_buf(), key.to_owned()))
+}
+
+/// Expand the `${VAR}` in the envs' values, in the order of their references.
+/// `${VAR}` refers to the task's envs, then the process's ones,
+/// and `${KEY}` in `KEY`'s own value always refers to the process's one, e.g.,
+/// `PATH = "${PATH}:/opt/bin"`
+fn resolve_envs(
+  file: &Path,
+  envs: &IndexMap<String, String>,
+) -> Result<IndexMap<String, String>, BuildError> {
+  fn resolve(
+    key: &str,
+    file: &Path,
+    envs: &IndexMap<String, String>,
+    resolved: &mut IndexMap<String, String>,
+    stack: &mut Vec<String>,
+  ) -> Result<String, BuildError> {
+    if let Some(value) = resolved.get(key) {
+      return Ok(value.clone());
+    }
+    if let Some(pos) = stack.iter().position(|k| k == key) {
+      let mut cycle = stack.split_off(pos);
+      cycle.push(key.to_owned());
+      return Err(BuildError::EnvCycle(file.to_path_buf(), cycle));
+    }
+    stack.push(key.to_owned());
+    let mut value = envs[key].clone();
+    eval_vars(&mut value, |name| {
+      if name != key && envs.contains_key(name) {
+        resolve(name, file, envs, resolved, stack)
+      } else {
+        process_var(file, name)
+      }
+    })?;
+    stack.pop();
+    resolved.insert(key.to_owned(), value.clone());
+    Ok(value)
+  }
+  let mut resolved = IndexMap::with_capacity(envs.len());
+  let mut stack = Vec::new();
+  for key in envs.keys() {
+    resolve(key, file, envs, &mut resolved, &mut stack)?;
+  }
+  Ok(envs.keys().map(|key| (key.clone(), resolved[key].clone())).collect())
+}
+
+/// Replace all `{{bin.<name>}}` with the binary path
+fn eval_bins(s: &mut String, file: &Path, args: &Args) -> Result<(), BuildError> {
+  const BGN: &str = "{{bin.";
+  let mut searched = 0;
+  while let Some(bgn) = s[searched..].find(BGN).map(|idx| idx + searched) {
+    let Some(len) = s[bgn..].find("}}") else {
+      break;
+    };
+    let name = &s[bgn + BGN.len()..bgn + len];
+    let path = args
+      .bin_path(name)
+      .ok_or_else(|| BuildError::UnresolvedBin(file.to_path_buf(), name.to_owned()))?;
+    let path = path.display().to_string();
+    s.replace_range(bgn..bgn + len + 2, &path);
+    searched = bgn + path.len();
+  }
+  Ok(())
+}
+
 #[derive(Default, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 struct Extend {
   args: Option<Vec<String>>,
   envs: Option<IndexMap<String, String>>,
   extern_files: Option<Vec<String>>,
+  extensions: Option<HashSet<String>>,
 }
 
 #[test]
@@ -675,3 +2169,415 @@ equal = true
     Err(e) => println!("{e}"),
   }
 }
+
+#[test]
+fn test_eval_bins() {
+  let args = Args::new("demo").bin("mytool", "/path/to/mytool");
+  let file = Path::new("demo/test.sh");
+  let mut s = "{{bin.mytool}} --flag {{bin.mytool}}".to_owned();
+  eval_bins(&mut s, file, &args).unwrap();
+  assert_eq!(s, "/path/to/mytool --flag /path/to/mytool");
+  let mut s = "{{bin.cargo-regression-unknown-bin}}".to_owned();
+  assert!(matches!(
+    eval_bins(&mut s, file, &args),
+    Err(BuildError::UnresolvedBin(_, name)) if name == "cargo-regression-unknown-bin"
+  ));
+}
+
+#[test]
+fn test_env_precedence() {
+  let tmpdir = crate::TempDir::new("env-precedence");
+  let path = tmpdir.join("env-precedence.env");
+  std::fs::write(&path, "K1=file\nK2=file\n").unwrap();
+  let args = Args::new("demo")
+    .cmd("bash")
+    .extensions(["sh"])
+    .env_file([&path])
+    .rebuild()
+    .unwrap();
+  let mut config = FullConfig::new(args);
+  config.envs.insert("K2".into(), "config".into());
+  config.args.push("{{env.K1}}".into());
+  let config = config.eval(Path::new("demo/test.sh"), args).unwrap();
+  assert_eq!(config.envs["K1"], "file");
+  assert_eq!(config.envs["K2"], "config");
+  assert_eq!(config.args.last().unwrap(), "file");
+}
+
+#[test]
+fn test_env_vars() {
+  let args = Args::new("demo").cmd("bash").extensions(["sh"]).rebuild().unwrap();
+  let file = Path::new("demo/test.sh");
+  let home = std::env::var("HOME").unwrap();
+  let path = std::env::var("PATH").unwrap();
+  let mut config = FullConfig::new(args);
+  // refer to the later one, and the process's one
+  config.envs.insert("BIN".into(), "${PREFIX}/bin".into());
+  config.envs.insert("PREFIX".into(), "${HOME}/{{name}}".into());
+  config.envs.insert("PATH".into(), "${BIN}:${PATH}".into());
+  config.envs.insert("RAW".into(), "$${BIN} ${BIN:-x}".into());
+  config.args.push("${BIN}/tool".into());
+  config.args.push("{{env.RAW}}".into());
+  let config = config.eval(file, args).unwrap();
+  assert_eq!(config.envs["PREFIX"], format!("{home}/test"));
+  assert_eq!(config.envs["BIN"], format!("{home}/test/bin"));
+  assert_eq!(config.envs["PATH"], format!("{home}/test/bin:{path}"));
+  assert_eq!(config.envs["RAW"], "${BIN} ${BIN:-x}");
+  assert_eq!(config.args[config.args.len() - 2], format!("{home}/test/bin/tool"));
+  assert_eq!(config.args.last().unwrap(), "${BIN} ${BIN:-x}");
+  let mut config = FullConfig::new(args);
+  config.envs.insert("A".into(), "${B}".into());
+  config.envs.insert("B".into(), "x${C}".into());
+  config.envs.insert("C".into(), "${A}".into());
+  assert!(matches!(
+    config.eval(file, args),
+    Err(BuildError::EnvCycle(_, cycle)) if cycle == ["A", "B", "C", "A"]
+  ));
+  let mut config = FullConfig::new(args);
+  config.args.push("${CARGO_REGRESSION_UNKNOWN_VAR}".into());
+  assert!(matches!(
+    config.eval(file, args),
+    Err(BuildError::UnresolvedVar(_, key)) if key == "CARGO_REGRESSION_UNKNOWN_VAR"
+  ));
+}
+
+#[test]
+fn test_coverage() {
+  let args = Args::new("demo").coverage_dir(std::env::temp_dir().join("coverage"));
+  let args = args.rebuild().unwrap();
+  let mut configs =
+    ["demo/test-sh/test-match.sh", "demo/test-py/test-match.sh"].map(|path| {
+      let mut config = FullConfig::new(args);
+      config
+        .set_coverage(args.relative_path(Path::new(path)), args)
+        .unwrap();
+      config
+    });
+  let [profile1, profile2] = configs.each_mut().map(|config| {
+    config
+      .envs
+      .swap_remove("LLVM_PROFILE_FILE")
+      .expect("should inject LLVM_PROFILE_FILE")
+  });
+  assert_ne!(profile1, profile2);
+  assert!(profile1.ends_with("test-sh/test-match.sh-%p.profraw"));
+}
+
+#[test]
+fn test_cmd() {
+  let builder = Args::new("demo").cmd("bash").rebuild().unwrap();
+  let cli = Args::parse_from(["", "demo", "--cmd", "bash"]).rebuild().unwrap();
+  for args in [builder, cli] {
+    assert_eq!(*FullConfig::new(args).cmd, "bash");
+  }
+  // the configs' `cmd` takes precedence
+  let config = FullConfig::new(builder)
+    .update(Path::new("demo/test-py/__all__.toml"), false)
+    .unwrap();
+  assert_eq!(*config.cmd, "python");
+}
+
+#[test]
+fn test_extend_extensions() {
+  let args = Args::new("demo").extensions(["sh"]).rebuild().unwrap();
+  let tmpdir = crate::TempDir::new("extend-extensions");
+  let dir = tmpdir.join("dir");
+  create_dir_all(&dir).unwrap();
+  let config_path = dir.join("__all__.toml");
+  let update = |toml_str| {
+    std::fs::write(&config_path, toml_str).unwrap();
+    FullConfig::new(args).update(&config_path, false)
+  };
+  let config = update("[extend]\nextensions = [\"py\"]").unwrap();
+  assert_eq!(*config.extensions, HashSet::from(["sh".into(), "py".into()]));
+  let config = update("extensions = [\"py\"]").unwrap();
+  assert_eq!(*config.extensions, HashSet::from(["py".into()]));
+  for toml_str in ["[extend]\nextensions = [\"toml\"]", "extensions = [\"toml\"]"] {
+    assert!(matches!(update(toml_str), Err(BuildError::InputExtToml)));
+  }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_wait_peak_rss() {
+  let mut command = std::process::Command::new("bash");
+  command.args(["-c", "exit 3"]);
+  let (status, peak_rss) = wait_peak_rss(command.spawn().unwrap()).unwrap();
+  assert_eq!(status.code(), Some(3));
+  assert!(peak_rss > 1024, "{peak_rss}");
+}
+
+#[test]
+fn test_copy_inputs() {
+  let tmpdir = crate::TempDir::new("copy-inputs");
+  let dir = tmpdir.join("dir");
+  create_dir_all(dir.join("original/sub")).unwrap();
+  std::fs::write(dir.join("original/sub/data.txt"), "data").unwrap();
+  link_or_copy(&dir.join("original"), &dir.join("copied"), true).unwrap();
+  let copied = dir.join("copied/sub/data.txt");
+  assert!(!copied.symlink_metadata().unwrap().is_symlink());
+  assert_eq!(read_to_string(copied).unwrap(), "data");
+  link_or_copy(&dir.join("original/sub/data.txt"), &dir.join("linked.txt"), false)
+    .unwrap();
+  assert_eq!(read_to_string(dir.join("linked.txt")).unwrap(), "data");
+}
+
+#[test]
+fn test_share_fields() {
+  let args = Args::new("demo").cmd("bash").extensions(["sh"]).rebuild().unwrap();
+  let tmpdir = crate::TempDir::new("share-fields");
+  let config_path = tmpdir.join("__all__.toml");
+  std::fs::write(&config_path, "only-on = [\"linux\"]\nargs = [\"{{name}}.sh\"]")
+    .unwrap();
+  let config = FullConfig::new(args).update(&config_path, true).unwrap();
+  let [a, b] = ["a.sh", "b.sh"]
+    .map(|file| FullConfig::clone(&config).eval(&tmpdir.join(file), args).unwrap());
+  // the fields not evaluated are shared with the directory's config
+  for task in [&a, &b] {
+    assert!(Arc::ptr_eq(&task.only_on.inner, &config.only_on.inner));
+    assert!(Arc::ptr_eq(&task.only_on.source, &config.only_on.source));
+    assert!(Arc::ptr_eq(&task.extensions.inner, &config.extensions.inner));
+  }
+  // the evaluated ones are copied
+  assert_eq!(*config.args, ["{{name}}.sh"]);
+  assert_eq!(*a.args, ["a.sh"]);
+  assert_eq!(*b.args, ["b.sh"]);
+}
+
+#[test]
+fn test_permit_percent() {
+  let file = Path::new("demo/test.sh");
+  let resolve = |s: &str, permits| Permit::Percent(s.into()).resolve(file, permits);
+  assert_eq!(resolve("50%", 4).unwrap(), 2);
+  assert_eq!(resolve("50 %", 3).unwrap(), 2);
+  assert_eq!(resolve("100%", 3).unwrap(), 3);
+  assert_eq!(resolve("0%", 3).unwrap(), 0);
+  for s in ["150%", "-1%", "50", "half%"] {
+    assert!(matches!(resolve(s, 4), Err(BuildError::InvalidPermit(..))));
+  }
+}
+
+#[test]
+fn test_resources() {
+  let args = Args::parse_from(["", "demo", "--cmd", "bash", "--resource", "gpu=1"])
+    .extensions(["sh"])
+    .rebuild()
+    .unwrap();
+  assert_eq!(args.resources["gpu"], 1);
+  let eval = |resources: &[(&str, u32)]| {
+    let mut config = FullConfig::new(args);
+    config
+      .resources
+      .extend(resources.iter().map(|(k, v)| (k.to_string(), *v)));
+    config.eval(Path::new("demo/test.sh"), args)
+  };
+  assert_eq!(eval(&[("gpu", 1)]).unwrap().resource("gpu"), Some(1));
+  assert!(matches!(
+    eval(&[("gpu", 2)]),
+    Err(BuildError::ResourceExceed(_, name, 2, 1)) if name == "gpu"
+  ));
+  assert!(matches!(
+    eval(&[("license", 1)]),
+    Err(BuildError::UnknownResource(_, name)) if name == "license"
+  ));
+  for resource in ["gpu", "gpu=x", "=1"] {
+    let err = Args::parse_from(["", "demo", "--resource", resource])
+      .rebuild()
+      .unwrap_err();
+    assert!(matches!(err, BuildError::InvalidResource(_)), "{resource}");
+  }
+}
+
+#[test]
+fn test_golden_generator_only_all() {
+  let tmpdir = crate::TempDir::new("golden-generator-only-all");
+  let args = Args::new("demo").rebuild().unwrap();
+  for name in ["__all__.toml", "task.toml"] {
+    let path = tmpdir.join(name);
+    std::fs::write(&path, "[golden-generator]\ncmd = 'true'").unwrap();
+    let result = FullConfig::new(args).update(&path, false);
+    if name == "__all__.toml" {
+      assert!(result.unwrap().golden_generator.is_some());
+    } else {
+      assert!(matches!(result, Err(BuildError::GoldenGeneratorNotAll(p)) if p == path));
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_generate_golden_create_dir() {
+  let tmpdir = crate::TempDir::new("generate-golden-create-dir");
+  std::fs::write(tmpdir.join("file"), "").unwrap();
+  let args = Args::new("demo").rebuild().unwrap();
+  let generator: PrePostProcess = toml::from_str(&format!(
+    "cmd = 'true'\nworkdir = {:?}",
+    tmpdir.join("file/sub").display().to_string()
+  ))
+  .unwrap();
+  let err = generator.generate_golden(&tmpdir, args).await.unwrap_err();
+  assert!(matches!(err, BuildError::CreateDir(dir, _) if dir == tmpdir.join("file/sub")));
+}
+
+#[test]
+fn test_platform() {
+  let toml_str = r#"
+only-on = ["linux", "macos"]
+skip-on = ["macos"]
+"#;
+  let tmpdir = crate::TempDir::new("platform");
+  let path = tmpdir.join("platform.toml");
+  std::fs::write(&path, toml_str).unwrap();
+  let args = Args::new("demo").rebuild().unwrap();
+  let config = FullConfig::new(args).update(&path, false).unwrap();
+  assert_eq!(config.platform_mismatch("linux"), None);
+  assert_eq!(config.platform_mismatch("macos").as_deref(), Some("platform macos"));
+  assert_eq!(config.platform_mismatch("windows").as_deref(), Some("platform windows"));
+  assert_eq!(FullConfig::new(args).platform_mismatch("windows"), None);
+}
+
+#[test]
+fn test_ports() {
+  assert_eq!(port_count("--port {{port}}"), 1);
+  assert_eq!(port_count("{{port}} {{port.3}} {{port.2}}"), 3);
+  assert_eq!(port_count("{{port.x}} {{port.0}} {{portx}} {{port"), 0);
+  let args = Args::new("demo").cmd("server").extensions(["sh"]).rebuild().unwrap();
+  let mut config = FullConfig::new(args);
+  config.args.push("{{port}}:{{port.2}}".into());
+  config.envs.insert("ADDR".into(), "127.0.0.1:{{port.2}}".into());
+  let mut config = config.eval(Path::new("demo/test.sh"), args).unwrap();
+  let listeners = config.set_ports().unwrap();
+  assert_eq!(listeners.len(), 2);
+  let [port1, port2] =
+    [&config.envs["REGRESSION_PORT"], &config.envs["REGRESSION_PORT_2"]];
+  assert_ne!(port1, port2);
+  assert_eq!(config.exec_args.last().unwrap(), &format!("{port1}:{port2}"));
+  assert_eq!(config.envs["ADDR"], format!("127.0.0.1:{port2}"));
+  // never handed out twice, even after released
+  drop(listeners);
+  let mut other = FullConfig::new(args);
+  other.args.push("{{port}}".into());
+  let mut other = other.eval(Path::new("demo/test.sh"), args).unwrap();
+  let _listeners = other.set_ports().unwrap();
+  assert!(![port1, port2].contains(&&other.envs["REGRESSION_PORT"]));
+}
+
+#[test]
+fn test_seed() {
+  let seed = |args: Args| {
+    let args = args.cmd("bash").extensions(["sh"]).rebuild().unwrap();
+    let mut config = FullConfig::new(args);
+    config.args.push("--seed={{seed}}".into());
+    let config = config.eval(Path::new("demo/test-sh/test.sh"), args).unwrap();
+    let seed = config.envs["REGRESSION_SEED"].clone();
+    assert_eq!(config.exec_args.last().unwrap(), &format!("--seed={seed}"));
+    seed.parse::<u64>().unwrap()
+  };
+  // stable across runs, so that the goldens stay valid
+  let stable = seed(Args::new("demo"));
+  assert_eq!(stable, seed(Args::new("demo")));
+  assert_eq!(stable, seed_hash("test-sh/test.sh"));
+  assert_eq!(seed_hash("test-sh/test.sh"), seed_hash("test-sh\\test.sh"));
+  assert_ne!(stable, seed_hash("test-sh/test2.sh"));
+  assert_eq!(seed(Args::new("demo").seed(42)), stable ^ 42);
+  let random = seed(Args::new("demo").random_seed());
+  assert_ne!(random, stable);
+  assert_ne!(random, seed(Args::new("demo").random_seed()));
+}
+
+#[test]
+fn test_schema() {
+  use crate::schema::{ROOT, sample_def};
+  fn assert_same_keys(path: &str, want: &toml::Value, got: &toml::Value) {
+    match (want, got) {
+      (toml::Value::Table(want), toml::Value::Table(got)) => {
+        for (k, v) in want {
+          let Some(got_v) = got.get(k) else {
+            panic!("schema key \"{path}{k}\" is not deserialized by the config");
+          };
+          assert_same_keys(&format!("{path}{k}."), v, got_v);
+        }
+      }
+      (toml::Value::Array(want), toml::Value::Array(got)) => {
+        assert_same_keys(path, &want[0], &got[0]);
+      }
+      _ => {}
+    }
+  }
+  let sample = sample_def(&ROOT);
+  let config = toml::from_str::<Config>(&toml::to_string(&sample).unwrap())
+    .expect("schema sample should be a valid config");
+  let got = toml::Value::try_from(&config).unwrap();
+  assert_same_keys("", &sample, &got);
+}
+
+#[tokio::test]
+async fn test_report_tails() {
+  let tmpdir = crate::TempDir::new("report-tails");
+  let workdir = tmpdir.join("workdir");
+  std::fs::create_dir_all(&workdir).unwrap();
+  let log = (1..=300).map(|n| format!("line {n}\n")).collect::<String>();
+  std::fs::write(workdir.join("a.log"), &log).unwrap();
+  std::fs::write(workdir.join("b.log"), "only\n").unwrap();
+  let patterns = ["*.log".to_owned(), "none.txt".to_owned()];
+  let tails = report_tails(&patterns, 3, &workdir).await;
+  assert_eq!(
+    tails,
+    "==== TAIL a.log (last 3 lines) ===\nline 298\nline 299\nline 300\n\
+     ==== TAIL b.log (last 3 lines) ===\nonly\n\
+     ==== TAIL none.txt ===\nno such file\n"
+  );
+  // only the whole lines within the budget
+  let tail = tail(&workdir.join("a.log"), 50, 20).await.unwrap();
+  assert_eq!(tail, "line 299\nline 300");
+}
+
+#[tokio::test]
+async fn test_report_wrapped_cmd() {
+  let tmpdir = crate::TempDir::new("report-wrapped-cmd");
+  let workdir = tmpdir.join("workdir");
+  std::fs::create_dir_all(&workdir).unwrap();
+  let args = Args::new("demo")
+    .cmd("bash")
+    .extensions(["sh"])
+    .wrapper("env FOO=1")
+    .rebuild()
+    .unwrap();
+  let mut config = FullConfig::new(args);
+  *config.args = vec!["-c".into(), "exit 3".into()];
+  let config = config.eval(Path::new("demo/test.sh"), args).unwrap();
+  let errs = config.assert(Path::new("demo"), workdir, None).await;
+  let [AssertError::ExitCode { got: 3, cmd, .. }] = errs.as_slice() else {
+    panic!("{errs:?}")
+  };
+  // the reproduction is the executed command, with the wrapper
+  assert!(cmd.contains("\"env\" \\\n\t\"FOO=1\" \\\n\t\"bash\""), "{cmd}");
+}
+
+#[tokio::test]
+async fn test_capture() {
+  let tmpdir = crate::TempDir::new("capture");
+  let workdir = tmpdir.join("workdir");
+  std::fs::create_dir_all(&workdir).unwrap();
+  let args = Args::new("demo").cmd("bash").extensions(["sh"]).rebuild().unwrap();
+  let mut config = FullConfig::new(args);
+  // 50 MB, streamed into the file
+  *config.args = vec!["-c".into(), "head -c 50000000 /dev/zero; echo err >&2".into()];
+  *config.stdout = "{{name}}.log".into();
+  let config = config.eval(Path::new("demo/test.sh"), args).unwrap();
+  let (status, ..) = config.exe(&workdir).await.unwrap();
+  assert!(status.success());
+  assert_eq!(std::fs::metadata(workdir.join("test.log")).unwrap().len(), 50_000_000);
+  assert_eq!(std::fs::read_to_string(workdir.join("test.stderr")).unwrap(), "err\n");
+  assert!(!workdir.join("test.stdout").exists());
+  // like `2>&1`
+  let mut config = FullConfig::new(args);
+  *config.args = vec!["-c".into(), "echo out; echo err >&2; echo out".into()];
+  *config.stderr = "stdout".into();
+  let config = config.eval(Path::new("demo/merged.sh"), args).unwrap();
+  config.exe(&workdir).await.unwrap();
+  assert_eq!(
+    std::fs::read_to_string(workdir.join("merged.stdout")).unwrap(),
+    "out\nerr\nout\n"
+  );
+  assert!(!workdir.join("merged.stderr").exists());
+}
@@ -0,0 +1,199 @@
+//! The self-contained HTML report of `--html`, e.g., to share the results with
+//! non-engineers, without any external asset so that it can be attached.
+use crate::summary::escape_html;
+use core::fmt::{self, Write as _};
+use std::{path::PathBuf, time::Duration};
+
+/// One row of the tests table
+pub(crate) struct HtmlRow {
+  pub(crate) id: String,
+  /// One of `passed`, `failed`, `warned`, `skipped` and `ignored`
+  pub(crate) status: &'static str,
+  /// Only for the executed tasks
+  pub(crate) time: Option<Duration>,
+  /// The errors text of failed and warned tasks, or the reason of skipped tasks
+  pub(crate) details: String,
+}
+
+/// The counts and the rows of a run, the filtered out tasks are only counted
+pub(crate) struct HtmlReport<'a> {
+  pub(crate) count_ok: usize,
+  pub(crate) count_failed: usize,
+  pub(crate) count_skipped: usize,
+  pub(crate) count_ignored: usize,
+  pub(crate) count_filtered: usize,
+  pub(crate) rows: &'a [HtmlRow],
+  pub(crate) denieds: &'a [(PathBuf, &'static str)],
+  pub(crate) time: Duration,
+}
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em}\
+table{border-collapse:collapse}\
+th,td{border:1px solid #ccc;padding:4px 8px;text-align:left;vertical-align:top}\
+th.sort{cursor:pointer;user-select:none}th.sort:after{content:' \\2195';color:#999}\
+pre{margin:4px 0;font-size:12px;white-space:pre-wrap}\
+.passed{color:#1a7f37}.failed{color:#cf222e}.warned{color:#9a6700}\
+.skipped,.ignored{color:#6e7781}\
+.del{color:#cf222e;background:#ffebe9}.ins{color:#1a7f37;background:#dafbe1}";
+
+/// Sort the rows by the clicked column, with the `data-key` of the cells
+const SCRIPT: &str = "document.querySelectorAll('th.sort').forEach(function(th,col){\
+th.addEventListener('click',function(){\
+var body=document.querySelector('#tests tbody');\
+var asc=th.dataset.asc!=='1';th.dataset.asc=asc?'1':'0';\
+var rows=Array.from(body.rows);\
+rows.sort(function(r1,r2){\
+var k1=r1.cells[col].dataset.key,k2=r2.cells[col].dataset.key;\
+var n1=parseFloat(k1),n2=parseFloat(k2);\
+var c=isNaN(n1)||isNaN(n2)?k1.localeCompare(k2):n1-n2;\
+return asc?c:-c;});\
+rows.forEach(function(r){body.appendChild(r);});});});";
+
+impl HtmlReport<'_> {
+  pub(crate) fn to_html(&self) -> String {
+    let mut s = String::new();
+    self.write_html(&mut s).expect("write to string");
+    s
+  }
+  fn write_html(&self, f: &mut String) -> fmt::Result {
+    let success = self.count_failed == 0 && self.denieds.is_empty();
+    let result = if success { "ok" } else { "FAILED" };
+    writeln!(f, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">")?;
+    writeln!(f, "<title>Test result: {result}</title>\n<style>{STYLE}</style>")?;
+    writeln!(f, "</head>\n<body>")?;
+    writeln!(
+      f,
+      "<h2>Test result: <span class=\"{}\">{result}</span></h2>",
+      if success { "passed" } else { "failed" }
+    )?;
+    writeln!(f, "<table>")?;
+    writeln!(
+      f,
+      "<tr><th>Passed</th><th>Failed</th><th>Skipped</th><th>Ignored</th><th>Filtered out</th><th>Time</th></tr>"
+    )?;
+    writeln!(
+      f,
+      "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.2}s</td></tr>",
+      self.count_ok,
+      self.count_failed,
+      self.count_skipped,
+      self.count_ignored,
+      self.count_filtered,
+      self.time.as_secs_f32()
+    )?;
+    writeln!(f, "</table>")?;
+    if !self.denieds.is_empty() {
+      writeln!(f, "<h3>Denied</h3>\n<ul>")?;
+      for (path, reason) in self.denieds {
+        writeln!(
+          f,
+          "<li><code>{}</code> is {reason}</li>",
+          escape_html(&path.display().to_string())
+        )?;
+      }
+      writeln!(f, "</ul>")?;
+    }
+    writeln!(f, "<h3>Tests</h3>\n<table id=\"tests\">")?;
+    writeln!(
+      f,
+      "<thead><tr><th class=\"sort\">Test</th><th class=\"sort\">Status</th><th class=\"sort\">Duration</th></tr></thead>\n<tbody>"
+    )?;
+    for row in self.rows {
+      let id = escape_html(&row.id);
+      write!(f, "<tr><td data-key=\"{id}\">")?;
+      if row.details.is_empty() {
+        write!(f, "<code>{id}</code>")?;
+      } else {
+        write!(f, "<details><summary><code>{id}</code></summary><pre>")?;
+        write_details(f, &row.details)?;
+        write!(f, "</pre></details>")?;
+      }
+      write!(f, "</td><td class=\"{0}\" data-key=\"{0}\">{0}</td>", row.status)?;
+      match row.time {
+        Some(time) => writeln!(
+          f,
+          "<td data-key=\"{}\">{:.2}s</td></tr>",
+          time.as_millis(),
+          time.as_secs_f32()
+        )?,
+        None => writeln!(f, "<td data-key=\"-1\">-</td></tr>")?,
+      }
+    }
+    writeln!(f, "</tbody>\n</table>\n<script>{SCRIPT}</script>\n</body>\n</html>")
+  }
+}
+
+/// Escape the errors text, and color the lines of the diffs, i.e., the lines
+/// like `12   12  |-old` and `     13  |+new`
+fn write_details(f: &mut String, details: &str) -> fmt::Result {
+  for line in details.lines() {
+    let class = line.split_once(" |").and_then(|(lines, change)| {
+      if !lines.chars().all(|c| c.is_ascii_digit() || c == ' ') {
+        return None;
+      }
+      match change.as_bytes().first() {
+        Some(b'-') => Some("del"),
+        Some(b'+') => Some("ins"),
+        _ => None,
+      }
+    });
+    match class {
+      Some(class) => writeln!(f, "<span class=\"{class}\">{}</span>", escape_html(line))?,
+      None => writeln!(f, "{}", escape_html(line))?,
+    }
+  }
+  Ok(())
+}
+
+#[test]
+fn test_html_report() {
+  let rows = [
+    HtmlRow {
+      id: "a<b>.sh".to_owned(),
+      status: "failed",
+      time: Some(Duration::from_millis(1500)),
+      details:
+        "```bash\nbash a.sh\n```\n1    1   |-old\n     1   |+new\n2    2   | same\n"
+          .to_owned(),
+    },
+    HtmlRow {
+      id: "c.sh".to_owned(),
+      status: "passed",
+      time: Some(Duration::from_millis(20)),
+      details: String::new(),
+    },
+    HtmlRow {
+      id: "d.sh".to_owned(),
+      status: "skipped",
+      time: None,
+      details: "dependency \"c.sh\" failed".to_owned(),
+    },
+  ];
+  let report = HtmlReport {
+    count_ok: 1,
+    count_failed: 1,
+    count_skipped: 1,
+    count_ignored: 0,
+    count_filtered: 2,
+    rows: &rows,
+    denieds: &[],
+    time: Duration::from_millis(2000),
+  };
+  let html = report.to_html();
+  assert!(html.starts_with("<!DOCTYPE html>\n"), "{html}");
+  // self-contained
+  assert!(!html.contains("src=") && !html.contains("href="), "{html}");
+  assert!(html.contains("<h2>Test result: <span class=\"failed\">FAILED</span></h2>"));
+  assert!(
+    html.contains("<td>1</td><td>1</td><td>1</td><td>0</td><td>2</td><td>2.00s</td>"),
+    "{html}"
+  );
+  assert!(html.contains("<summary><code>a&lt;b&gt;.sh</code></summary>"), "{html}");
+  assert!(html.contains("<span class=\"del\">1    1   |-old</span>\n"), "{html}");
+  assert!(html.contains("<span class=\"ins\">     1   |+new</span>\n"), "{html}");
+  assert!(html.contains("\n2    2   | same\n"), "{html}");
+  assert!(html.contains("<td data-key=\"1500\">1.50s</td>"), "{html}");
+  assert!(html.contains("dependency &quot;c.sh&quot; failed"), "{html}");
+  assert!(html.contains("<tr><td data-key=\"c.sh\"><code>c.sh</code></td>"), "{html}");
+  assert_eq!(html.matches("</details>").count(), 2);
+}
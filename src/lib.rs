@@ -1,11 +1,17 @@
 mod args;
 mod assert;
 mod config;
+mod html;
 mod regression;
+mod schema;
+mod summary;
+mod timings;
 use assert::Assert;
 
-pub use args::Args;
-pub use regression::TestExitCode;
+pub use args::{Args, Format, Retain, ShowSkipped, SummaryStyle, WorkdirLayout};
+pub use assert::{AssertConfig, AssertError, Golden, compare};
+pub use regression::{TestExitCode, TestStatus};
+pub use schema::config_schema;
 
 #[tokio::test]
 async fn demo() -> TestExitCode {
@@ -14,3 +20,35 @@ async fn demo() -> TestExitCode {
     .include(["demo/test-premit/test2.sh"]);
   args.test().await
 }
+
+/// The directory of a test, unique to the process so that the concurrent
+/// `cargo test` do not collide, and removed on drop even if the test panics
+#[cfg(test)]
+pub(crate) struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+  pub(crate) fn new(name: &str) -> Self {
+    let path = std::env::temp_dir()
+      .join(format!("cargo-regression-{name}-{}", std::process::id()));
+    // left by a killed run of the same pid
+    _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    Self(path)
+  }
+}
+
+#[cfg(test)]
+impl core::ops::Deref for TempDir {
+  type Target = std::path::Path;
+  fn deref(&self) -> &Self::Target {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+  fn drop(&mut self) {
+    _ = std::fs::remove_dir_all(&self.0);
+  }
+}
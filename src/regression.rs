@@ -1,9 +1,14 @@
-use core::fmt;
+use core::{cmp::Reverse, fmt};
 use std::{
-  io,
-  path::PathBuf,
+  collections::{BTreeMap, HashMap, HashSet},
+  ffi::OsString,
+  io::{self, IsTerminal as _, Write as _},
+  path::{Path, PathBuf},
   process::{ExitCode, Termination},
-  sync::Arc,
+  sync::{
+    Arc, Mutex,
+    atomic::{AtomicUsize, Ordering},
+  },
   time::{Duration, Instant},
 };
 
@@ -11,16 +16,32 @@ use colored::Colorize;
 use itertools::{Either, Itertools};
 use tokio::{
   fs::remove_dir_all,
-  sync::{Mutex, Semaphore},
+  sync::{Semaphore, mpsc, watch},
+  task::JoinSet,
 };
 
 use crate::{
   Args,
-  assert::{AssertError, DisplayErrs},
-  config::FullConfig,
+  args::{Command, Format, Retain, ShowSkipped, SummaryStyle},
+  assert::{AssertError, bounded_errs, tree_files},
+  config::{FullConfig, dump_configs, task_seed},
+  html::{HtmlReport, HtmlRow},
+  schema::config_schema,
+  summary::Summary,
+  timings::{BaselineReport, Timings, write_timings},
 };
 
 pub(crate) const GOLDEN_DIR: &str = "__golden__";
+/// The generated debug files, e.g., `__debug__.{{name}}.toml`
+pub(crate) const DEBUG_PREFIX: &str = "__debug__.";
+/// The redraw interval of `--progress`, to update the elapsed time
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+/// Move to the line start and erase the line
+const CLEAR_LINE: &str = "\r\x1b[2K";
+/// The max number of running tasks shown in the progress line
+const PROGRESS_RUNNING: usize = 3;
+/// The absolute paths of failed tasks of last run, in workdir
+pub(crate) const LAST_FAILED: &str = ".last-failed";
 
 #[derive(Debug, thiserror::Error)]
 pub enum BuildError {
@@ -28,14 +49,70 @@ pub enum BuildError {
   Toml(PathBuf, toml::de::Error),
   #[error("task \"{0}\": its permit = {1}, exceed total permits = {2}")]
   PermitEcxceed(PathBuf, u32, u32),
+  #[error("--permit-default = {0}, exceed total permits = {1}")]
+  PermitDefaultExceed(u32, u32),
+  #[error("task \"{0}\": its resource '{1}' = {2}, exceed total = {3}")]
+  ResourceExceed(PathBuf, String, u32, u32),
+  #[error("task \"{0}\": unknown resource '{1}', declare it by '--resource {1}=<total>'")]
+  UnknownResource(PathBuf, String),
+  #[error("--resource \"{0}\": expect 'NAME=COUNT'")]
+  InvalidResource(String),
+  #[error(
+    "task \"{0}\": invalid permit \"{1}\", expect an integer or a percentage in 0%~100%"
+  )]
+  InvalidPermit(PathBuf, String),
   #[error("task \"{0}\": need to specify '{1}'")]
   MissConfig(PathBuf, &'static str),
+  #[error("rootdir \"{0}\" not found, please check the path and the current directory")]
+  RootDirNotFound(PathBuf),
+  #[error("--{0} \"{1}\" not found, please check the path and the current directory")]
+  FilterNotFound(&'static str, PathBuf),
+  #[error(
+    "workdir \"{0}\" contains rootdir \"{1}\", cleaning it will delete the sources"
+  )]
+  WorkdirContainsRootDir(PathBuf, PathBuf),
+  #[error("tasks \"{0}\" and \"{1}\": their workdirs collide at \"{2}\"")]
+  WorkdirCollision(PathBuf, PathBuf, PathBuf),
   #[error("file \"{0}\": {1}")]
   UnableToRead(PathBuf, io::Error),
+  #[error("write file \"{0}\": {1}")]
+  Write(PathBuf, io::Error),
   #[error("read dir \"{0}\": {1}")]
   ReadDir(PathBuf, io::Error),
+  #[error("create dir \"{0}\": {1}")]
+  CreateDir(PathBuf, io::Error),
   #[error("clean dir \"{0}\": {1}")]
   CleanDir(PathBuf, io::Error),
+  #[error(
+    "task \"{0}\": can not resolve {{{{bin.{1}}}}}, register it or set '--bin-dir'"
+  )]
+  UnresolvedBin(PathBuf, String),
+  #[error(
+    "task \"{0}\": can not resolve {{{{dep.{1}}}}}, add the dependency into 'depends-on'"
+  )]
+  UnresolvedDep(PathBuf, String),
+  #[error("task \"{0}\": dependency \"{1}\" not found")]
+  DependencyNotFound(PathBuf, PathBuf),
+  #[error("dependency cycle: {}", .0.iter().map(|path| path.display()).join(" -> "))]
+  DependencyCycle(Vec<PathBuf>),
+  #[error("env file \"{0}\" line {1}: {2}")]
+  EnvFile(PathBuf, usize, &'static str),
+  #[error("task \"{0}\": can not resolve {{{{env.{1}}}}}")]
+  UnresolvedEnv(PathBuf, String),
+  #[error("task \"{0}\": can not resolve ${{{1}}}")]
+  UnresolvedVar(PathBuf, String),
+  #[error("task \"{0}\": {1}")]
+  AssertSugar(PathBuf, String),
+  #[error("config \"{0}\": retry-backoff-ms = {1} should be non-negative")]
+  NegativeBackoff(PathBuf, i64),
+  #[error("task \"{}\": envs cycle: {}", .0.display(), .1.join(" -> "))]
+  EnvCycle(PathBuf, Vec<String>),
+  #[error("golden generator of \"{0}\":\n{1}")]
+  GoldenGenerator(PathBuf, String),
+  #[error("config \"{0}\": 'golden-generator' can only be defined in __all__.toml")]
+  GoldenGeneratorNotAll(PathBuf),
+  #[error("file \"{0}\": {1}")]
+  Json(PathBuf, serde_json::Error),
   #[error("input extensions can not contains 'toml'")]
   InputExtToml,
 }
@@ -43,13 +120,18 @@ pub enum BuildError {
 #[derive(Debug)]
 pub(crate) enum FailedState {
   ReportSaved(PathBuf),
-  NoReport(PathBuf, Vec<AssertError>),
+  /// The input, its errors, the tails of `report-files`, and `max-report-bytes`
+  NoReport(PathBuf, Vec<AssertError>, String, usize),
 }
 pub(crate) enum State {
   Ok(Option<Duration>),
   Failed(Option<(FailedState, Duration)>),
+  /// Passed, but with the warnings of the `soft` goldens
+  Warned(FailedState, Duration),
   Ignored,
   FilteredOut,
+  /// Not run for the reason, e.g., its dependency failed
+  Skipped(String),
 }
 
 impl fmt::Display for FailedState {
@@ -58,8 +140,13 @@ impl fmt::Display for FailedState {
       Self::ReportSaved(report) => {
         write!(f, "\n     report: {}", report.display())
       }
-      Self::NoReport(input, errs) => {
-        write!(f, "\n----------- {} -----------\n{}", input.display(), DisplayErrs(errs))
+      Self::NoReport(input, errs, tails, max_report_bytes) => {
+        write!(
+          f,
+          "\n----------- {} -----------\n{}",
+          input.display(),
+          bounded_errs(errs, tails, *max_report_bytes, true)
+        )
       }
     }
   }
@@ -73,44 +160,611 @@ impl fmt::Display for State {
         write!(f, "{:.2}s {}", time.as_secs_f32(), "FAILED".red())
       }
       Self::Failed(None) => write!(f, "{}", "FAILED".red()),
+      Self::Warned(_, time) => {
+        write!(f, "{:.2}s {} ({})", time.as_secs_f32(), "ok".green(), "warned".yellow())
+      }
       Self::Ignored => write!(f, "{}", "ignored".yellow()),
       Self::FilteredOut => write!(f, "{}", "filtered out".bright_black()),
+      Self::Skipped(reason) => write!(f, "{} ({reason})", "skipped".cyan()),
+    }
+  }
+}
+
+impl FailedState {
+  /// The errors text, read back from the report if it is saved
+  fn errs_text(&self) -> String {
+    match self {
+      Self::ReportSaved(report) => std::fs::read_to_string(report)
+        .unwrap_or_else(|e| format!("read report \"{}\": {e}", report.display())),
+      Self::NoReport(_, errs, tails, max_report_bytes) => {
+        bounded_errs(errs, tails, *max_report_bytes, false)
+      }
     }
   }
 }
 
+/// The printed result of one task, the TAP test point number is assigned when printing
+struct ResultLine {
+  path: PathBuf,
+  ok: bool,
+  text: String,
+}
+
+impl ResultLine {
+  fn new(path: PathBuf, state: &State, args: &Args) -> Self {
+    let ok = !matches!(state, State::Failed(_));
+    let id = args.task_id(&path);
+    let text = match args.format {
+      Format::Pretty => format!("test {id} ... {state}\n"),
+      Format::Tap => match state {
+        State::Ignored => format!(" - {id} # SKIP ignored\n"),
+        State::FilteredOut => format!(" - {id} # SKIP filtered out\n"),
+        State::Skipped(reason) => format!(" - {id} # SKIP {reason}\n"),
+        State::Failed(Some((failed, _))) | State::Warned(failed, _) => {
+          let mut text = format!(" - {id}\n  ---\n  message: |\n");
+          for line in failed.errs_text().lines() {
+            text.push_str(&format!("    {line}\n"));
+          }
+          text.push_str("  ...\n");
+          text
+        }
+        _ => format!(" - {id}\n"),
+      },
+    };
+    Self { path, ok, text }
+  }
+  fn print(&self, format: Format, idx: usize) {
+    match format {
+      Format::Pretty => print_line(&self.text),
+      Format::Tap => print_line(&format!(
+        "{} {idx}{}",
+        if self.ok { "ok" } else { "not ok" },
+        self.text
+      )),
+    }
+  }
+}
+
+/// The in-place progress line of `--progress`
+struct Progress {
+  /// The discovered tasks, which grows while the discovery runs
+  total: AtomicUsize,
+  start: Instant,
+  /// The ids of the tasks that hold their permits
+  running: Mutex<Vec<String>>,
+}
+
+impl Progress {
+  /// Only draw on a terminal, so that the piped outputs will not change
+  fn new(args: &Args) -> Option<Self> {
+    (args.progress
+      && !args.no_capture
+      && matches!(args.format, Format::Pretty)
+      && io::stdout().is_terminal())
+    .then(|| Self {
+      total: AtomicUsize::new(0),
+      start: Instant::now(),
+      running: Mutex::new(Vec::new()),
+    })
+  }
+  fn discovered(&self) {
+    self.total.fetch_add(1, Ordering::Relaxed);
+  }
+  fn start(&self, id: String) {
+    self.running.lock().expect("progress lock").push(id);
+  }
+  fn finish(&self, id: &str) {
+    self
+      .running
+      .lock()
+      .expect("progress lock")
+      .retain(|running| running != id);
+  }
+  fn draw(&self, done: usize, failed: usize) {
+    let running = self.running.lock().expect("progress lock");
+    let line = progress_line(
+      done,
+      self.total.load(Ordering::Relaxed),
+      failed,
+      self.start.elapsed(),
+      &running,
+    );
+    print_line(&format!("{CLEAR_LINE}{line}"));
+  }
+  fn clear(&self) {
+    print_line(CLEAR_LINE);
+  }
+}
+
+fn progress_line(
+  done: usize,
+  total: usize,
+  failed: usize,
+  elapsed: Duration,
+  running: &[String],
+) -> String {
+  let width = total.to_string().len();
+  let mut line =
+    format!("[{done:>width$}/{total}] {failed} failed, {:.2}s", elapsed.as_secs_f32());
+  if !running.is_empty() {
+    line.push_str(", running: ");
+    line.push_str(&running.iter().take(PROGRESS_RUNNING).join(", "));
+    if running.len() > PROGRESS_RUNNING {
+      line.push_str(&format!(" (+{})", running.len() - PROGRESS_RUNNING));
+    }
+  }
+  line
+}
+
 pub(crate) struct TestResult {
+  format: Format,
+  summary: SummaryStyle,
+  count_ok: usize,
+  count_skipped: usize,
+  count_ignored: usize,
+  count_filtered: usize,
+  faileds: Vec<Failed>,
+  /// The passed tasks with the warnings of `soft` goldens
+  warneds: Vec<Failed>,
+  /// The ignored/filtered tasks denied by `--deny-ignored`/`--deny-filtered`
+  denieds: Vec<(PathBuf, &'static str)>,
+  /// The results grouped by directory, see `--group-depth`
+  groups: BTreeMap<String, Group>,
+  /// The comparison with `--baseline`
+  baseline: Option<BaselineReport>,
+  /// The ignored and filtered out tasks, see `--show-skipped`
+  skipped: Option<SkippedReport>,
+  /// The golden files that no assert references, see `--check-unused-goldens`
+  unused_goldens: Vec<PathBuf>,
+  /// The total size of the retained workdirs, see `--retain`
+  retained: u64,
+  workdir: &'static Path,
+  /// The base of `{{seed}}`, only for `--random-seed`
+  seed: Option<u64>,
+}
+
+/// A failed task of the failures section
+pub(crate) struct Failed {
+  id: String,
+  state: FailedState,
+  /// The errors text without task-specific paths and names, `None` to not dedupe
+  fingerprint: Option<String>,
+}
+
+/// The errors text without the task's workdir, path, and name, so that
+/// the identical failures, e.g., of a broken binary, have the same fingerprint
+fn fingerprint(errs_text: &str, path: &Path, args: &Args) -> String {
+  let relative = args.relative_path(path);
+  let workdir = args.task_workdir(path).display().to_string();
+  let file_name = path.file_name().map(|s| s.to_string_lossy()).unwrap_or_default();
+  let name = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+  let text = errs_text
+    .replace(&workdir, "{{workdir}}")
+    .replace(&path.display().to_string(), "{{path}}")
+    .replace(relative, "{{path}}")
+    .replace(&*file_name, "{{file}}")
+    .replace(&task_seed(path, args).to_string(), "{{seed}}");
+  match regex::Regex::new(&format!(r"\b{}\b", regex::escape(&name))) {
+    Ok(re) if !name.is_empty() => re.replace_all(&text, "{{name}}").into_owned(),
+    _ => text,
+  }
+}
+
+/// The max ids listed for the identical failures
+const DEDUPE_LISTED: usize = 10;
+
+/// Group the failures with the same fingerprint in the first-seen order,
+/// each one is the first failure and the ids of the others
+fn dedupe_faileds(faileds: &[Failed]) -> Vec<(&Failed, Vec<&str>)> {
+  let mut deduped: Vec<(&Failed, Vec<&str>)> = Vec::new();
+  let mut seen = HashMap::<&str, usize>::new();
+  for failed in faileds {
+    if let Some(fingerprint) = failed.fingerprint.as_deref() {
+      if let Some(&idx) = seen.get(fingerprint) {
+        deduped[idx].1.push(failed.id.as_str());
+        continue;
+      }
+      seen.insert(fingerprint, deduped.len());
+    }
+    deduped.push((failed, Vec::new()));
+  }
+  deduped
+}
+
+fn fmt_identical(others: &[&str]) -> String {
+  let mut s = format!(
+    "\n     ... and {} other test{} failed identically: {}",
+    others.len(),
+    if others.len() == 1 { "" } else { "s" },
+    others.iter().take(DEDUPE_LISTED).join(", ")
+  );
+  if others.len() > DEDUPE_LISTED {
+    s.push_str(&format!(", ... ({} more)", others.len() - DEDUPE_LISTED));
+  }
+  s
+}
+
+/// The results of the tasks in the same directory at `--group-depth` under rootdir
+#[derive(Debug, Default)]
+struct Group {
   count_ok: usize,
+  count_failed: usize,
+  count_skipped: usize,
   count_ignored: usize,
   count_filtered: usize,
-  faileds: Vec<FailedState>,
+  time: Duration,
+}
+
+/// The groups sorted by the failures descending, then by the names
+fn by_failures(groups: &BTreeMap<String, Group>) -> Vec<(&String, &Group)> {
+  let mut groups: Vec<_> = groups.iter().collect();
+  // stable, so that the ties keep the name order
+  groups.sort_by_key(|(_, group)| Reverse(group.count_failed));
+  groups
+}
+
+/// Each group with failures takes one line, the most failed first,
+/// the others are collapsed to one line
+fn fmt_groups(groups: &BTreeMap<String, Group>) -> String {
+  let mut s = String::from("groups:");
+  let mut others = Group::default();
+  let mut other_names = Vec::new();
+  for (name, group) in by_failures(groups) {
+    if group.count_failed == 0 {
+      others.count_ok += group.count_ok;
+      others.count_skipped += group.count_skipped;
+      others.count_ignored += group.count_ignored;
+      others.count_filtered += group.count_filtered;
+      others.time += group.time;
+      other_names.push(name.as_str());
+    } else {
+      s.push_str(&format!(
+        "\n     {name}: {} failed; {} passed; {} skipped; {} ignored; {} filtered out; {:.2}s",
+        group.count_failed,
+        group.count_ok,
+        group.count_skipped,
+        group.count_ignored,
+        group.count_filtered,
+        group.time.as_secs_f32()
+      ));
+    }
+  }
+  if !other_names.is_empty() {
+    s.push_str(&format!(
+      "\n     {} ({}): ok. {} passed; {} skipped; {} ignored; {} filtered out; {:.2}s",
+      other_names.join(", "),
+      other_names.len(),
+      others.count_ok,
+      others.count_skipped,
+      others.count_ignored,
+      others.count_filtered,
+      others.time.as_secs_f32()
+    ));
+  }
+  s
 }
 
-pub struct TestExitCode(Result<TestResult, Vec<BuildError>>, Instant);
+/// The aligned table of `--summary table`, the total row and then a row per group
+fn fmt_table(total: &Group, groups: &BTreeMap<String, Group>, color: bool) -> String {
+  const HEADER: [&str; 6] =
+    ["passed", "failed", "skipped", "ignored", "filtered", "time"];
+  let cells = |group: &Group| {
+    [
+      group.count_ok.to_string(),
+      group.count_failed.to_string(),
+      group.count_skipped.to_string(),
+      group.count_ignored.to_string(),
+      group.count_filtered.to_string(),
+      format!("{:.2}s", group.time.as_secs_f32()),
+    ]
+  };
+  // a single group is the same as the total
+  let rows: Vec<_> = core::iter::once(("total", total))
+    .chain(
+      by_failures(groups)
+        .into_iter()
+        .filter(|_| groups.len() > 1)
+        .map(|(name, group)| (name.as_str(), group)),
+    )
+    .map(|(name, group)| (name, group, cells(group)))
+    .collect();
+  let name_width = rows.iter().map(|(name, ..)| name.chars().count()).max().unwrap_or(0);
+  let widths: Vec<_> = (0..HEADER.len())
+    .map(|idx| {
+      rows
+        .iter()
+        .map(|(.., cells)| cells[idx].len())
+        .fold(HEADER[idx].len(), usize::max)
+    })
+    .collect();
+  let mut s = format!("{:name_width$}", "");
+  for (cell, width) in HEADER.iter().zip(&widths) {
+    let cell = format!("  {cell:>width$}");
+    s.push_str(&if color { cell.bold().to_string() } else { cell });
+  }
+  for (name, group, cells) in &rows {
+    s.push_str(&format!("\n{name:name_width$}"));
+    for (idx, (cell, width)) in cells.iter().zip(&widths).enumerate() {
+      let cell = format!("  {cell:>width$}");
+      s.push_str(&match idx {
+        0 if color && group.count_ok != 0 => cell.green().to_string(),
+        1 if color && group.count_failed != 0 => cell.red().to_string(),
+        _ => cell,
+      });
+    }
+  }
+  s
+}
+
+/// The ignored and filtered out tasks of `--show-skipped`
+pub(crate) struct SkippedReport {
+  show: ShowSkipped,
+  ignoreds: Vec<String>,
+  /// The top-level directories and the ids of the filtered out tasks
+  filtereds: Vec<(String, String)>,
+}
+
+impl SkippedReport {
+  fn is_empty(&self) -> bool {
+    self.ignoreds.is_empty() && self.filtereds.is_empty()
+  }
+  /// The report lines, each one is prefixed with `prefix`, e.g., `# ` for TAP
+  fn lines(&self, prefix: &str) -> String {
+    let mut s = String::new();
+    if !self.ignoreds.is_empty() {
+      s.push_str(&format!("{prefix}ignored:\n"));
+      for id in &self.ignoreds {
+        s.push_str(&format!("{prefix}     {id}\n"));
+      }
+    }
+    if !self.filtereds.is_empty() {
+      s.push_str(&format!("{prefix}filtered out:\n"));
+      match self.show {
+        ShowSkipped::Dirs => {
+          let mut counts = BTreeMap::<_, usize>::new();
+          for (dir, _) in &self.filtereds {
+            *counts.entry(dir).or_default() += 1;
+          }
+          for (dir, count) in counts {
+            s.push_str(&format!("{prefix}     {dir}: {count}\n"));
+          }
+        }
+        ShowSkipped::All => {
+          for (_, id) in &self.filtereds {
+            s.push_str(&format!("{prefix}     {id}\n"));
+          }
+        }
+      }
+    }
+    s
+  }
+}
+
+fn fmt_unused_goldens(goldens: &[PathBuf]) -> String {
+  let mut s = String::from("unused goldens:");
+  for golden in goldens {
+    s.push_str(&format!("\n     {}", golden.display()));
+  }
+  s
+}
+
+/// The reports of the passed tasks with warnings
+fn fmt_warneds(warneds: &[Failed]) -> String {
+  let mut s = String::from("warnings:");
+  for warned in warneds {
+    s.push_str(&warned.state.to_string());
+  }
+  s
+}
+
+/// The exit status of a run, it is reported as the process exit code.
+/// A panic keeps Rust's default exit code `101`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestStatus {
+  /// All tasks passed, or there is no test to run, exit code `0`
+  Passed,
+  /// Some tasks failed or are denied, exit code `1`
+  Failed,
+  /// Fail to build the tasks, e.g., a malformed toml, exit code `2`
+  BuildError,
+}
+
+impl TestStatus {
+  /// The process exit code
+  pub const fn code(self) -> u8 {
+    match self {
+      Self::Passed => 0,
+      Self::Failed => 1,
+      Self::BuildError => 2,
+    }
+  }
+}
+
+impl From<TestStatus> for ExitCode {
+  fn from(status: TestStatus) -> Self {
+    Self::from(status.code())
+  }
+}
+
+impl TestResult {
+  fn status(&self) -> TestStatus {
+    if self.faileds.is_empty()
+      && self.denieds.is_empty()
+      && !self.baseline.as_ref().is_some_and(BaselineReport::failed)
+    {
+      TestStatus::Passed
+    } else {
+      TestStatus::Failed
+    }
+  }
+}
+
+/// `Ok(None)` means there is no test to run, e.g., run a subcommand
+pub struct TestExitCode(Result<Option<TestResult>, Vec<BuildError>>, Instant);
+
+impl TestExitCode {
+  /// The status that will be reported as the exit code
+  pub fn status(&self) -> TestStatus {
+    match &self.0 {
+      Ok(None) => TestStatus::Passed,
+      Ok(Some(result)) => result.status(),
+      Err(_) => TestStatus::BuildError,
+    }
+  }
+}
 
 impl Termination for TestExitCode {
   fn report(self) -> ExitCode {
     let time = self.1.elapsed().as_secs_f32();
+    let status = self.status();
     match self.0 {
-      Ok(TestResult { count_ok, count_ignored, count_filtered, faileds }) => {
+      Ok(None) => {}
+      Ok(Some(TestResult {
+        format: Format::Tap,
+        summary: _,
+        count_ok,
+        count_skipped,
+        count_ignored,
+        count_filtered,
+        faileds,
+        warneds: _,
+        denieds,
+        groups: _,
+        baseline,
+        skipped,
+        unused_goldens,
+        retained,
+        workdir,
+        seed,
+      })) => {
+        // the failures are already in the YAML diagnostics
+        let failed_num = faileds.len();
+        for (path, reason) in &denieds {
+          println!("# denied: {} is {reason}", path.display());
+        }
+        for golden in &unused_goldens {
+          println!("# unused golden: {}", golden.display());
+        }
+        if let Some(baseline) = &baseline {
+          print!("{}", baseline.lines("# "));
+        }
+        if let Some(skipped) = &skipped {
+          print!("{}", skipped.lines("# "));
+        }
+        println!("# {}", fmt_retained(retained, workdir));
+        if let Some(seed) = seed {
+          println!("# {}", fmt_seed(seed));
+        }
+        println!(
+          "# test result: {}. {count_ok} passed; {failed_num} failed; {count_skipped} skipped; {count_ignored} ignored; {count_filtered} filtered out; finished in {time:.2}s",
+          if status == TestStatus::Passed { "ok" } else { "FAILED" }
+        );
+      }
+      Ok(Some(TestResult {
+        format: Format::Pretty,
+        summary,
+        count_ok,
+        count_skipped,
+        count_ignored,
+        count_filtered,
+        faileds,
+        warneds,
+        denieds,
+        groups,
+        baseline,
+        skipped,
+        unused_goldens,
+        retained,
+        workdir,
+        seed,
+      })) => {
         println!();
         let failed_num = faileds.len();
-        if failed_num == 0 {
-          println!(
-            "test result: {}. {count_ok} passed; {failed_num} failed; {count_ignored} ignored; {count_filtered} filtered out; finished in {time:.2}s",
-            State::Ok(None)
-          );
-          ExitCode::SUCCESS
+        let table = (summary == SummaryStyle::Table).then(|| {
+          let total = Group {
+            count_ok,
+            count_failed: failed_num,
+            count_skipped,
+            count_ignored,
+            count_filtered,
+            time: self.1.elapsed(),
+          };
+          fmt_table(&total, &groups, colored::control::SHOULD_COLORIZE.should_colorize())
+        });
+        let baseline = baseline.filter(|baseline| !baseline.is_empty());
+        let skipped = skipped.filter(|skipped| !skipped.is_empty());
+        if status == TestStatus::Passed {
+          if !warneds.is_empty() {
+            println!("{}\n", fmt_warneds(&warneds));
+          }
+          if let Some(skipped) = &skipped {
+            println!("{}", skipped.lines(""));
+          }
+          if !unused_goldens.is_empty() {
+            println!("{}\n", fmt_unused_goldens(&unused_goldens));
+          }
+          if let Some(baseline) = &baseline {
+            println!("{}", baseline.lines(""));
+          }
+          println!("{}", fmt_retained(retained, workdir));
+          if let Some(seed) = seed {
+            println!("{}", fmt_seed(seed));
+          }
+          if let Some(table) = table {
+            println!("test result: {}.\n{table}", State::Ok(None));
+          } else {
+            println!(
+              "test result: {}. {count_ok} passed; {failed_num} failed; {count_skipped} skipped; {count_ignored} ignored; {count_filtered} filtered out; finished in {time:.2}s",
+              State::Ok(None)
+            );
+          }
         } else {
-          eprint!("failures:");
-          for failed in &faileds {
-            eprint!("{failed}");
+          // only worth it when the failures spread over the groups,
+          // the table already has them
+          if failed_num != 0 && groups.len() > 1 && table.is_none() {
+            eprintln!("{}\n", fmt_groups(&groups));
+          }
+          if !warneds.is_empty() {
+            eprintln!("{}\n", fmt_warneds(&warneds));
+          }
+          if let Some(skipped) = &skipped {
+            eprintln!("{}", skipped.lines(""));
+          }
+          if !unused_goldens.is_empty() {
+            eprintln!("{}\n", fmt_unused_goldens(&unused_goldens));
+          }
+          if failed_num != 0 {
+            eprint!("failures:");
+            for (failed, others) in dedupe_faileds(&faileds) {
+              eprint!("{}", failed.state);
+              if !others.is_empty() {
+                eprint!("{}", fmt_identical(&others));
+              }
+            }
+          }
+          if !denieds.is_empty() {
+            eprint!("{}denied:", if failed_num == 0 { "" } else { "\n\n" });
+            for (path, reason) in &denieds {
+              eprint!("\n     {} is {reason}", path.display());
+            }
+          }
+          if let Some(baseline) = &baseline {
+            let separator =
+              if failed_num == 0 && denieds.is_empty() { "" } else { "\n\n" };
+            eprint!("{separator}{}", baseline.lines("").trim_end());
+          }
+          eprint!("\n\n{}", fmt_retained(retained, workdir));
+          if let Some(seed) = seed {
+            eprint!("\n{}", fmt_seed(seed));
+          }
+          if let Some(table) = table {
+            eprintln!("\n\ntest result: {}.\n{table}", State::Failed(None));
+          } else {
+            eprintln!(
+              "\n\ntest result: {}. {count_ok} passed; {failed_num} failed; {count_skipped} skipped; {count_ignored} ignored; {count_filtered} filtered out; finished in {time:.2}s",
+              State::Failed(None)
+            );
           }
-          eprintln!(
-            "\n\ntest result: {}. {count_ok} passed; {failed_num} failed; {count_ignored} ignored; {count_filtered} filtered out; finished in {time:.2}s",
-            State::Failed(None)
-          );
-          ExitCode::FAILURE
         }
       }
       Err(build_errs) => {
@@ -118,18 +772,22 @@ impl Termination for TestExitCode {
         for err in &build_errs {
           eprintln!("{err}");
         }
-        ExitCode::FAILURE
       }
     }
+    status.into()
   }
 }
 
 impl Args {
   pub async fn test(self) -> TestExitCode {
     let now = Instant::now();
+    if let Some(Command::Schema) = self.command {
+      print!("{}", config_schema());
+      return TestExitCode(Ok(None), now);
+    }
     TestExitCode(
       match self.rebuild() {
-        Ok(args) => _test(args).await,
+        Ok(args) => _test(args).await.map(Some),
         Err(e) => Err(vec![e]),
       },
       now,
@@ -137,145 +795,2214 @@ impl Args {
   }
 }
 async fn _test(args: &'static Args) -> Result<TestResult, Vec<BuildError>> {
-  let f1 = async {
-    if args.workdir.exists() {
-      remove_dir_all(&args.workdir)
-        .await
-        .map_err(|e| BuildError::CleanDir(args.workdir.to_path_buf(), e))
-    } else {
-      Ok(())
-    }
-  };
-  let f2 = walk(FullConfig::new(args), args.rootdir.to_path_buf(), args);
-  // walkthrough all config
-  let (clean_dir, file_configs) = tokio::join!(f1, f2);
-  if let Err(e) = clean_dir {
-    return Err(vec![e]);
-  }
-  let file_configs = file_configs?;
-  let faileds = Arc::new(Mutex::new(Vec::with_capacity(file_configs.len())));
-  let scheduler = Arc::new(Semaphore::new(args.permits as usize));
-  let handles: Vec<_> = file_configs
-    .into_iter()
-    .map(|(path, config)| {
-      let scheduler = scheduler.clone();
-      let faileds = faileds.clone();
-      tokio::spawn(async move {
-        let _permit = scheduler
-          .acquire_many(*config.permit)
-          .await
-          .expect("Semaphore closed");
-        let state = config.test(&path, args).await;
-        println!("test {} ... {}", path.display(), state);
-        match state {
-          State::Ok(Some(_)) => (1, 0, 0),
-          State::Failed(Some((failed, _))) => {
-            faileds.lock().await.push(failed);
-            (0, 0, 0)
-          }
-          State::Ok(None) | State::Failed(None) => unreachable!(),
-          State::Ignored => (0, 1, 0),
-          State::FilteredOut => (0, 0, 1),
-        }
-      })
-    })
-    .collect();
+  let now = Instant::now();
+  // discover the tasks in the background, they are streamed into the spawner
+  // as they are found, so that the discovery overlaps the execution
+  let (sender, mut discovered) = mpsc::unbounded_channel();
+  let mut walker = tokio::spawn(walk(
+    Arc::new(FullConfig::new(args)),
+    args.rootdir.to_path_buf(),
+    args.rootdir_abs.to_path_buf(),
+    args,
+    sender,
+  ));
+  if args.workdir.exists()
+    && let Err(e) = remove_dir_all(&args.workdir).await
+  {
+    walker.abort();
+    return Err(vec![BuildError::CleanDir(args.workdir.to_path_buf(), e)]);
+  }
+  let progress = Progress::new(args).map(Arc::new);
+  let mut spawner = Spawner::new(args, progress.clone());
+  let mut walked = false;
+  // checked as the tasks stream in, a colliding task is never spawned
+  let mut workdirs = Workdirs::new(args);
+  let mut collisions = Vec::new();
+  // the dependency checks need all the tasks, done once the discovery finished,
+  // the tasks in a cycle or with a missing dependency are pending until then
+  let mut graph = Vec::new();
+  let mut dumps = Vec::new();
+  // the semaphore is fair, the tasks acquire their permits in the spawn order,
+  // so `--heavy-first` has to wait for all the tasks to sort them
+  let mut heavies = Vec::new();
   let mut count_ok = 0;
+  let mut count_skipped = 0;
   let mut count_ignored = 0;
   let mut count_filtered = 0;
-  for handle in handles {
-    let (ok, ignored, filtered) = handle.await.unwrap();
-    count_ok += ok;
-    count_ignored += ignored;
-    count_filtered += filtered;
+  let mut denieds = Vec::new();
+  let mut faileds = Vec::new();
+  let mut warneds = Vec::new();
+  let mut last_failed = String::new();
+  // the ids and errors text of failed tasks, for `--summary-md`
+  let mut summary_faileds = Vec::new();
+  // the ids and reasons of skipped tasks, for `--summary-md`
+  let mut summary_skippeds = Vec::new();
+  // the rows of all tasks but the filtered out ones, for `--html`
+  let mut html_rows = Vec::new();
+  // the ignored and filtered out tasks, for `--show-skipped`
+  let mut ignoreds = Vec::new();
+  let mut filtereds = Vec::new();
+  // whether all tasks of each directory ran, for `--check-unused-goldens`
+  let mut golden_dirs = BTreeMap::<PathBuf, bool>::new();
+  let mut buffered = Vec::new();
+  let mut printed = 0;
+  let mut done = 0;
+  let mut groups = BTreeMap::<_, Group>::new();
+  // the durations of the executed tasks, for `--timings` and `--baseline`
+  let mut timings = Timings::new();
+  let mut ticker = tokio::time::interval(PROGRESS_INTERVAL);
+  // stream the results in completion order, only this task writes to stdout
+  while !walked || !spawner.handles.is_empty() {
+    let res = tokio::select! {
+      task = discovered.recv(), if !walked => {
+        match task {
+          Some((path, config)) => {
+            if !config.skipped()
+              && let Err(e) = workdirs.insert(&path)
+            {
+              collisions.push(e);
+            }
+            graph.push((path.clone(), config.dependencies.clone()));
+            if args.dump_config.is_some() {
+              dumps.push((path.clone(), config.clone()));
+            }
+            // nothing is spawned after a collision, the run will be aborted
+            if collisions.is_empty() {
+              // `--no-capture` waits for all the tasks to count the selected ones
+              if args.heavy_first || args.no_capture {
+                heavies.push((path, config));
+              } else {
+                spawner.push(path, config);
+              }
+            }
+          }
+          None => {
+            walked = true;
+            // the same checks in the same order as before any task runs
+            let errs = match (&mut walker).await.expect("join handle") {
+              Ok(()) if collisions.is_empty() => dependency_errs(&graph),
+              Ok(()) => core::mem::take(&mut collisions),
+              Err(errs) => errs,
+            };
+            if !errs.is_empty() {
+              spawner.abort().await;
+              return Err(errs);
+            }
+            if let Some(file) = &args.dump_config {
+              dumps.sort_unstable_by(|(p1, _), (p2, _)| p1.cmp(p2));
+              dump_configs(file, &dumps).map_err(|e| vec![e])?;
+            }
+            if args.heavy_first {
+              heavies.sort_by_key(|(_, config)| Reverse(config.permit()));
+            }
+            if args.no_capture {
+              no_capture(&mut heavies);
+            }
+            for (path, config) in heavies.drain(..) {
+              spawner.push(path, config);
+            }
+            spawner.walked(&graph);
+          }
+        }
+        continue;
+      }
+      Some(res) = spawner.handles.join_next() => res,
+      _ = ticker.tick(), if progress.is_some() => {
+        if let Some(progress) = &progress {
+          progress.draw(done, faileds.len());
+        }
+        continue;
+      }
+    };
+    let (path, state) = res.expect("join handle");
+    done += 1;
+    let line = ResultLine::new(path, &state, args);
+    let group = if args.group_depth == 0 {
+      None
+    } else {
+      Some(groups.entry(args.group(&line.path)).or_default())
+    };
+    match &state {
+      State::Ok(Some(time)) | State::Failed(Some((_, time))) | State::Warned(_, time) => {
+        timings.insert(args.relative_path(&line.path).to_owned(), time.as_secs_f64());
+      }
+      _ => {}
+    }
+    if args.golden_refs.is_some()
+      && let Some(dir) = line.path.parent()
+    {
+      let ran = matches!(state, State::Ok(_) | State::Failed(_) | State::Warned(..));
+      *golden_dirs.entry(dir.to_path_buf()).or_insert(true) &= ran;
+    }
+    match state {
+      State::Ok(Some(time)) => {
+        count_ok += 1;
+        if let Some(group) = group {
+          group.count_ok += 1;
+          group.time += time;
+        }
+        if args.html.is_some() {
+          html_rows.push(HtmlRow {
+            id: args.task_id(&line.path),
+            status: "passed",
+            time: Some(time),
+            details: String::new(),
+          });
+        }
+      }
+      State::Failed(Some((failed, time))) => {
+        if let Some(group) = group {
+          group.count_failed += 1;
+          group.time += time;
+        }
+        if let Ok(path_abs) = std::fs::canonicalize(&line.path) {
+          last_failed.push_str(&format!("{}\n", path_abs.display()));
+        }
+        let id = args.task_id(&line.path);
+        let dedupe = !args.no_dedupe_failures && matches!(args.format, Format::Pretty);
+        let errs_text = if dedupe || args.summary_md.is_some() || args.html.is_some() {
+          failed.errs_text()
+        } else {
+          String::new()
+        };
+        let fingerprint = dedupe.then(|| fingerprint(&errs_text, &line.path, args));
+        if args.html.is_some() {
+          html_rows.push(HtmlRow {
+            id: id.clone(),
+            status: "failed",
+            time: Some(time),
+            details: errs_text.clone(),
+          });
+        }
+        if args.summary_md.is_some() {
+          summary_faileds.push((id.clone(), errs_text));
+        }
+        faileds.push(Failed { id, state: failed, fingerprint })
+      }
+      State::Warned(warned, time) => {
+        count_ok += 1;
+        if let Some(group) = group {
+          group.count_ok += 1;
+          group.time += time;
+        }
+        if args.html.is_some() {
+          html_rows.push(HtmlRow {
+            id: args.task_id(&line.path),
+            status: "warned",
+            time: Some(time),
+            details: warned.errs_text(),
+          });
+        }
+        warneds.push(Failed {
+          id: args.task_id(&line.path),
+          state: warned,
+          fingerprint: None,
+        });
+      }
+      State::Ok(None) | State::Failed(None) => unreachable!(),
+      State::Ignored => {
+        count_ignored += 1;
+        if let Some(group) = group {
+          group.count_ignored += 1;
+        }
+        if args.deny_ignored {
+          denieds.push((line.path.clone(), "ignored"));
+        }
+        if args.show_skipped.is_some() {
+          ignoreds.push(args.task_id(&line.path));
+        }
+        if args.html.is_some() {
+          html_rows.push(HtmlRow {
+            id: args.task_id(&line.path),
+            status: "ignored",
+            time: None,
+            details: String::new(),
+          });
+        }
+      }
+      State::FilteredOut => {
+        count_filtered += 1;
+        if let Some(group) = group {
+          group.count_filtered += 1;
+        }
+        if args.deny_filtered {
+          denieds.push((line.path.clone(), "filtered out"));
+        }
+        if args.show_skipped.is_some() {
+          filtereds.push((args.dir_at(&line.path, 1), args.task_id(&line.path)));
+        }
+      }
+      State::Skipped(reason) => {
+        count_skipped += 1;
+        if let Some(group) = group {
+          group.count_skipped += 1;
+        }
+        if args.html.is_some() {
+          html_rows.push(HtmlRow {
+            id: args.task_id(&line.path),
+            status: "skipped",
+            time: None,
+            details: reason.clone(),
+          });
+        }
+        if args.summary_md.is_some() {
+          summary_skippeds.push((args.task_id(&line.path), reason));
+        }
+      }
+    }
+    if args.sort_output {
+      buffered.push(line);
+    } else {
+      if let Some(progress) = &progress {
+        progress.clear();
+      }
+      printed += 1;
+      line.print(args.format, printed);
+    }
+    if let Some(progress) = &progress {
+      progress.draw(done, faileds.len());
+    }
+  }
+  if let Some(progress) = &progress {
+    progress.clear();
+  }
+  spawner.scheduler.close();
+  let mut unused_goldens = Vec::new();
+  if let Some(refs) = &args.golden_refs {
+    // only the directories whose tasks all ran, otherwise the goldens of the
+    // filtered out, ignored, or skipped tasks are not referenced
+    for (dir, _) in golden_dirs.into_iter().filter(|(_, ran)| *ran) {
+      let golden_dir = dir.join(GOLDEN_DIR);
+      if !golden_dir.is_dir() {
+        continue;
+      }
+      let files = tree_files(&golden_dir, None)
+        .map_err(|e| vec![BuildError::UnableToRead(golden_dir.clone(), e)])?;
+      unused_goldens.extend(
+        files
+          .into_iter()
+          .map(|file| golden_dir.join(file))
+          .filter(|golden| !refs.references(golden)),
+      );
+    }
+    if args.deny_unused_goldens {
+      denieds.extend(unused_goldens.drain(..).map(|golden| (golden, "unused")));
+    }
+  }
+  // the reported lists are in path order, rather than in completion order
+  faileds.sort_by(|f1, f2| f1.id.cmp(&f2.id));
+  warneds.sort_by(|w1, w2| w1.id.cmp(&w2.id));
+  denieds.sort();
+  summary_faileds.sort();
+  summary_skippeds.sort();
+  html_rows.sort_by(|r1, r2| r1.id.cmp(&r2.id));
+  let last_failed_file = args.workdir.join(LAST_FAILED);
+  if let Err(e) = std::fs::create_dir_all(&args.workdir)
+    .and_then(|_| std::fs::write(&last_failed_file, last_failed))
+  {
+    return Err(vec![BuildError::Write(last_failed_file, e)]);
+  }
+  if let Some(file) = &args.summary_md {
+    let summary = Summary {
+      count_ok,
+      count_ignored,
+      count_filtered,
+      faileds: &summary_faileds,
+      skippeds: &summary_skippeds,
+      denieds: &denieds,
+      time: now.elapsed(),
+      budget: args.summary_md_budget,
+    };
+    if let Err(e) = std::fs::write(file, summary.to_markdown()) {
+      return Err(vec![BuildError::Write(file.to_path_buf(), e)]);
+    }
+  }
+  if let Some(file) = &args.html {
+    let report = HtmlReport {
+      count_ok,
+      count_failed: faileds.len(),
+      count_skipped,
+      count_ignored,
+      count_filtered,
+      rows: &html_rows,
+      denieds: &denieds,
+      time: now.elapsed(),
+    };
+    if let Err(e) = std::fs::write(file, report.to_html()) {
+      return Err(vec![BuildError::Write(file.to_path_buf(), e)]);
+    }
+  }
+  if let Some(file) = &args.timings {
+    write_timings(file, &timings).map_err(|e| vec![e])?;
+  }
+  let retained = dir_size(&args.workdir);
+  let skipped = args.show_skipped.map(|show| {
+    ignoreds.sort();
+    filtereds.sort_by(|(_, id1), (_, id2)| id1.cmp(id2));
+    SkippedReport { show, ignoreds, filtereds }
+  });
+  let baseline = args.baseline_timings.as_ref().map(|baseline| {
+    BaselineReport::new(baseline, &timings, args.baseline_tolerance, args.baseline_strict)
+  });
+  buffered.sort_unstable_by(|l1, l2| l1.path.cmp(&l2.path));
+  for line in &buffered {
+    printed += 1;
+    line.print(args.format, printed);
+  }
+  // the plan is only known at the end, which is also allowed by TAP
+  if let Format::Tap = args.format {
+    print_line(&format!("1..{done}\n"));
   }
-  scheduler.close();
   Ok(TestResult {
+    format: args.format,
+    summary: args.summary,
     count_ok,
+    count_skipped,
     count_ignored,
     count_filtered,
-    faileds: Arc::try_unwrap(faileds).unwrap().into_inner(),
+    faileds,
+    warneds,
+    denieds,
+    groups,
+    baseline,
+    skipped,
+    unused_goldens,
+    retained,
+    workdir: &args.workdir,
+    seed: args.random_seed.then_some(args.seed),
   })
 }
 
+/// The outcome of a task that its dependents wait for, or why it did not pass
+type Outcome = Option<Result<(), &'static str>>;
+
+/// Spawn the discovered tasks, a task is pending until all its dependencies are
+/// spawned, so that the tasks in a dependency cycle are never spawned
+struct Spawner {
+  args: &'static Args,
+  handles: JoinSet<(PathBuf, State)>,
+  scheduler: Arc<Semaphore>,
+  /// Sorted by the names, so that all tasks acquire them in the same order
+  resource_schedulers: Arc<BTreeMap<&'static str, Semaphore>>,
+  io_scheduler: Option<Arc<Semaphore>>,
+  progress: Option<Arc<Progress>>,
+  /// The outcomes of the spawned tasks, which their dependents wait for
+  outcomes: HashMap<PathBuf, watch::Receiver<Outcome>>,
+  pending: Vec<(PathBuf, FullConfig)>,
+  /// The tasks in others' `depends-on`, sent once the discovery finished,
+  /// their dependents may read their workdirs, so they are always retained
+  dependencies: watch::Sender<Option<Arc<HashSet<PathBuf>>>>,
+}
+
+impl Spawner {
+  fn new(args: &'static Args, progress: Option<Arc<Progress>>) -> Self {
+    Self {
+      args,
+      handles: JoinSet::new(),
+      scheduler: Arc::new(Semaphore::new(args.permits as usize)),
+      resource_schedulers: Arc::new(
+        args
+          .resources
+          .iter()
+          .map(|(name, total)| (name.as_str(), Semaphore::new(*total as usize)))
+          .collect(),
+      ),
+      io_scheduler: args
+        .io_permits
+        .map(|io_permits| Arc::new(Semaphore::new(io_permits as usize))),
+      progress,
+      outcomes: HashMap::new(),
+      pending: Vec::new(),
+      dependencies: watch::Sender::new(None),
+    }
+  }
+  fn push(&mut self, path: PathBuf, config: FullConfig) {
+    if let Some(progress) = &self.progress {
+      progress.discovered();
+    }
+    if !self.ready(&config) {
+      self.pending.push((path, config));
+      return;
+    }
+    self.spawn(path, config);
+    // the pending tasks may be ready now
+    while let Some(idx) = self.pending.iter().position(|(_, config)| self.ready(config)) {
+      let (path, config) = self.pending.swap_remove(idx);
+      self.spawn(path, config);
+    }
+  }
+  fn ready(&self, config: &FullConfig) -> bool {
+    config.dependencies.iter().all(|dep| self.outcomes.contains_key(dep))
+  }
+  /// All tasks are discovered and valid, so none is pending
+  fn walked(&self, graph: &[(PathBuf, Vec<PathBuf>)]) {
+    debug_assert!(self.pending.is_empty());
+    let dependencies = graph.iter().flat_map(|(_, deps)| deps.iter().cloned()).collect();
+    self.dependencies.send_replace(Some(Arc::new(dependencies)));
+  }
+  /// Stop the spawned tasks, e.g., on the build errors found after the discovery
+  async fn abort(&mut self) {
+    self.handles.abort_all();
+    while self.handles.join_next().await.is_some() {}
+  }
+  fn spawn(&mut self, path: PathBuf, config: FullConfig) {
+    let args = self.args;
+    let (done, outcome) = watch::channel(None);
+    self.outcomes.insert(path.clone(), outcome);
+    let dependencies: Vec<_> = config
+      .dependencies
+      .iter()
+      .map(|dep| (dep.clone(), self.outcomes[dep].clone()))
+      .collect();
+    let scheduler = self.scheduler.clone();
+    let resource_schedulers = self.resource_schedulers.clone();
+    let io_scheduler = self.io_scheduler.clone();
+    let progress = self.progress.clone();
+    let mut dependents = self.dependencies.subscribe();
+    self.handles.spawn(async move {
+      // wait for the dependencies before acquiring anything, they are spawned
+      // before, and the dependency graph is acyclic, so they will finish
+      for (dep, mut outcome) in dependencies {
+        let outcome = outcome
+          .wait_for(Option::is_some)
+          .await
+          .map_or(Err("failed"), |outcome| outcome.expect("waited"));
+        if let Err(reason) = outcome {
+          done.send_replace(Some(Err("is skipped")));
+          let state =
+            State::Skipped(format!("dependency \"{}\" {reason}", dep.display()));
+          return (path, state);
+        }
+      }
+      // acquire the resources before the permit, so that the task waiting for
+      // a resource does not hold the permit
+      let mut _resources = Vec::new();
+      for (name, resource_scheduler) in resource_schedulers.iter() {
+        if let Some(count) = config.resource(name) {
+          _resources.push(
+            resource_scheduler
+              .acquire_many(count)
+              .await
+              .expect("Semaphore closed"),
+          );
+        }
+      }
+      let _permit = scheduler
+        .acquire_many(config.permit())
+        .await
+        .expect("Semaphore closed");
+      let id = progress.as_ref().map(|progress| {
+        let id = args.task_id(&path);
+        progress.start(id.clone());
+        id
+      });
+      let state = config.test(&path, args, io_scheduler.as_deref()).await;
+      if let (Some(progress), Some(id)) = (&progress, id) {
+        progress.finish(&id);
+      }
+      done.send_replace(Some(match state {
+        State::Ok(_) | State::Warned(..) => Ok(()),
+        State::Failed(_) => Err("failed"),
+        State::Ignored => Err("is ignored"),
+        State::FilteredOut => Err("is filtered out"),
+        State::Skipped(_) => Err("is skipped"),
+      }));
+      // a later discovered task may depend on it, wait for the discovery
+      // before removing its workdir
+      let retain = match args.retain {
+        Retain::All => Retain::All,
+        retain => match dependents.wait_for(Option::is_some).await {
+          Ok(dependents) if !dependents.as_ref().expect("waited").contains(&path) => {
+            retain
+          }
+          _ => Retain::All,
+        },
+      };
+      let workdir = args.task_workdir(&path);
+      if let Err(e) = remove_workdir(&workdir, &state, retain).await {
+        eprintln!("warning: remove workdir \"{}\": {e}", workdir.display());
+      }
+      (path, state)
+    });
+  }
+}
+
+/// Remove the workdir of the finished task by `--retain`, the symlinked inputs
+/// are removed as links, never followed into the sources
+async fn remove_workdir(workdir: &Path, state: &State, retain: Retain) -> io::Result<()> {
+  match (retain, state) {
+    (Retain::Failed, State::Ok(_))
+    | (Retain::None, State::Ok(_) | State::Failed(_) | State::Warned(..)) => {}
+    _ => return Ok(()),
+  }
+  let report = match state {
+    State::Failed(Some((FailedState::ReportSaved(report), _)))
+    | State::Warned(FailedState::ReportSaved(report), _) => Some(report),
+    _ => None,
+  };
+  if report.is_none() {
+    return remove_dir_all(workdir).await;
+  }
+  // only keep the report, so that its printed path is still valid
+  let mut read_dir = tokio::fs::read_dir(workdir).await?;
+  while let Some(entry) = read_dir.next_entry().await? {
+    let path = entry.path();
+    if Some(&path) == report {
+      continue;
+    }
+    // not followed, i.e., `is_dir` is false for the links
+    if entry.file_type().await?.is_dir() {
+      remove_dir_all(path).await?;
+    } else {
+      tokio::fs::remove_file(path).await?;
+    }
+  }
+  Ok(())
+}
+
+/// The total size of the files in the dir, the links are not followed
+fn dir_size(dir: &Path) -> u64 {
+  let Ok(read_dir) = dir.read_dir() else {
+    return 0;
+  };
+  read_dir
+    .filter_map(Result::ok)
+    .map(|entry| match entry.metadata() {
+      Ok(metadata) if metadata.is_dir() => dir_size(&entry.path()),
+      Ok(metadata) => metadata.len(),
+      Err(_) => 0,
+    })
+    .sum()
+}
+
+fn fmt_retained(retained: u64, workdir: &Path) -> String {
+  format!("retained: {} in workdir \"{}\"", fmt_size(retained), workdir.display())
+}
+
+fn fmt_seed(seed: u64) -> String {
+  format!("seed: {seed}, replay with --seed {seed}")
+}
+
+/// The size in the binary units, e.g., `1.50 GiB`
+fn fmt_size(bytes: u64) -> String {
+  const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+  if bytes < 1024 {
+    return format!("{bytes} B");
+  }
+  let mut size = bytes as f64 / 1024.0;
+  let mut unit = 0;
+  while size >= 1024.0 && unit + 1 < UNITS.len() {
+    size /= 1024.0;
+    unit += 1;
+  }
+  format!("{size:.2} {}", UNITS[unit])
+}
+
+/// `--no-capture` only works for a single selected task, since the outputs of
+/// the parallel tasks would interleave
+fn no_capture(tasks: &mut [(PathBuf, FullConfig)]) {
+  let mut selected = tasks.iter_mut().filter(|(_, config)| !config.skipped());
+  match (selected.next(), selected.count()) {
+    (Some((path, config)), 0) => {
+      let skipped = config.set_no_capture();
+      if skipped != 0 {
+        eprintln!(
+          "warning: --no-capture, skip {skipped} golden(s) on the stdout/stderr of \"{}\"",
+          path.display()
+        );
+      }
+    }
+    (None, _) => {}
+    (Some(_), others) => eprintln!(
+      "warning: --no-capture is ignored, since {} tasks are selected",
+      others + 1
+    ),
+  }
+}
+
+/// The workdirs of the discovered tasks, to reject the same or nested ones as the
+/// tasks stream in, since preparing one of them wipes the other's workdir.
+/// They are compared case-insensitively when the workdir's filesystem is, e.g.,
+/// `A/b.sh` and `a--b.sh` of the flat layout share the workdir `A--b.sh`, and so do
+/// `A.sh` and `a.sh` of a case-sensitive rootdir mirrored there
+struct Workdirs<'a> {
+  args: &'a Args,
+  /// The compared keys, to the workdirs and the tasks
+  keys: BTreeMap<PathBuf, (PathBuf, PathBuf)>,
+}
+
+impl<'a> Workdirs<'a> {
+  const fn new(args: &'a Args) -> Self {
+    Self { args, keys: BTreeMap::new() }
+  }
+  /// Record the task's workdir, unless it collides with a recorded one
+  fn insert(&mut self, path: &Path) -> Result<(), BuildError> {
+    let workdir = self.args.task_workdir(path);
+    let key = if self.args.workdir_case_insensitive {
+      PathBuf::from(workdir.to_string_lossy().to_lowercase())
+    } else {
+      workdir.clone()
+    };
+    // the same or an ancestor, or a nested one, which is right after it in order
+    let collision = match key.ancestors().find_map(|ancestor| self.keys.get(ancestor)) {
+      Some((ancestor_workdir, ancestor_path)) => {
+        Some((ancestor_path.as_path(), path, ancestor_workdir.as_path()))
+      }
+      None => self
+        .keys
+        .range(key.clone()..)
+        .next()
+        .filter(|(nested, _)| nested.starts_with(&key))
+        .map(|(_, (_, nested_path))| (path, nested_path.as_path(), workdir.as_path())),
+    };
+    if let Some((path1, path2, workdir)) = collision {
+      return Err(BuildError::WorkdirCollision(
+        path1.to_path_buf(),
+        path2.to_path_buf(),
+        workdir.to_path_buf(),
+      ));
+    }
+    self.keys.insert(key, (workdir, path.to_path_buf()));
+    Ok(())
+  }
+}
+
+/// The missing dependencies and the dependency cycles
+fn dependency_errs(graph: &[(PathBuf, Vec<PathBuf>)]) -> Vec<BuildError> {
+  /// Depth-first search, `visiting` is the current path of the search
+  fn visit(
+    idx: usize,
+    graph: &[(&PathBuf, Vec<usize>)],
+    visiting: &mut Vec<usize>,
+    visited: &mut [bool],
+    errs: &mut Vec<BuildError>,
+  ) {
+    if let Some(pos) = visiting.iter().position(|&i| i == idx) {
+      let mut cycle: Vec<_> =
+        visiting[pos..].iter().map(|&i| graph[i].0.to_path_buf()).collect();
+      cycle.push(graph[idx].0.to_path_buf());
+      errs.push(BuildError::DependencyCycle(cycle));
+      return;
+    }
+    if visited[idx] {
+      return;
+    }
+    visiting.push(idx);
+    for &dep in &graph[idx].1 {
+      visit(dep, graph, visiting, visited, errs);
+    }
+    visiting.pop();
+    visited[idx] = true;
+  }
+  let indices: HashMap<_, _> =
+    graph.iter().enumerate().map(|(idx, (path, _))| (path, idx)).collect();
+  let mut errs = Vec::new();
+  let graph: Vec<_> = graph
+    .iter()
+    .map(|(path, dependencies)| {
+      let deps = dependencies
+        .iter()
+        .filter_map(|dep| {
+          let idx = indices.get(dep).copied();
+          if idx.is_none() {
+            errs.push(BuildError::DependencyNotFound(path.clone(), dep.clone()));
+          }
+          idx
+        })
+        .collect();
+      (path, deps)
+    })
+    .collect();
+  if errs.is_empty() {
+    let mut visited = vec![false; graph.len()];
+    for idx in 0..graph.len() {
+      visit(idx, &graph, &mut Vec::new(), &mut visited, &mut errs);
+    }
+  }
+  errs
+}
+
+/// Write the whole line with one locked write, so that it will not interleave
+/// with other outputs
+fn print_line(line: &str) {
+  let mut stdout = io::stdout().lock();
+  _ = stdout.write_all(line.as_bytes());
+  _ = stdout.flush();
+}
+
 #[async_recursion::async_recursion]
+/// The `current_abs` is the canonicalized `current_path`, the paths below are
+/// joined to it, only the symlinks are canonicalized
 async fn walk(
-  mut current_config: FullConfig,
+  mut current_config: Arc<FullConfig>,
   current_path: PathBuf,
+  current_abs: PathBuf,
   args: &'static Args,
-) -> Result<Vec<(PathBuf, FullConfig)>, Vec<BuildError>> {
+  sender: mpsc::UnboundedSender<(PathBuf, FullConfig)>,
+) -> Result<(), Vec<BuildError>> {
   let all_path = current_path.join("__all__.toml");
   if all_path.exists() {
-    match current_config.update(&all_path, !args.nodebug) {
-      Ok(_config) => current_config = _config,
-      Err(e) => return Err(vec![e]),
+    // only clone the super's config when it is shared
+    let mut config =
+      match Arc::unwrap_or_clone(current_config).update(&all_path, !args.nodebug) {
+        Ok(config) => config,
+        Err(e) => return Err(vec![e]),
+      };
+    // generate goldens once for the directory, before any task is collected
+    if let Some(generator) = config.golden_generator.take()
+      && let Err(e) = generator.generate_golden(&current_path, args).await
+    {
+      return Err(vec![e]);
     }
+    current_config = Arc::new(config);
   }
   let read_dir = match current_path.read_dir() {
     Ok(read_dir) => read_dir,
     Err(e) => return Err(vec![BuildError::ReadDir(current_path, e)]),
   };
+  let mut errs = Vec::new();
+  let canonicalize = |path: &Path, is_symlink: bool, name: OsString| {
+    if is_symlink {
+      std::fs::canonicalize(path).map_err(|e| BuildError::ReadDir(path.to_path_buf(), e))
+    } else {
+      Ok(current_abs.join(name))
+    }
+  };
   let (sub_dir_futures, files): (Vec<_>, Vec<_>) =
     read_dir.into_iter().partition_map(|entry| {
-      let path = entry.unwrap().path();
+      let entry = entry.unwrap();
+      let (path, name) = (entry.path(), entry.file_name());
+      let is_symlink = entry.file_type().is_ok_and(|t| t.is_symlink());
       if path.is_dir() {
-        if path.file_name().unwrap() == GOLDEN_DIR {
-          Either::Left(None)
-        } else {
-          let current_config = current_config.clone();
-          Either::Left(Some(tokio::spawn(walk(current_config, path, args))))
+        if name == GOLDEN_DIR {
+          return Either::Left(None);
         }
+        match canonicalize(&path, is_symlink, name) {
+          // neither parse its configs nor list its files
+          Ok(path_abs) if args.is_workdir(&path_abs) || args.pruned(&path_abs) => {
+            Either::Left(None)
+          }
+          Ok(path_abs) => {
+            let current_config = Arc::clone(&current_config);
+            let sender = sender.clone();
+            Either::Left(Some(tokio::spawn(walk(
+              current_config,
+              path,
+              path_abs,
+              args,
+              sender,
+            ))))
+          }
+          Err(e) => {
+            errs.push(e);
+            Either::Left(None)
+          }
+        }
+      } else if name.to_string_lossy().starts_with(DEBUG_PREFIX) {
+        Either::Left(None)
       } else {
-        Either::Right(path)
+        Either::Right((path, name, is_symlink))
       }
     });
-  let mut errs = Vec::new();
-  let mut file_configs = files
-    .into_iter()
-    .filter_map(|file| {
-      if current_config.match_extension(&file) {
-        match args.filtered(&file) {
-          Ok(filtered) => {
-            if filtered {
-              Some((file, FullConfig::new_filtered()))
-            } else {
-              let config_file = file.with_extension("toml");
-              let current_config = current_config.clone();
-              if config_file.is_file() {
-                match current_config.update(&config_file, !args.nodebug) {
-                  Ok(config) => Some((file, config)),
-                  Err(e) => {
-                    errs.push(e);
-                    None
-                  }
-                }
-              } else {
-                Some((file, current_config))
-              }
-              .and_then(|(file, config)| match config.eval(&file, args) {
+  // check once for the directory, rather than for each file
+  let subtree_filtered = args.subtree_filtered(&current_abs);
+  let file_configs = files.into_iter().filter_map(|(file, name, is_symlink)| {
+    if current_config.match_extension(&file) {
+      let filtered = if subtree_filtered && !is_symlink {
+        Ok(true)
+      } else {
+        canonicalize(&file, is_symlink, name).map(|file_abs| args.filtered(&file_abs))
+      };
+      match filtered {
+        Ok(filtered) => {
+          if filtered {
+            Some((file, FullConfig::new_filtered()))
+          } else {
+            let config_file = file.with_extension("toml");
+            // the task's own copy, which shares the fields with the directory's
+            // config, only the evaluated ones are copied
+            let current_config = FullConfig::clone(&current_config);
+            if config_file.is_file() {
+              match current_config.update(&config_file, !args.nodebug) {
                 Ok(config) => Some((file, config)),
                 Err(e) => {
                   errs.push(e);
                   None
                 }
-              })
+              }
+            } else {
+              Some((file, current_config))
             }
-          }
-          Err(e) => {
-            errs.push(e);
-            None
+            .and_then(|(file, config)| match config.eval(&file, args) {
+              Ok(config) => Some((file, config)),
+              Err(e) => {
+                errs.push(e);
+                None
+              }
+            })
           }
         }
-      } else {
-        None
+        Err(e) => {
+          errs.push(e);
+          None
+        }
       }
-    })
-    .collect::<Vec<_>>();
+    } else {
+      None
+    }
+  });
+  for file_config in file_configs {
+    // the receiver is only dropped when the run is aborted
+    _ = sender.send(file_config);
+  }
   for f in sub_dir_futures.into_iter().flatten() {
-    match f.await.expect("join handle") {
-      Ok(res) => file_configs.extend(res),
-      Err(e) => errs.extend(e),
+    if let Err(e) = f.await.expect("join handle") {
+      errs.extend(e);
     }
   }
-  if errs.is_empty() { Ok(file_configs) } else { Err(errs) }
+  if errs.is_empty() { Ok(()) } else { Err(errs) }
+}
+
+/// Collect all the discovered tasks of [`walk`]
+#[cfg(test)]
+async fn walk_all(
+  args: &'static Args,
+) -> Result<Vec<(PathBuf, FullConfig)>, Vec<BuildError>> {
+  let (sender, mut receiver) = mpsc::unbounded_channel();
+  walk(
+    Arc::new(FullConfig::new(args)),
+    args.rootdir.to_path_buf(),
+    args.rootdir_abs.to_path_buf(),
+    args,
+    sender,
+  )
+  .await?;
+  let mut file_configs = Vec::new();
+  while let Some(file_config) = receiver.recv().await {
+    file_configs.push(file_config);
+  }
+  Ok(file_configs)
+}
+
+/// The `*.sh` tasks in a temp `rootdir`, and their `workdir`, for the end-to-end tests
+#[cfg(test)]
+struct Fixture {
+  tmpdir: crate::TempDir,
+  rootdir: PathBuf,
+  workdir: PathBuf,
+}
+
+#[cfg(test)]
+impl Fixture {
+  fn new(name: &str) -> Self {
+    let tmpdir = crate::TempDir::new(name);
+    let (rootdir, workdir) = (tmpdir.join("rootdir"), tmpdir.join("workdir"));
+    std::fs::create_dir_all(&rootdir).unwrap();
+    Self { tmpdir, rootdir, workdir }
+  }
+  /// Write the file in rootdir, along with its parent directories
+  fn write(&self, file: impl AsRef<Path>, contents: impl AsRef<[u8]>) {
+    let path = self.rootdir.join(file);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, contents).unwrap();
+  }
+  /// The tasks run by `bash`
+  fn args(&self) -> Args {
+    Args::new(&self.rootdir)
+      .workdir(&self.workdir)
+      .cmd("bash")
+      .extensions(["sh"])
+  }
+}
+
+#[test]
+fn test_workdir_collisions() {
+  use crate::args::WorkdirLayout;
+  fn workdir_collisions<'a>(
+    args: &Args,
+    paths: impl Iterator<Item = &'a PathBuf>,
+  ) -> Vec<BuildError> {
+    let mut workdirs = Workdirs::new(args);
+    paths.filter_map(|path| workdirs.insert(path).err()).collect()
+  }
+  let args = Args::new("demo");
+  // the workdir contains the extension, so that they will not collide
+  let paths = ["demo/a/test.sh", "demo/a/test.py", "demo/a/test.sh2"].map(PathBuf::from);
+  assert!(workdir_collisions(&args, paths.iter()).is_empty());
+  let paths = ["demo/a.sh", "demo/b.sh", "demo/a.sh/c.sh"].map(PathBuf::from);
+  let errs = workdir_collisions(&args, paths.iter());
+  assert!(matches!(
+    errs.as_slice(),
+    [BuildError::WorkdirCollision(p1, p2, workdir)]
+      if p1 == &paths[0] && p2 == &paths[2] && workdir.ends_with("tmp/a.sh")
+  ));
+  // the nested one first
+  let errs = workdir_collisions(&args, paths.iter().rev());
+  assert!(matches!(
+    errs.as_slice(),
+    [BuildError::WorkdirCollision(p1, p2, workdir)]
+      if p1 == &paths[0] && p2 == &paths[2] && workdir.ends_with("tmp/a.sh")
+  ));
+  // the flat workdirs never nest, but the separators may collide with the names
+  let args = Args::new("demo").workdir_layout(WorkdirLayout::Flat);
+  assert!(workdir_collisions(&args, paths.iter()).is_empty());
+  let paths = ["demo/a/b.sh", "demo/a--b.sh", "demo/a/c.sh"].map(PathBuf::from);
+  let errs = workdir_collisions(&args, paths.iter());
+  assert!(matches!(
+    errs.as_slice(),
+    [BuildError::WorkdirCollision(p1, p2, workdir)]
+      if p1 != p2 && paths[..2].contains(p1) && paths[..2].contains(p2)
+        && workdir.ends_with("tmp/a--b.sh")
+  ));
+  // the workdirs differing only in case collide on a case-insensitive filesystem,
+  // in both layouts
+  let paths = ["demo/A/b.sh", "demo/a--b.sh"].map(PathBuf::from);
+  assert!(workdir_collisions(&args, paths.iter()).is_empty());
+  let mut args = args;
+  args.workdir_case_insensitive = true;
+  assert_eq!(workdir_collisions(&args, paths.iter()).len(), 1);
+  let paths = ["demo/A.sh", "demo/a.sh"].map(PathBuf::from);
+  let mut args = Args::new("demo");
+  assert!(workdir_collisions(&args, paths.iter()).is_empty());
+  args.workdir_case_insensitive = true;
+  assert_eq!(workdir_collisions(&args, paths.iter()).len(), 1);
+}
+
+#[test]
+fn test_progress_line() {
+  let running = |n: usize| (0..n).map(|idx| format!("test{idx}.sh")).collect::<Vec<_>>();
+  let elapsed = Duration::from_millis(1250);
+  assert_eq!(progress_line(0, 5, 0, elapsed, &[]), "[0/5] 0 failed, 1.25s");
+  assert_eq!(
+    progress_line(7, 120, 2, elapsed, &running(2)),
+    "[  7/120] 2 failed, 1.25s, running: test0.sh, test1.sh"
+  );
+  assert_eq!(
+    progress_line(7, 120, 2, elapsed, &running(5)),
+    "[  7/120] 2 failed, 1.25s, running: test0.sh, test1.sh, test2.sh (+2)"
+  );
+}
+
+#[test]
+fn test_fmt_groups() {
+  let args = Args::new("demo").group_depth(1);
+  assert_eq!(args.group(Path::new("demo/codegen/deep/a.sh")), "codegen");
+  assert_eq!(args.group(Path::new("demo/a.sh")), ".");
+  let args = args.group_depth(2);
+  assert_eq!(args.group(Path::new("demo/codegen/deep/a.sh")), "codegen/deep");
+  assert_eq!(args.group(Path::new("demo/codegen/a.sh")), "codegen");
+  let group = |count_ok, count_failed, millis| Group {
+    count_ok,
+    count_failed,
+    count_skipped: 0,
+    count_ignored: 1,
+    count_filtered: 0,
+    time: Duration::from_millis(millis),
+  };
+  let groups = BTreeMap::from([
+    ("ast".to_owned(), group(1, 3, 500)),
+    ("codegen".to_owned(), group(2, 58, 12_300)),
+    ("parser".to_owned(), group(10, 0, 3_000)),
+    ("runtime".to_owned(), group(5, 0, 200)),
+  ]);
+  assert_eq!(
+    fmt_groups(&groups),
+    "groups:\
+    \n     codegen: 58 failed; 2 passed; 0 skipped; 1 ignored; 0 filtered out; 12.30s\
+    \n     ast: 3 failed; 1 passed; 0 skipped; 1 ignored; 0 filtered out; 0.50s\
+    \n     parser, runtime (2): ok. 15 passed; 0 skipped; 2 ignored; 0 filtered out; 3.20s"
+  );
+}
+
+#[test]
+fn test_fmt_table() {
+  let group = |count_ok, count_failed, millis| Group {
+    count_ok,
+    count_failed,
+    count_skipped: 0,
+    count_ignored: 0,
+    count_filtered: 0,
+    time: Duration::from_millis(millis),
+  };
+  let groups = BTreeMap::from([
+    ("ast".to_owned(), group(10, 0, 3_000)),
+    ("codegen".to_owned(), group(2, 58, 12_300)),
+  ]);
+  let total = group(12, 58, 15_300);
+  assert_eq!(
+    fmt_table(&total, &groups, false),
+    "         passed  failed  skipped  ignored  filtered    time\
+    \ntotal        12      58        0        0         0  15.30s\
+    \ncodegen       2      58        0        0         0  12.30s\
+    \nast          10       0        0        0         0   3.00s"
+  );
+  // the single group is omitted
+  colored::control::set_override(true);
+  let groups = BTreeMap::from([(".".to_owned(), group(12, 0, 200))]);
+  let table = fmt_table(&group(12, 0, 200), &groups, true);
+  assert_eq!(table.lines().count(), 2);
+  assert!(table.contains(&"      12".green().to_string()), "{table}");
+  assert!(!table.contains(&"       0".red().to_string()), "{table}");
+}
+
+#[test]
+fn test_dedupe_faileds() {
+  let args = Args::new("root").workdir("tmp");
+  let failed = |path: &str, code: u8| {
+    let path = Path::new(path);
+    let name = path.file_stem().unwrap().to_str().unwrap();
+    let workdir = args.workdir.join(args.relative_path(path));
+    let errs_text = format!(
+      "exit code, want: 0, got: {code}\nexport \"name\"=\"{name}\"\ncd \"{}\"\n\"bash\" \"{}\"",
+      workdir.display(),
+      path.file_name().unwrap().to_str().unwrap()
+    );
+    Failed {
+      id: path.display().to_string(),
+      state: FailedState::ReportSaved(workdir.join(format!("{name}.report"))),
+      fingerprint: Some(fingerprint(&errs_text, path, &args)),
+    }
+  };
+  let faileds = [
+    failed("root/a/t1.sh", 127),
+    failed("root/other.sh", 3),
+    failed("root/a/t2.sh", 127),
+    failed("root/b/t3.sh", 127),
+  ];
+  let deduped = dedupe_faileds(&faileds);
+  assert_eq!(deduped.len(), 2);
+  assert_eq!(deduped[0].0.id, "root/a/t1.sh");
+  assert_eq!(deduped[0].1, ["root/a/t2.sh", "root/b/t3.sh"]);
+  assert_eq!(deduped[1].0.id, "root/other.sh");
+  assert!(deduped[1].1.is_empty());
+  assert_eq!(
+    fmt_identical(&deduped[0].1),
+    "\n     ... and 2 other tests failed identically: root/a/t2.sh, root/b/t3.sh"
+  );
+  let others = vec!["x"; DEDUPE_LISTED + 2];
+  assert!(fmt_identical(&others).ends_with("x, ... (2 more)"));
+  // not deduped without the fingerprints
+  let faileds = faileds.map(|failed| Failed { fingerprint: None, ..failed });
+  assert_eq!(dedupe_faileds(&faileds).len(), 4);
+}
+
+#[tokio::test]
+async fn test_walk_large_tree() {
+  let fixture = Fixture::new("walk-large-tree");
+  let rootdir = &fixture.rootdir;
+  for dir in 0..100 {
+    let dir = rootdir.join(format!("dir{dir}"));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("__all__.toml"), "envs = { k = 'v' }").unwrap();
+    for file in 0..100 {
+      std::fs::write(dir.join(format!("test{file}.sh")), "").unwrap();
+    }
+  }
+  let args = fixture.args().nodebug();
+  let args = args.rebuild().unwrap();
+  let now = Instant::now();
+  let file_configs = walk_all(args).await.unwrap();
+  assert_eq!(file_configs.len(), 10_000);
+  assert!(now.elapsed() < Duration::from_secs(60), "discovery is too slow");
+  // only one is included, the other directories are pruned without parsing
+  // their configs, unless the filtered out tasks are listed
+  fixture.write("dir7/__all__.toml", "invalid =");
+  let include = || fixture.args().nodebug().include([rootdir.join("dir42/test42.sh")]);
+  let args = include().rebuild().unwrap();
+  let now = Instant::now();
+  let file_configs = walk_all(args).await.unwrap();
+  assert_eq!(file_configs.len(), 100);
+  let included = file_configs.iter().filter(|(_, config)| !config.skipped());
+  assert!(included.map(|(path, _)| path).eq([&rootdir.join("dir42/test42.sh")]));
+  assert!(now.elapsed() < Duration::from_secs(30), "filtering is too slow");
+  let args = include().show_skipped(ShowSkipped::Dirs).rebuild().unwrap();
+  assert!(matches!(walk_all(args).await.unwrap_err().as_slice(), [BuildError::Toml(..)]));
+}
+
+#[tokio::test]
+async fn test_io_permits() {
+  let fixture = Fixture::new("io-permits");
+  let markers = fixture.tmpdir.join("markers");
+  std::fs::create_dir_all(&markers).unwrap();
+  fixture.write("__all__.toml", "permit = 1");
+  // every task waits for the others to start, which only passes when the
+  // executions overlap, i.e., the io permit is released before executing
+  for idx in 0..4 {
+    fixture.write(
+      format!("test{idx}.sh"),
+      format!(
+        "cd {markers:?}\ntouch {idx}\nfor _ in $(seq 300); do\n  \
+        [ -e 0 ] && [ -e 1 ] && [ -e 2 ] && [ -e 3 ] && exit 0\n  sleep 0.1\ndone\nexit 1"
+      ),
+    );
+  }
+  let args = fixture.args().nodebug().permits(4).io_permits(1);
+  let args = args.rebuild().unwrap();
+  let result = _test(args).await.unwrap();
+  assert_eq!(result.count_ok, 4);
+}
+
+#[tokio::test]
+async fn test_permit_default() {
+  let fixture = Fixture::new("permit-default");
+  for name in ["default.sh", "own.sh", "sub/inherited.sh"] {
+    fixture.write(name, "");
+  }
+  fixture.write("own.toml", "permit = 2");
+  fixture.write("sub/__all__.toml", "permit = \"100%\"");
+  let new_args = || fixture.args().nodebug();
+  // the task's own permit > --permit-default > 0
+  for (permits, permit_default, want) in
+    [(4, 0, [0, 2, 4]), (4, 3, [3, 2, 4]), (8, 1, [1, 2, 8])]
+  {
+    let args = new_args().permits(permits).permit_default(permit_default);
+    let file_configs = walk_all(args.rebuild().unwrap()).await.unwrap();
+    let permits = ["default.sh", "own.sh", "sub/inherited.sh"].map(|name| {
+      let (_, config) =
+        file_configs.iter().find(|(path, _)| path.ends_with(name)).unwrap();
+      config.permit()
+    });
+    assert_eq!(permits, want, "--permit-default {permit_default}");
+  }
+  let Err(err) = new_args().permits(2).permit_default(3).rebuild() else { panic!() };
+  assert!(matches!(err, BuildError::PermitDefaultExceed(3, 2)));
+}
+
+#[tokio::test]
+async fn test_heavy_first() {
+  let fixture = Fixture::new("heavy-first");
+  let rootdir = &fixture.rootdir;
+  fixture.write("__all__.toml", "permit = 1");
+  for name in ["light0", "light1", "light2", "heavy"] {
+    let script = format!("echo {name} >> $REGRESSION_ROOTDIR.order");
+    fixture.write(format!("{name}.sh"), script);
+  }
+  fixture.write("heavy.toml", "permit = 2");
+  let order_file = rootdir.with_extension("order");
+  let args = fixture.args().nodebug().permits(2).heavy_first();
+  let args = args.rebuild().unwrap();
+  let result = _test(args).await.unwrap();
+  assert_eq!(result.count_ok, 4);
+  let order = std::fs::read_to_string(&order_file).unwrap();
+  assert_eq!(order.lines().next(), Some("heavy"), "{order}");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_filter_symlink() {
+  use std::os::unix::fs::symlink;
+  let fixture = Fixture::new("filter-symlink");
+  let rootdir = &fixture.rootdir;
+  std::fs::create_dir_all(rootdir.join("sub")).unwrap();
+  fixture.write("real.sh", "");
+  fixture.write("other/x.sh", "");
+  symlink("../real.sh", rootdir.join("sub/link.sh")).unwrap();
+  symlink("other", rootdir.join("linkdir")).unwrap();
+  // the symlinks and their targets are filtered the same, the walked ones
+  // are listed, and the directories without the included files are pruned
+  for (include, walked, want) in [
+    ("sub/link.sh", 2, ["real.sh", "sub/link.sh"]),
+    ("linkdir/x.sh", 3, ["linkdir/x.sh", "other/x.sh"]),
+  ] {
+    let args = fixture.args().nodebug().include([rootdir.join(include)]);
+    let args = args.rebuild().unwrap();
+    let file_configs = walk_all(args).await.unwrap();
+    assert_eq!(file_configs.len(), walked, "include {include}");
+    let included = file_configs
+      .iter()
+      .filter(|(_, config)| !config.skipped())
+      .map(|(path, _)| path.strip_prefix(rootdir).unwrap())
+      .sorted();
+    assert!(included.eq(want.map(Path::new)), "include {include}");
+  }
+  // `sub` is pruned, unless the filtered out tasks are listed
+  for (show_skipped, want) in
+    [(false, &["real.sh"][..]), (true, &["real.sh", "sub/link.sh"])]
+  {
+    let args = fixture.args().nodebug().include([rootdir.join("real.sh")]);
+    let args = if show_skipped { args.show_skipped(ShowSkipped::All) } else { args };
+    let file_configs = walk_all(args.rebuild().unwrap()).await.unwrap();
+    let included = file_configs
+      .iter()
+      .filter(|(_, config)| !config.skipped())
+      .map(|(path, _)| path.strip_prefix(rootdir).unwrap())
+      .sorted();
+    assert!(included.eq(want.iter().map(Path::new)), "show skipped {show_skipped}");
+  }
+}
+
+#[tokio::test]
+async fn test_dump_config() {
+  let fixture = Fixture::new("dump-config");
+  let rootdir = &fixture.rootdir;
+  fixture.write("__all__.toml", "cmd = 'bash'");
+  fixture.write("test.sh", "");
+  fixture.write("test.toml", "[extend]\nargs = ['arg1']");
+  let dump_file = rootdir.with_extension("json");
+  for nodebug in [false, true] {
+    let args = Args::new(rootdir).extensions(["sh"]).dump_config(&dump_file);
+    let args = if nodebug { args.nodebug() } else { args }.rebuild().unwrap();
+    let file_configs = walk_all(args).await.unwrap();
+    dump_configs(&dump_file, &file_configs).unwrap();
+    let dumps: serde_json::Value =
+      serde_json::from_str(&std::fs::read_to_string(&dump_file).unwrap()).unwrap();
+    let dump = &dumps[0];
+    assert_eq!(dump["path"], rootdir.join("test.sh").to_str().unwrap());
+    assert_eq!(dump["config"]["cmd"], "bash");
+    assert_eq!(dump["config"]["args"], serde_json::json!(["test.sh", "arg1"]));
+    if nodebug {
+      assert!(dump.get("sources").is_none());
+    } else {
+      let sources = &dump["sources"];
+      assert_eq!(sources["cmd"], serde_json::json!([rootdir.join("__all__.toml")]));
+      assert_eq!(sources["args"], serde_json::json!([rootdir.join("test.toml")]));
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_baseline() {
+  let fixture = Fixture::new("baseline");
+  let rootdir = &fixture.rootdir;
+  fixture.write("fast.sh", "true");
+  fixture.write("slow.sh", "true");
+  fixture.write("new.sh", "true");
+  // fixed durations instead of a measured run: no run exceeds a day,
+  // and any run exceeds zero
+  let timings_file = rootdir.with_extension("json");
+  std::fs::write(&timings_file, r#"{"fast.sh": 86400.0, "slow.sh": 0.0}"#).unwrap();
+  let new_args = || fixture.args().nodebug().permits(2);
+  let args = new_args().baseline(&timings_file).rebuild().unwrap();
+  let result = _test(args).await.unwrap();
+  assert_eq!(result.status(), TestStatus::Passed);
+  let lines = result.baseline.as_ref().unwrap().lines("");
+  assert!(lines.starts_with("slower than baseline (tolerance 1.30x):\n     slow.sh: "));
+  assert!(lines.contains(", baseline 0.00s\n"), "{lines}");
+  assert!(!lines.contains("fast.sh"), "{lines}");
+  assert!(lines.ends_with("not in baseline:\n     new.sh\n"), "{lines}");
+  let args = new_args()
+    .baseline(&timings_file)
+    .baseline_strict()
+    .rebuild()
+    .unwrap();
+  let result = _test(args).await.unwrap();
+  assert_eq!(result.status(), TestStatus::Failed);
+  // `--timings` writes every executed task
+  let args = new_args().timings(&timings_file).rebuild().unwrap();
+  let result = _test(args).await.unwrap();
+  assert_eq!(result.status(), TestStatus::Passed);
+  let timings = crate::timings::read_timings(&timings_file).unwrap();
+  assert_eq!(timings.keys().collect::<Vec<_>>(), ["fast.sh", "new.sh", "slow.sh"]);
+}
+
+#[tokio::test]
+async fn test_resources() {
+  let fixture = Fixture::new("resources");
+  fixture.write("__all__.toml", "permit = 1");
+  // fail if another gpu task is running
+  let gpu_script = "lock=$REGRESSION_ROOTDIR/gpu.lock\n[ -e $lock ] && exit 1\n\
+                    touch $lock\nsleep 1\nrm $lock";
+  for name in ["gpu0", "gpu1"] {
+    fixture.write(format!("{name}.sh"), gpu_script);
+    fixture.write(format!("{name}.toml"), "resources = { gpu = 1 }");
+  }
+  for name in ["cpu0", "cpu1"] {
+    fixture.write(format!("{name}.sh"), "sleep 1");
+  }
+  let args = fixture.args().nodebug().permits(4).resource("gpu", 1);
+  let args = args.rebuild().unwrap();
+  let result = _test(args).await.unwrap();
+  // the gpu tasks are serialized, otherwise one of them finds the lock
+  assert_eq!(result.count_ok, 4);
+}
+
+#[tokio::test]
+async fn test_depends_on() {
+  let fixture = Fixture::new("depends-on");
+  let workdir = &fixture.workdir;
+  fixture.write("__all__.toml", "args = [\"{{name}}.sh\"]");
+  fixture.write("build/compile_lib.sh", "sleep 0.5\necho lib > lib.out");
+  for name in ["use0", "use1"] {
+    fixture.write(format!("run/{name}.sh"), "cat $1/lib.out");
+    fixture.write(
+      format!("run/{name}.toml"),
+      "depends-on = [\"../build/compile_lib.sh\"]\n\
+       extend.args = [\"{{dep.compile_lib.workdir}}\"]",
+    );
+  }
+  fixture.write("fail.sh", "exit 1");
+  fixture.write("after_fail.sh", "true");
+  fixture.write("after_fail.toml", "depends-on = [\"fail.sh\"]");
+  // depends on a failed dependent
+  fixture.write("after_after.sh", "true");
+  fixture.write("after_after.toml", "depends-on = [\"./after_fail.sh\"]");
+  let new_args = || fixture.args().permits(4);
+  let result = _test(new_args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.count_ok, 3);
+  assert_eq!(result.faileds.len(), 1);
+  // not run, rather than failed
+  assert_eq!(result.count_skipped, 2);
+  assert!(!workdir.join("after_fail.sh").exists());
+  // unresolved variable, missing dependency, and cycle
+  fixture.write("fail.toml", "args = [\"{{dep.compile_lib.workdir}}\"]");
+  let Err(errs) = _test(new_args().rebuild().unwrap()).await else { panic!() };
+  assert!(
+    matches!(&errs[..], [BuildError::UnresolvedDep(_, name)] if name == "compile_lib.workdir")
+  );
+  fixture.write("fail.toml", "depends-on = [\"missing.sh\"]");
+  let Err(errs) = _test(new_args().rebuild().unwrap()).await else { panic!() };
+  assert!(
+    matches!(&errs[..], [BuildError::DependencyNotFound(_, dep)] if dep.ends_with("missing.sh"))
+  );
+  fixture.write("fail.toml", "depends-on = [\"after_after.sh\"]");
+  let Err(errs) = _test(new_args().rebuild().unwrap()).await else { panic!() };
+  assert_eq!(errs.len(), 1);
+  let err = errs[0].to_string();
+  assert!(err.starts_with("dependency cycle: "), "{err}");
+  assert_eq!(err.matches(" -> ").count(), 3, "{err}");
+}
+
+#[tokio::test]
+async fn test_streaming() {
+  let fixture = Fixture::new("streaming");
+  let (rootdir, workdir) = (&fixture.rootdir, &fixture.workdir);
+  for dir in 0..20 {
+    let dir = rootdir.join(format!("dir{dir:02}"));
+    std::fs::create_dir_all(&dir).unwrap();
+    for file in 0..5 {
+      let script = if file % 2 == 0 { "true" } else { "exit 1" };
+      std::fs::write(dir.join(format!("test{file}.sh")), script).unwrap();
+    }
+  }
+  // the dependency is discovered in another directory
+  fixture.write("dir19/lib.sh", "echo lib > lib.out");
+  fixture.write("dir00/use.sh", "cat $1/lib.out");
+  fixture.write(
+    "dir00/use.toml",
+    "depends-on = [\"../dir19/lib.sh\"]\nargs = [\"{{name}}.sh\", \"{{dep.lib.workdir}}\"]",
+  );
+  let args = fixture.args().nodebug().permits(8).retain(Retain::Failed);
+  let result = _test(args.rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.count_ok, 62);
+  assert_eq!(result.faileds.len(), 40);
+  // reported in path order, rather than in completion order
+  assert!(result.faileds.iter().map(|failed| &failed.id).is_sorted());
+  assert!(workdir.join("dir19/lib.sh").exists());
+  assert!(!workdir.join("dir00/use.sh").exists());
+  assert!(!workdir.join("dir19/test0.sh").exists());
+}
+
+/// The discovery blocks on reading a fifo `__all__.toml`, which only the task
+/// discovered before it writes, so the run only finishes when they overlap
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_streaming_overlap() {
+  let fixture = Fixture::new("streaming-overlap");
+  let rootdir = &fixture.rootdir;
+  std::fs::create_dir_all(rootdir.join("sub")).unwrap();
+  let fifo = rootdir.join("sub/__all__.toml");
+  let status = std::process::Command::new("mkfifo").arg(&fifo).status().unwrap();
+  assert!(status.success());
+  fixture.write("writer.sh", format!("echo 'permit = 0' > '{}'", fifo.display()));
+  fixture.write("sub/reader.sh", "true");
+  let args = fixture.args();
+  let run = _test(args.rebuild().unwrap());
+  match tokio::time::timeout(Duration::from_secs(30), run).await {
+    Ok(result) => assert_eq!(result.unwrap().count_ok, 2),
+    Err(_) => {
+      // unblock the discovery before failing
+      std::fs::write(&fifo, "").unwrap();
+      panic!("the discovery does not overlap the execution");
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_reject_before_spawn() {
+  use crate::args::WorkdirLayout;
+  let fixture = Fixture::new("reject-before-spawn");
+  let rootdir = &fixture.rootdir;
+  let log = fixture.tmpdir.join("spawned.log");
+  let write_task = |name: &str| {
+    fixture.write(name, format!("echo {name} >> '{}'", log.display()));
+  };
+  let spawned = || std::fs::read_to_string(&log).unwrap_or_default();
+  // the flat workdirs of `x/y.sh` and `x--y.sh` collide, the later discovered one
+  // is never spawned, and nothing is spawned after it
+  write_task("x/y.sh");
+  write_task("x--y.sh");
+  let errs = _test(fixture.args().workdir_layout(WorkdirLayout::Flat).rebuild().unwrap())
+    .await
+    .err()
+    .unwrap();
+  assert!(matches!(errs.as_slice(), [BuildError::WorkdirCollision(..)]));
+  assert!(spawned().lines().count() <= 1, "{}", spawned());
+  std::fs::remove_file(rootdir.join("x--y.sh")).unwrap();
+  // the tasks in the dependency cycle are never spawned, but the others may be
+  write_task("side.sh");
+  write_task("z.sh");
+  fixture.write("x/y.toml", "depends-on = [\"../z.sh\"]");
+  fixture.write("z.toml", "depends-on = [\"x/y.sh\"]");
+  _ = std::fs::remove_file(&log);
+  let errs = _test(fixture.args().rebuild().unwrap()).await.err().unwrap();
+  assert!(matches!(errs.as_slice(), [BuildError::DependencyCycle(..)]), "{errs:?}");
+  assert!(!spawned().contains("y.sh") && !spawned().contains("z.sh"), "{}", spawned());
+  // runs once the checks pass
+  std::fs::remove_file(rootdir.join("z.toml")).unwrap();
+  let result = _test(fixture.args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.count_ok, 3);
+  assert!(spawned().contains("x/y.sh") && spawned().contains("z.sh"), "{}", spawned());
+}
+
+#[tokio::test]
+async fn test_workdir_layout() {
+  use crate::args::WorkdirLayout;
+  let fixture = Fixture::new("workdir-layout");
+  let workdir = &fixture.workdir;
+  fixture.write("top.sh", "echo top");
+  fixture.write("sub/deep/fail.sh", "echo new");
+  fixture.write("sub/deep/__golden__/fail.stdout", "old\n");
+  fixture.write(
+    "sub/deep/fail.toml",
+    "[[assert.golden]]\nfile = \"{{name}}.stdout\"\nequal = true",
+  );
+  for (layout, name, top, fail) in [
+    ("mirror", WorkdirLayout::Mirror, "top.sh", "sub/deep/fail.sh"),
+    ("flat", WorkdirLayout::Flat, "top.sh", "sub--deep--fail.sh"),
+  ] {
+    let args = fixture.args().workdir_layout(name);
+    let result = _test(args.rebuild().unwrap()).await.unwrap();
+    assert_eq!(result.count_ok, 1);
+    // the goldens are resolved from the sources, and the report is in the workdir
+    let [Failed { state: FailedState::ReportSaved(report), .. }] = &result.faileds[..]
+    else {
+      panic!("{layout}")
+    };
+    assert_eq!(report, &workdir.join(fail).join("fail.report"), "{layout}");
+    assert!(std::fs::read_to_string(report).unwrap().contains("old"), "{layout}");
+    assert!(workdir.join(top).join("top.stdout").is_file(), "{layout}");
+    let debug = std::fs::read_to_string(workdir.join(fail).join("__debug__.fail.toml"));
+    let want = format!("# workdir-layout = \"{layout}\"\n");
+    assert!(debug.unwrap().starts_with(&want), "{layout}");
+    let mut dirs: Vec<_> = std::fs::read_dir(workdir)
+      .unwrap()
+      .map(|entry| entry.unwrap())
+      .filter(|entry| entry.file_type().unwrap().is_dir())
+      .map(|entry| entry.file_name().into_string().unwrap())
+      .collect();
+    dirs.sort();
+    let want = match name {
+      WorkdirLayout::Mirror => ["sub", "top.sh"],
+      WorkdirLayout::Flat => ["sub--deep--fail.sh", "top.sh"],
+    };
+    assert_eq!(dirs, want, "{layout}");
+  }
+}
+
+#[tokio::test]
+async fn test_retain() {
+  let fixture = Fixture::new("retain");
+  let (rootdir, workdir) = (&fixture.rootdir, &fixture.workdir);
+  fixture.write("pass.sh", "echo ok > pass.out");
+  fixture.write("pass.data", "input");
+  fixture.write("fail.sh", "echo err > fail.out\nexit 1");
+  // the dependency is retained for its dependent
+  fixture.write("lib.sh", "echo lib > lib.out");
+  fixture.write("use.sh", "cat $1/lib.out");
+  fixture.write(
+    "use.toml",
+    "depends-on = [\"lib.sh\"]\nargs = [\"{{name}}.sh\", \"{{dep.lib.workdir}}\"]",
+  );
+  let new_args = |retain| fixture.args().nodebug().permits(2).retain(retain);
+  let result = _test(new_args(Retain::Failed).rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.count_ok, 3);
+  assert!(!workdir.join("pass.sh").exists());
+  assert!(!workdir.join("use.sh").exists());
+  assert!(workdir.join("lib.sh/lib.out").is_file());
+  assert!(workdir.join("fail.sh/fail.out").is_file());
+  assert_eq!(result.retained, dir_size(workdir));
+  // the symlinked inputs are removed as links
+  assert_eq!(std::fs::read_to_string(rootdir.join("pass.data")).unwrap(), "input");
+  assert!(rootdir.join("pass.sh").is_file());
+  _test(new_args(Retain::None).rebuild().unwrap()).await.unwrap();
+  assert!(!workdir.join("pass.sh").exists());
+  let kept: Vec<_> = std::fs::read_dir(workdir.join("fail.sh"))
+    .unwrap()
+    .map(|entry| entry.unwrap().file_name())
+    .collect();
+  assert_eq!(kept, ["fail.report"]);
+  assert!(rootdir.join("fail.sh").is_file());
+  assert_eq!(fmt_size(512), "512 B");
+  assert_eq!(fmt_size(3 << 29), "1.50 GiB");
+}
+
+#[tokio::test]
+async fn test_flock() {
+  let fixture = Fixture::new("flock");
+  let workdir = &fixture.workdir;
+  let lock = fixture.tmpdir.join("flock.lock");
+  fixture.write(
+    "__all__.toml",
+    format!("flock = {:?}\nflock-timeout = 1", lock.display().to_string()),
+  );
+  // the execution windows, in nanoseconds
+  for name in ["board0", "board1"] {
+    fixture.write(
+      format!("{name}.sh"),
+      "date +%s%N > $REGRESSION_NAME.window\nsleep 0.3\ndate +%s%N >> $REGRESSION_NAME.window",
+    );
+  }
+  let new_args = || fixture.args().nodebug().permits(2);
+  let result = _test(new_args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.count_ok, 2);
+  let window = |name: &str| -> Vec<u128> {
+    std::fs::read_to_string(workdir.join(format!("{name}.sh/{name}.window")))
+      .unwrap()
+      .lines()
+      .map(|line| line.parse().unwrap())
+      .collect()
+  };
+  let [w0, w1] = [window("board0"), window("board1")];
+  assert!(w0[1] <= w1[0] || w1[1] <= w0[0], "{w0:?} overlaps {w1:?}");
+  // held by another process, e.g., a human
+  let holder = std::fs::File::open(&lock).unwrap();
+  holder.lock().unwrap();
+  let result = _test(new_args().print_errs().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.faileds.len(), 2);
+  let errs = result.faileds[0].state.errs_text();
+  assert!(
+    errs.contains("could not acquire lock") && errs.contains("within 1 s"),
+    "{errs}"
+  );
+  drop(holder);
+}
+
+#[tokio::test]
+async fn test_soft() {
+  let fixture = Fixture::new("soft");
+  let workdir = &fixture.workdir;
+  let golden = |soft| {
+    format!(
+      "[[assert.golden]]\nfile = \"{{{{name}}}}.stdout\"\n\
+       match = [{{ pattern = \"converged\", count = 1 }}]\nsoft = {soft}"
+    )
+  };
+  fixture.write("warned.sh", "echo diverged");
+  fixture.write("warned.toml", golden(true));
+  // the hard errors still fail the task, along with the warnings
+  fixture.write("failed.sh", "echo diverged\nexit 1");
+  fixture.write("failed.toml", golden(true));
+  fixture.write("hard.sh", "echo diverged");
+  fixture.write("hard.toml", golden(false));
+  let args = fixture.args().nodebug().permits(2);
+  let result = _test(args.rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.count_ok, 1);
+  assert_eq!(result.faileds.len(), 2);
+  assert_eq!(result.warneds.len(), 1);
+  let report = result.warneds[0].state.errs_text();
+  assert!(report.starts_with("==== WARNING 1 ===\nfile \"warned.stdout\""), "{report}");
+  let report = std::fs::read_to_string(workdir.join("failed.sh/failed.report")).unwrap();
+  assert!(report.contains("==== ERROR 1 ===\nexit code"), "{report}");
+  assert!(report.contains("==== WARNING 2 ==="), "{report}");
+  assert!(fmt_warneds(&result.warneds).starts_with("warnings:\n     report: "));
+}
+
+#[tokio::test]
+async fn test_debug_cmd() {
+  let fixture = Fixture::new("debug-cmd");
+  let (rootdir, workdir) = (&fixture.rootdir, &fixture.workdir);
+  fixture.write("task.sh", "echo ok | tee task.log");
+  fixture.write(
+    "task.toml",
+    r#"
+args = ["{{name}}.{{extension}}", "--seed={{seed}}"]
+envs = { MODE = "{{name}}-fast" }
+preprocess = [{ cmd = "touch", args = ["{{name}}.pre"] }]
+[[assert.golden]]
+file = "{{name}}.log"
+same-as = "{{name}}.stdout"
+"#,
+  );
+  let built = fixture.args().rebuild().unwrap();
+  let result = _test(built).await.unwrap();
+  assert_eq!(result.count_ok, 1);
+  let task_workdir = workdir.join("task.sh");
+  let cmd = std::fs::read_to_string(task_workdir.join("__debug__.task.cmd")).unwrap();
+  let seed = crate::config::task_seed(&rootdir.join("task.sh"), built);
+  for want in [
+    "==== COMMAND ===\n```bash\n".to_owned(),
+    "export \"MODE\"=\"task-fast\"\n".to_owned(),
+    format!(
+      "cd {:?}\n\"bash\" \\\n\t\"task.sh\" \\\n\t\"--seed={seed}\"\n",
+      task_workdir
+    ),
+    "==== PREPROCESS 1 ===\n".to_owned(),
+    "\"touch\" \\\n\t\"task.pre\"\n".to_owned(),
+    format!("==== GOLDEN ===\n{}\n", task_workdir.join("task.log").display()),
+    format!("  same-as: {}\n", task_workdir.join("task.stdout").display()),
+  ] {
+    assert!(cmd.contains(&want), "{want:?} not in:\n{cmd}");
+  }
+  // not written with `--nodebug`
+  std::fs::remove_dir_all(workdir).unwrap();
+  _ = _test(fixture.args().nodebug().rebuild().unwrap()).await.unwrap();
+  assert!(!task_workdir.join("__debug__.task.cmd").exists());
+}
+
+#[tokio::test]
+async fn test_process_logs() {
+  let fixture = Fixture::new("process-logs");
+  let workdir = &fixture.workdir;
+  fixture.write("task.sh", "echo ok");
+  fixture.write(
+    "task.toml",
+    r#"
+preprocess = [
+  { cmd = "bash", args = ["-c", "echo 'cache miss'"] },
+  { cmd = "bash", args = ["-c", "echo 'cache hit'; echo warn >&2"] },
+]
+postprocess = [{ cmd = "bash", args = ["-c", "exit 0"] }]
+[[assert.golden]]
+file = "__debug__.preprocess.2.stdout"
+match = [{ pattern = 'cache hit', count = 1 }]
+"#,
+  );
+  // written even with `--nodebug`
+  let args = || fixture.args().nodebug();
+  let result = _test(args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.count_ok, 1);
+  let read =
+    |name: &str| std::fs::read_to_string(workdir.join("task.sh").join(name)).unwrap();
+  assert_eq!(read("__debug__.preprocess.1.stdout"), "cache miss\n");
+  assert_eq!(read("__debug__.preprocess.2.stderr"), "warn\n");
+  assert_eq!(read("__debug__.preprocess.2.status"), "0\n");
+  assert_eq!(read("__debug__.postprocess.1.status"), "0\n");
+  // the failed step is the last one written
+  fixture.write(
+    "task.toml",
+    r#"preprocess = [{ cmd = "bash", args = ["-c", "echo err >&2; exit 3"] }, { cmd = "true" }]"#,
+  );
+  let result = _test(args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.faileds.len(), 1);
+  assert_eq!(read("__debug__.preprocess.1.status"), "3\n");
+  assert_eq!(read("__debug__.preprocess.1.stderr"), "err\n");
+  assert!(!workdir.join("task.sh/__debug__.preprocess.2.status").exists());
+}
+
+#[tokio::test]
+async fn test_postprocess_results() {
+  let fixture = Fixture::new("postprocess-results");
+  let workdir = &fixture.workdir;
+  fixture.write("task.sh", "sleep 0.2; exit 3");
+  let results = workdir.join("task.sh/results");
+  let write_toml = |exit_code: i32, postprocess_on: &str, cmd: &str| {
+    fixture.write(
+      "task.toml",
+      format!(
+        r#"
+postprocess-on = "{postprocess_on}"
+postprocess = [{{ cmd = "bash", args = ["-c", '{cmd}'] }}]
+[assert]
+exit-code = {exit_code}
+"#
+      ),
+    );
+  };
+  let echo =
+    r#"echo "$REGRESSION_STATE $REGRESSION_EXIT_CODE $REGRESSION_DURATION_MS" > results"#;
+  // the postprocess runs after the assertions, with the results
+  write_toml(3, "always", echo);
+  let result = _test(fixture.args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.count_ok, 1);
+  let got = std::fs::read_to_string(&results).unwrap();
+  let [state, exit_code, duration_ms] = got.split_whitespace().collect::<Vec<_>>()[..]
+  else {
+    panic!("{got}");
+  };
+  assert_eq!((state, exit_code), ("pass", "3"));
+  assert!(duration_ms.parse::<u64>().unwrap() >= 200, "{got}");
+  // only on the failed ones
+  write_toml(0, "pass", echo);
+  let result = _test(fixture.args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.faileds.len(), 1);
+  assert!(!results.exists());
+  write_toml(0, "fail", echo);
+  let result = _test(fixture.args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.faileds.len(), 1);
+  assert!(std::fs::read_to_string(&results).unwrap().starts_with("fail 3 "));
+  // the failed postprocess still fails the task
+  write_toml(3, "always", "exit 1");
+  let result = _test(fixture.args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.faileds.len(), 1);
+}
+
+#[tokio::test]
+async fn test_no_capture() {
+  let fixture = Fixture::new("no-capture");
+  let (rootdir, workdir) = (&fixture.rootdir, &fixture.workdir);
+  for name in ["a", "b"] {
+    fixture.write(format!("{name}.sh"), "echo ok > out");
+    // not the real stdout, which can not be asserted with `--no-capture`
+    fixture.write(format!("{GOLDEN_DIR}/{name}.stdout"), "x");
+    fixture.write(
+      format!("{name}.toml"),
+      "[[assert.golden]]\nfile = \"{{name}}.stdout\"\nequal = true\n[[assert.golden]]\nfile = \"out\"\nmatch = [{ pattern = 'ok', count = 1 }]",
+    );
+  }
+  let args = || fixture.args().no_capture();
+  // the single selected task streams its outputs, the other goldens are kept
+  let result = _test(args().include([rootdir.join("a.sh")]).rebuild().unwrap())
+    .await
+    .unwrap();
+  assert_eq!(result.count_ok, 1);
+  assert!(!workdir.join("a.sh/a.stdout").exists());
+  assert!(workdir.join("a.sh/out").exists());
+  // ignored for more tasks
+  let result = _test(args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.faileds.len(), 2);
+  assert!(workdir.join("a.sh/a.stdout").exists());
+}
+
+#[tokio::test]
+async fn test_show_skipped() {
+  let fixture = Fixture::new("show-skipped");
+  let rootdir = &fixture.rootdir;
+  for task in ["a/x.sh", "a/y.sh", "b/z.sh", "b/sub/w.sh", "c.sh"] {
+    fixture.write(task, "echo ok");
+  }
+  fixture.write("a/x.toml", "ignore = true");
+  let args = |show| {
+    fixture
+      .args()
+      .short_ids()
+      .include([rootdir.join("a/x.sh"), rootdir.join("a/y.sh")])
+      .show_skipped(show)
+  };
+  let result = _test(args(ShowSkipped::Dirs).rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.count_ok, 1);
+  let skipped = result.skipped.unwrap();
+  assert_eq!(skipped.ignoreds, ["a/x.sh"]);
+  assert_eq!(
+    skipped.lines(""),
+    "ignored:\n     a/x.sh\nfiltered out:\n     .: 1\n     b: 2\n"
+  );
+  let result = _test(args(ShowSkipped::All).rebuild().unwrap()).await.unwrap();
+  assert_eq!(
+    result.skipped.unwrap().lines("# "),
+    "# ignored:\n#      a/x.sh\n# filtered out:\n#      b/sub/w.sh\n#      b/z.sh\n#      c.sh\n"
+  );
+  // not collected by default
+  let args = fixture.args();
+  let result = _test(args.rebuild().unwrap()).await.unwrap();
+  assert!(result.skipped.is_none());
+}
+
+#[tokio::test]
+async fn test_unused_goldens() {
+  let fixture = Fixture::new("unused-goldens");
+  let rootdir = &fixture.rootdir;
+  let golden_dir = rootdir.join(GOLDEN_DIR);
+  let nested_golden_dir = rootdir.join("nested").join(GOLDEN_DIR);
+  std::fs::create_dir_all(golden_dir.join("sub")).unwrap();
+  std::fs::create_dir_all(&nested_golden_dir).unwrap();
+  fixture.write(
+    "a.sh",
+    "echo ok > out.log\nmkdir sub\necho 1 > sub/x.log\necho 2 > sub/y.log\necho final 1.5",
+  );
+  fixture.write(
+    "a.toml",
+    "[[assert.golden]]\nfile = \"out.log\"\nequal = true\n[[assert.golden]]\nfile = \"sub/*.log\"\nequal = true\n[[assert.golden]]\nfile = \"a.stdout\"\nvalue = [{ pattern-before = 'final', value-file = \"a.final\" }]",
+  );
+  for (golden, content) in [
+    ("out.log", "ok\n"),
+    ("sub/x.log", "1\n"),
+    ("sub/y.log", "2\n"),
+    ("a.final", "1.5"),
+    // the orphaned one
+    ("sub/stale.log", "0\n"),
+  ] {
+    std::fs::write(golden_dir.join(golden), content).unwrap();
+  }
+  // the nested `__golden__` of another directory
+  fixture.write("nested/b.sh", "echo ok > out.log");
+  fixture.write("nested/b.toml", "[[assert.golden]]\nfile = \"out.log\"\nequal = true");
+  std::fs::write(nested_golden_dir.join("out.log"), "ok\n").unwrap();
+  std::fs::write(nested_golden_dir.join("stale.log"), "").unwrap();
+  let result = _test(fixture.args().check_unused_goldens().rebuild().unwrap())
+    .await
+    .unwrap();
+  assert_eq!(result.count_ok, 2);
+  assert_eq!(
+    result.unused_goldens,
+    [golden_dir.join("sub/stale.log"), nested_golden_dir.join("stale.log")]
+  );
+  assert_eq!(result.status(), TestStatus::Passed);
+  // escalated to failure
+  let result = _test(fixture.args().deny_unused_goldens().rebuild().unwrap())
+    .await
+    .unwrap();
+  assert!(result.unused_goldens.is_empty());
+  assert_eq!(result.denieds.len(), 2);
+  assert_eq!(result.status(), TestStatus::Failed);
+  // the directory of a filtered out task is not checked
+  let result = _test(
+    fixture
+      .args()
+      .deny_unused_goldens()
+      .include([rootdir.join("a.sh")])
+      .rebuild()
+      .unwrap(),
+  )
+  .await
+  .unwrap();
+  assert_eq!(result.denieds, [(golden_dir.join("sub/stale.log"), "unused")]);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_inject_env() {
+  use std::os::unix::fs::PermissionsExt as _;
+  let fixture = Fixture::new("inject-env");
+  let (rootdir, workdir) = (&fixture.rootdir, &fixture.workdir);
+  fixture.write(
+    "task.sh",
+    r#"echo "$REGRESSION_NAME|$REGRESSION_EXTENSION|$REGRESSION_ROOTDIR|${name-unset}" > out"#,
+  );
+  fixture.write(format!("{GOLDEN_DIR}/out"), "");
+  let cmp = rootdir.join("cmp.custom");
+  std::fs::write(
+    &cmp,
+    "#!/bin/sh\necho \"$REGRESSION_EPSILON|${epsilon-unset}\" > epsilon",
+  )
+  .unwrap();
+  std::fs::set_permissions(&cmp, std::fs::Permissions::from_mode(0o755)).unwrap();
+  let write_toml = |toml_str: &str| {
+    fixture.write("task.toml", format!(
+        "{toml_str}\nepsilon = 0.5\n[[assert.golden]]\nfile = \"out\"\ncustom = [{{ cmd = \"{{{{rootdir}}}}/cmp.custom\" }}]"
+      ));
+  };
+  let read = |name| std::fs::read_to_string(workdir.join("task.sh").join(name)).unwrap();
+  let rootdir_abs = rootdir.canonicalize().unwrap();
+  // the namespaced ones by default
+  write_toml("");
+  assert_eq!(_test(fixture.args().rebuild().unwrap()).await.unwrap().count_ok, 1);
+  let (out, epsilon) = (read("out"), read("epsilon"));
+  assert_eq!(out, format!("task|sh|{}|unset\n", rootdir_abs.display()));
+  assert_eq!(epsilon, "0.5|unset\n");
+  // with the old names
+  write_toml("legacy-env = true");
+  assert_eq!(_test(fixture.args().rebuild().unwrap()).await.unwrap().count_ok, 1);
+  let (out, epsilon) = (read("out"), read("epsilon"));
+  assert_eq!(out, format!("task|sh|{}|task\n", rootdir_abs.display()));
+  assert_eq!(epsilon, "0.5|0.5\n");
+  // opt out
+  write_toml("inject-env = false\nlegacy-env = true");
+  assert_eq!(_test(fixture.args().rebuild().unwrap()).await.unwrap().count_ok, 1);
+  assert_eq!(read("out"), "|||unset\n");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_custom_workdir() {
+  use std::os::unix::fs::PermissionsExt as _;
+  let fixture = Fixture::new("custom-workdir");
+  let (rootdir, workdir) = (&fixture.rootdir, &fixture.workdir);
+  std::fs::create_dir_all(rootdir.join("tools")).unwrap();
+  fixture.write("task.sh", "echo ok > out");
+  fixture.write(format!("{GOLDEN_DIR}/out"), "ok\n");
+  // asserts its own CWD, and the paths are still valid
+  let cmp = rootdir.join("tools/cmp.custom");
+  std::fs::write(&cmp, "#!/bin/sh\n[ \"$(pwd -P)\" = \"$WANT\" ] && cmp \"$1\" \"$2\"")
+    .unwrap();
+  std::fs::set_permissions(&cmp, std::fs::Permissions::from_mode(0o755)).unwrap();
+  let write_toml = |cwd: &str| {
+    fixture.write("task.toml", format!(
+        "[[assert.golden]]\nfile = \"out\"\ncustom = [{{ cmd = \"{{{{rootdir}}}}/tools/cmp.custom\", envs = {{ WANT = \"{{{{rootdir}}}}/tools\" }}{cwd} }}]"
+      ));
+  };
+  write_toml(", workdir = \"{{rootdir}}/tools\"");
+  let result = _test(fixture.args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.count_ok, 1);
+  // the task's workdir by default, shown in the report
+  write_toml("");
+  let result = _test(fixture.args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.faileds.len(), 1);
+  let report = std::fs::read_to_string(workdir.join("task.sh/task.report")).unwrap();
+  let cd = format!("cd {:?}\n", workdir.join("task.sh"));
+  assert!(report.contains(&cd), "{report}");
+}
+
+#[tokio::test]
+async fn test_assert_retry() {
+  let fixture = Fixture::new("assert-retry");
+  let workdir = &fixture.workdir;
+  fixture.write("task.sh", "echo 1 >> count");
+  let write_toml = |retry: &str| {
+    fixture.write("task.toml", format!(
+        "process-group = true\n[assert]\n{retry}\n[[assert.golden]]\nfile = \"out\"\nmatch = [{{ pattern = 'done', count = 1 }}]"
+      ));
+  };
+  let task_workdir = workdir.join("task.sh");
+  let report = task_workdir.join("task.report");
+  write_toml("");
+  let result = _test(fixture.args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.faileds.len(), 1);
+  // the command is not rerun
+  write_toml("assert-retry = { attempts = 20, delay-ms = 100 }");
+  _ = std::fs::remove_dir_all(workdir);
+  // the asynchronous writer is the test itself, which writes the golden only
+  // after the task created `count`, so nothing outlives the task
+  let writer = tokio::spawn({
+    let task_workdir = task_workdir.clone();
+    async move {
+      while !task_workdir.join("count").exists() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+      }
+      std::fs::write(task_workdir.join("out"), "done\n").unwrap();
+    }
+  });
+  let result = _test(fixture.args().rebuild().unwrap()).await.unwrap();
+  writer.abort();
+  assert_eq!(result.count_ok, 1);
+  let count = std::fs::read_to_string(task_workdir.join("count")).unwrap();
+  assert_eq!(count, "1\n");
+  // exhausted, with the errors of the last attempt
+  write_toml("assert-retry = { attempts = 2, delay-ms = 10 }");
+  let result = _test(fixture.args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.faileds.len(), 1);
+  let report = std::fs::read_to_string(report).unwrap();
+  assert!(report.contains("still failed after 2 attempts of `assert-retry`"), "{report}");
+  assert!(report.contains("file \"out\": no such file"), "{report}");
+}
+
+#[tokio::test]
+async fn test_clear_env() {
+  let fixture = Fixture::new("clear-env");
+  let workdir = &fixture.workdir;
+  // set by cargo for the tests, as a leaked variable of the shell
+  let leaked = std::env::var("CARGO_PKG_NAME").unwrap();
+  fixture.write("task.sh", "echo \"${CARGO_PKG_NAME:-}|${K:-}\"");
+  fixture.write(
+    "task.toml",
+    r#"
+envs = { K = "v" }
+preprocess = [{ cmd = "bash", args = ["-c", "echo ${CARGO_PKG_NAME:-}"] }]
+"#,
+  );
+  let read =
+    |name: &str| std::fs::read_to_string(workdir.join("task.sh").join(name)).unwrap();
+  let result = _test(fixture.args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.count_ok, 1);
+  assert_eq!(read("task.stdout"), format!("{leaked}|v\n"));
+  assert_eq!(read("__debug__.preprocess.1.stdout"), format!("{leaked}\n"));
+  assert!(!read("__debug__.task.cmd").contains("env -i"));
+  let result = _test(fixture.args().clear_env().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.count_ok, 1);
+  assert_eq!(read("task.stdout"), "|v\n");
+  assert_eq!(read("__debug__.preprocess.1.stdout"), "\n");
+  assert!(read("__debug__.task.toml").contains("clear-env = true"));
+  assert!(read("__debug__.task.cmd").contains("env -i \\\n\t\"PATH="));
+  // by the config, with the variable allowed
+  fixture.write(
+    "task.toml",
+    r#"
+clear-env = true
+env-allowlist = ["PATH", "CARGO_PKG_NAME"]
+"#,
+  );
+  let result = _test(fixture.args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.count_ok, 1);
+  assert_eq!(read("task.stdout"), format!("{leaked}|\n"));
+  // only the unset ones are removed, `envs` are still applied
+  fixture.write(
+    "task.toml",
+    r#"
+unset-envs = ["CARGO_PKG_NAME", "K"]
+envs = { K = "v" }
+preprocess = [{ cmd = "bash", args = ["-c", "echo ${CARGO_PKG_NAME:-}${PATH:+path}"] }]
+"#,
+  );
+  let result = _test(fixture.args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.count_ok, 1);
+  assert_eq!(read("task.stdout"), "|v\n");
+  assert_eq!(read("__debug__.preprocess.1.stdout"), "path\n");
+  assert!(read("__debug__.task.cmd").contains("unset \"CARGO_PKG_NAME\"\n"));
+}
+
+#[tokio::test]
+async fn test_assert_sugar() {
+  let fixture = Fixture::new("assert-sugar");
+  let workdir = &fixture.workdir;
+  fixture.write(format!("{GOLDEN_DIR}/quiet.stdout"), "hello\n");
+  for (name, script, toml) in [
+    (
+      "quiet",
+      "echo hello",
+      "stdout-equal = true\nstderr-empty = true\nstdout-match = [{ pattern = 'hel+o', count = 1 }]",
+    ),
+    ("noisy", "echo hello; echo warn >&2", "stderr-empty = true"),
+  ] {
+    fixture.write(format!("{name}.sh"), script);
+    fixture.write(format!("{name}.toml"), format!("[assert]\n{toml}"));
+  }
+  let result = _test(fixture.args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.count_ok, 1);
+  assert_eq!(result.faileds.len(), 1);
+  assert!(result.faileds[0].id.contains("noisy.sh"));
+  // the debug toml shows the desugared goldens
+  let toml =
+    std::fs::read_to_string(workdir.join("quiet.sh/__debug__.quiet.toml")).unwrap();
+  assert!(!toml.contains("stdout-equal"), "{toml}");
+  assert!(toml.contains("file = \"quiet.stdout\"\nequal = true"), "{toml}");
+  assert!(toml.contains("file = \"quiet.stderr\""), "{toml}");
+  // conflict with the explicit golden on the same stream
+  for toml in [
+    "[assert]\nstdout-equal = true\n[[assert.golden]]\nfile = '{{name}}.stdout'\nequal = false",
+    "[assert]\nstdout-match = []\n[[assert.golden]]\nfile = '{{name}}.stdout'\nmatch = []",
+    "stderr = 'stdout'\n[assert]\nstderr-empty = true",
+  ] {
+    fixture.write("noisy.toml", toml);
+    let Err(errs) = _test(fixture.args().rebuild().unwrap()).await else {
+      panic!("should conflict: {toml}")
+    };
+    assert!(
+      matches!(errs.as_slice(), [BuildError::AssertSugar(path, _)] if path.ends_with("noisy.sh")),
+      "{errs:?}"
+    );
+  }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_protect_inputs() {
+  let fixture = Fixture::new("protect-inputs");
+  let rootdir = &fixture.rootdir;
+  // appends to its own input through the symlink
+  let script = "echo '# appended' >> bad.sh\n";
+  let write = || {
+    fixture.write("bad.sh", script);
+    fixture.write("good.sh", "cat good.data\n");
+    fixture.write("good.data", "data\n");
+  };
+  write();
+  let result = _test(fixture.args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.count_ok, 2);
+  write();
+  let result = _test(fixture.args().protect_inputs().rebuild().unwrap())
+    .await
+    .unwrap();
+  assert_eq!(result.count_ok, 1);
+  let [Failed { state: FailedState::ReportSaved(report), .. }] = &result.faileds[..]
+  else {
+    panic!("bad.sh should fail")
+  };
+  let report = std::fs::read_to_string(report).unwrap();
+  assert!(report.contains("bad.sh\" modified by the task"), "{report}");
+  assert!(report.contains("+# appended"), "{report}");
+  // by the config, and the copied inputs are never modified
+  write();
+  fixture.write("bad.toml", "protect-inputs = true");
+  let result = _test(fixture.args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.faileds.len(), 1);
+  write();
+  let result = _test(fixture.args().copy_inputs().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.count_ok, 2);
+  assert_eq!(std::fs::read_to_string(rootdir.join("bad.sh")).unwrap(), script);
+}
+
+#[tokio::test]
+async fn test_retries() {
+  let fixture = Fixture::new("retries");
+  let rootdir = &fixture.rootdir;
+  // fail the first 2 attempts, the counter is out of the recreated workdir
+  fixture.write(
+    "flaky.sh",
+    "n=$(cat \"$COUNTER\" 2>/dev/null || echo 0)\necho $((n + 1)) > \"$COUNTER\"\n[ \"$n\" -ge 2 ]",
+  );
+  for (retries, ok) in [(1, false), (2, true)] {
+    _ = std::fs::remove_file(rootdir.join("counter"));
+    fixture.write(
+      "flaky.toml",
+      format!(
+        "retries = {retries}\nretry-backoff-ms = 100\nretry-backoff-exponential = true\n\
+         envs = {{ COUNTER = \"{{{{rootdir}}}}/counter\" }}"
+      ),
+    );
+    let now = Instant::now();
+    let result = _test(fixture.args().rebuild().unwrap()).await.unwrap();
+    assert_eq!(result.count_ok, usize::from(ok), "retries = {retries}");
+    let counter = std::fs::read_to_string(rootdir.join("counter")).unwrap();
+    assert_eq!(counter.trim(), (retries + 1).to_string());
+    // 100 ms, then 200 ms
+    assert!(now.elapsed() >= Duration::from_millis(if ok { 300 } else { 100 }));
+  }
+  fixture.write("flaky.toml", "retry-backoff-ms = -1");
+  let Err(errs) = _test(fixture.args().rebuild().unwrap()).await else {
+    panic!("should be invalid")
+  };
+  assert!(matches!(errs.as_slice(), [BuildError::NegativeBackoff(_, -1)]), "{errs:?}");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_process_group() {
+  let fixture = Fixture::new("process-group");
+  let workdir = &fixture.workdir;
+  // leave a background child, or time out with it
+  fixture.write("orphan.sh", "sleep 30 &\necho $! > orphan.pid");
+  fixture.write("hang.sh", "sleep 30 &\necho $! > hang.pid\nsleep 30");
+  fixture.write("hang.toml", "timeout = 1");
+  fixture.write("clean.sh", "sleep 0 &\nwait");
+  let pid = |name: &str| {
+    let pid = std::fs::read_to_string(workdir.join(format!("{name}.sh/{name}.pid")));
+    nix::unistd::Pid::from_raw(pid.unwrap().trim().parse().unwrap())
+  };
+  // the zombies are not reaped by the init of some containers
+  let alive = |pid: nix::unistd::Pid| {
+    std::fs::read_to_string(format!("/proc/{pid}/stat")).map_or_else(
+      |_| nix::sys::signal::kill(pid, None).is_ok(),
+      |stat| !stat.rsplit(')').next().unwrap().trim_start().starts_with('Z'),
+    )
+  };
+  let result = _test(fixture.args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.count_ok, 2);
+  let orphan = pid("orphan");
+  assert!(alive(orphan));
+  _ = nix::sys::signal::kill(orphan, nix::sys::signal::Signal::SIGKILL);
+  _ = nix::sys::signal::kill(pid("hang"), nix::sys::signal::Signal::SIGKILL);
+  let result = _test(fixture.args().process_group().rebuild().unwrap())
+    .await
+    .unwrap();
+  assert_eq!(result.count_ok, 1);
+  assert_eq!(result.faileds.len(), 2);
+  let report = std::fs::read_to_string(workdir.join("orphan.sh/orphan.report")).unwrap();
+  assert!(report.contains("left orphaned processes"), "{report}");
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  assert!(!alive(pid("orphan")));
+  assert!(!alive(pid("hang")));
+}
+
+#[tokio::test]
+async fn test_max_report_bytes() {
+  let fixture = Fixture::new("max-report-bytes");
+  let workdir = &fixture.workdir;
+  // a huge diff, all the output lines are inserted
+  fixture.write("huge.sh", "seq -f 'line %g of the output' 1 100000");
+  fixture.write(format!("{GOLDEN_DIR}/huge.stdout"), "");
+  fixture
+    .write("huge.toml", "[[assert.golden]]\nfile = \"{{name}}.stdout\"\nequal = true");
+  let args = || fixture.args().nodebug().max_report_bytes(64 << 10);
+  let result = _test(args().rebuild().unwrap()).await.unwrap();
+  assert_eq!(result.faileds.len(), 1);
+  let report = std::fs::read_to_string(workdir.join("huge.sh/huge.report")).unwrap();
+  let trailer =
+    "\n==== TRUNCATED ===\nreport truncated at 65536 bytes; 1 error(s) omitted\n";
+  assert!(report.ends_with(trailer), "{}", &report[report.len() - 200..]);
+  assert_eq!(report.len(), (64 << 10) + trailer.len());
+  // the same cap for `--print-errs`
+  let result = _test(args().print_errs().rebuild().unwrap()).await.unwrap();
+  let printed = result.faileds[0].state.to_string();
+  assert!(printed.ends_with(trailer));
+  assert!(printed.len() < (64 << 10) + 1024);
+  assert!(result.faileds[0].state.errs_text().ends_with(trailer));
 }
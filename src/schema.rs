@@ -0,0 +1,652 @@
+//! Hand-maintained JSON Schema of the TOML config (`__all__.toml` & `xxx.toml`).
+//!
+//! Keep it in sync with the serde definitions in `config.rs` and `assert.rs`,
+//! `test_schema` in `config.rs` fails when a key here can not be deserialized.
+use core::fmt::{self, Write as _};
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Type {
+  Bool,
+  Integer,
+  Number,
+  String,
+  /// String of these variants
+  Enum(&'static [&'static str]),
+  Array(&'static Type),
+  /// Table with arbitrary keys
+  Map(&'static Type),
+  Ref(&'static str),
+  /// Any of the types, the first one is used as sample
+  OneOf(&'static [Type]),
+  /// Any value, e.g., the expected JSON of `json-contains`
+  Any,
+}
+
+#[derive(Debug)]
+pub(crate) struct Property {
+  pub(crate) name: &'static str,
+  description: &'static str,
+  pub(crate) ty: Type,
+}
+
+#[derive(Debug)]
+pub(crate) struct Def {
+  pub(crate) name: &'static str,
+  description: &'static str,
+  pub(crate) properties: &'static [Property],
+  required: &'static [&'static str],
+  /// Exactly one of them should be specified
+  one_of: &'static [&'static str],
+  /// At least one of them should be specified
+  any_of: &'static [&'static str],
+}
+
+const fn prop(name: &'static str, description: &'static str, ty: Type) -> Property {
+  Property { name, description, ty }
+}
+
+const STRINGS: Type = Type::Array(&Type::String);
+const ENVS: Type = Type::Map(&Type::String);
+/// The signals of both Linux and macOS, as the names parsed by `SignalName`
+pub(crate) const SIGNALS: &[&str] = &[
+  "SIGHUP",
+  "SIGINT",
+  "SIGQUIT",
+  "SIGILL",
+  "SIGTRAP",
+  "SIGABRT",
+  "SIGBUS",
+  "SIGFPE",
+  "SIGKILL",
+  "SIGUSR1",
+  "SIGSEGV",
+  "SIGUSR2",
+  "SIGPIPE",
+  "SIGALRM",
+  "SIGTERM",
+  "SIGCHLD",
+  "SIGCONT",
+  "SIGSTOP",
+  "SIGTSTP",
+  "SIGTTIN",
+  "SIGTTOU",
+  "SIGURG",
+  "SIGXCPU",
+  "SIGXFSZ",
+  "SIGVTALRM",
+  "SIGPROF",
+  "SIGWINCH",
+  "SIGIO",
+  "SIGSYS",
+];
+
+pub(crate) const ROOT: Def = Def {
+  name: "Config",
+  description: "cargo-regression config, in `xx/__all__.toml` or `xxx.toml`",
+  properties: &[
+    prop("ignore", "Ignore that task", Type::Bool),
+    prop(
+      "only-on",
+      "Only run that task on these platforms, e.g., [\"linux\", \"macos\"], otherwise it is skipped",
+      STRINGS,
+    ),
+    prop("skip-on", "Skip that task on these platforms, e.g., [\"windows\"]", STRINGS),
+    prop("print-errs", "Print errors rather than save to reports", Type::Bool),
+    prop(
+      "clear-env",
+      "Run that task and its pre/postprocess with a cleared environment, only `envs` and `env-allowlist` are passed",
+      Type::Bool,
+    ),
+    prop(
+      "env-allowlist",
+      "The process's envs still passed with `clear-env`, default is [\"PATH\", \"HOME\", \"TMPDIR\"]",
+      STRINGS,
+    ),
+    prop(
+      "unset-envs",
+      "Remove these variables inherited from the process, e.g., [\"RUST_LOG\"], before applying `envs`",
+      STRINGS,
+    ),
+    prop(
+      "inject-env",
+      "Export `REGRESSION_NAME`, `REGRESSION_EXTENSION`, and `REGRESSION_ROOTDIR`, default is true",
+      Type::Bool,
+    ),
+    prop(
+      "legacy-env",
+      "Deprecated, also export `name`, `extension`, and `rootdir`, and pass `epsilon` to the custom asserts",
+      Type::Bool,
+    ),
+    prop(
+      "protect-inputs",
+      "Fail that task if it modifies or deletes the linked inputs, e.g., rewrites its own input",
+      Type::Bool,
+    ),
+    prop(
+      "tmpdir",
+      "Export a unique temp dir as `TMPDIR`/`TMP`/`TEMP`, removed after the task",
+      Type::Bool,
+    ),
+    prop(
+      "permit",
+      "The permit cost of that task, or a percentage of total permits, e.g., \"50%\", default is --permit-default (0)",
+      Type::OneOf(&[Type::Integer, Type::String]),
+    ),
+    prop(
+      "resources",
+      "The counts of the named resources that task holds, e.g., `{ gpu = 1 }`, whose totals are declared by `--resource gpu=2`",
+      Type::Map(&Type::Integer),
+    ),
+    prop(
+      "depends-on",
+      "The tasks to run before that task, relative to the task file, e.g., \"../build/compile_lib.sh\"",
+      STRINGS,
+    ),
+    prop("timeout", "Timeout second for each task, default is 600", Type::Integer),
+    prop(
+      "retries",
+      "Rerun the failed task at most that many times, default is 0",
+      Type::Integer,
+    ),
+    prop(
+      "retry-backoff-ms",
+      "The milliseconds to wait before each retry, should be non-negative, default is 0",
+      Type::Integer,
+    ),
+    prop(
+      "process-group",
+      "Unix only, run that task in a new process group, kill it after the task or the timeout, and fail if the task left orphaned processes",
+      Type::Bool,
+    ),
+    prop(
+      "retry-backoff-exponential",
+      "Double the `retry-backoff-ms` after each retry",
+      Type::Bool,
+    ),
+    prop(
+      "flock",
+      "The advisory file lock held by that task, from preprocess until it finishes, shared with other processes",
+      Type::String,
+    ),
+    prop(
+      "flock-timeout",
+      "The seconds to wait for `flock`, default is `timeout`",
+      Type::Integer,
+    ),
+    prop("cmd", "The executable path to execute task", Type::String),
+    prop(
+      "wrapper",
+      "Wrap the command, e.g., \"valgrind --error-exitcode=99\", or `false` to disable",
+      Type::OneOf(&[Type::String, Type::Bool]),
+    ),
+    prop(
+      "shell",
+      "Run `cmd` and `args` as one command line via `sh -c`, or the given shell",
+      Type::OneOf(&[Type::String, Type::Bool]),
+    ),
+    prop(
+      "preprocess",
+      "Commands executed before the task",
+      Type::Array(&Type::Ref("PrePostProcess")),
+    ),
+    prop(
+      "postprocess",
+      "Commands executed after the task and its assertions",
+      Type::Array(&Type::Ref("PrePostProcess")),
+    ),
+    prop(
+      "postprocess-on",
+      "When the postprocess runs, by the task's state, default is \"always\"",
+      Type::Enum(&["always", "pass", "fail"]),
+    ),
+    prop(
+      "extensions",
+      "The task identifier extensions, only in `xx/__all__.toml`",
+      STRINGS,
+    ),
+    prop(
+      "golden-generator",
+      "Command that populates `__golden__` once, only in `xx/__all__.toml`",
+      Type::Ref("PrePostProcess"),
+    ),
+    prop("epsilon", "The value assert's tolerance, default is 1e-10", Type::Number),
+    prop(
+      "stream-threshold",
+      "Stream the larger output files (bytes) line by line, default is 256 MiB",
+      Type::Integer,
+    ),
+    prop(
+      "diff-threshold",
+      "Only report the first difference for the larger files (bytes) in `equal` and `same-as`, default is 16 MiB",
+      Type::Integer,
+    ),
+    prop(
+      "diff-max-hunks",
+      "Truncate the diffs of `equal` and `same-as` after that many hunks, default is 32",
+      Type::Integer,
+    ),
+    prop(
+      "args",
+      "The arguements for execute task, default `[\"{{name}}.{{extension}}\"]`",
+      STRINGS,
+    ),
+    prop("envs", "The environment variables", ENVS),
+    prop(
+      "stdout",
+      "The file in workdir that the task's stdout is written into, default is \"{{name}}.stdout\"",
+      Type::String,
+    ),
+    prop(
+      "stderr",
+      "The file in workdir that the task's stderr is written into, default is \"{{name}}.stderr\", or \"stdout\" to merge it into stdout",
+      Type::String,
+    ),
+    prop("extern-files", "Link other files than `{{name}}.*` into workdir", STRINGS),
+    prop("inputs", "Glob patterns of extra files in the task's dir to link", STRINGS),
+    prop(
+      "report-files",
+      "Glob patterns of files in workdir, whose tails are appended to the failure report",
+      STRINGS,
+    ),
+    prop(
+      "report-tail",
+      "The last lines of each `report-files` in the failure report, default is 50",
+      Type::Integer,
+    ),
+    prop(
+      "max-report-bytes",
+      "The max bytes of the failure report (or printed errors), the rest is truncated, default is `--max-report-bytes`",
+      Type::Integer,
+    ),
+    prop("extend", "Extend the super's configs", Type::Ref("Extend")),
+    prop("assert", "The assertions", Type::Ref("Assert")),
+  ],
+  required: &[],
+  one_of: &[],
+  any_of: &[],
+};
+
+pub(crate) const DEFS: &[Def] = &[
+  Def {
+    name: "PrePostProcess",
+    description: "A pre/post-process command",
+    properties: &[
+      prop("cmd", "The executable path", Type::String),
+      prop("args", "The arguements, default is `[]`", STRINGS),
+      prop(
+        "workdir",
+        "The working directory, default is the task's workdir",
+        Type::String,
+      ),
+    ],
+    required: &["cmd"],
+    one_of: &[],
+    any_of: &[],
+  },
+  Def {
+    name: "Extend",
+    description: "Extend the super's configs rather than override them",
+    properties: &[
+      prop("args", "Extend `args`", STRINGS),
+      prop("envs", "Extend `envs`", ENVS),
+      prop("extern-files", "Extend `extern-files`", STRINGS),
+      prop("extensions", "Extend `extensions`, can not contain 'toml'", STRINGS),
+    ],
+    required: &[],
+    one_of: &[],
+    any_of: &[],
+  },
+  Def {
+    name: "Assert",
+    description: "The assertions",
+    properties: &[
+      prop("exit-code", "Assert the exit code, default is 0", Type::Integer),
+      prop(
+        "ignore-exit-codes",
+        "Tolerate these exit codes regardless of `exit-code`",
+        Type::Array(&Type::Integer),
+      ),
+      prop(
+        "signal",
+        "Unix only, assert terminated by the signal, e.g., \"SIGTERM\"",
+        Type::Enum(SIGNALS),
+      ),
+      prop(
+        "max-rss",
+        "Unix only, assert the peak resident set size of the process in bytes",
+        Type::Integer,
+      ),
+      prop(
+        "stdout-equal",
+        "Shorthand of a golden on the stdout file with `equal = true`",
+        Type::Bool,
+      ),
+      prop(
+        "stdout-match",
+        "Shorthand of a golden on the stdout file with these `match`",
+        Type::Array(&Type::Ref("Match")),
+      ),
+      prop(
+        "stderr-empty",
+        "Shorthand of a golden on the stderr file, which should be empty",
+        Type::Bool,
+      ),
+      prop(
+        "assert-retry",
+        "Re-evaluate the failed goldens without rerunning the command",
+        Type::Ref("AssertRetry"),
+      ),
+      prop("golden", "Assert the output files", Type::Array(&Type::Ref("Golden"))),
+    ],
+    required: &[],
+    one_of: &[],
+    any_of: &[],
+  },
+  Def {
+    name: "AssertRetry",
+    description: "Re-evaluate the goldens until they pass, the exit code, signal, and max-rss are asserted once",
+    properties: &[
+      prop(
+        "attempts",
+        "The max evaluations of the goldens, including the first one",
+        Type::Integer,
+      ),
+      prop(
+        "delay-ms",
+        "The milliseconds to wait before each retry, default is 0",
+        Type::Integer,
+      ),
+    ],
+    required: &["attempts"],
+    one_of: &[],
+    any_of: &[],
+  },
+  Def {
+    name: "Golden",
+    description: "Assertions for the output file(s)",
+    properties: &[
+      prop("file", "The output file(s) glob pattern", Type::String),
+      prop(
+        "files",
+        "Multiple output files' glob patterns, sharing the same asserts",
+        STRINGS,
+      ),
+      prop("equal", "The output file should equal to the golden", Type::Bool),
+      prop(
+        "tree",
+        "The output directory should equal to the golden directory, file by file",
+        Type::Bool,
+      ),
+      prop(
+        "same-as",
+        "The output file should equal to another output file in workdir",
+        Type::String,
+      ),
+      prop(
+        "ignore-trailing-whitespace",
+        "Ignore the trailing whitespaces (including newlines) in `equal` and `same-as`",
+        Type::Bool,
+      ),
+      prop(
+        "ignore-trailing-newlines",
+        "Ignore the trailing newlines in `equal` and `same-as`",
+        Type::Bool,
+      ),
+      prop(
+        "match",
+        "Match pattern and assert the count",
+        Type::Array(&Type::Ref("Match")),
+      ),
+      prop(
+        "lines",
+        "Assert the number of the output lines, a trailing newline does not start a new line",
+        Type::Ref("Lines"),
+      ),
+      prop(
+        "value",
+        "Capture float number and assert it",
+        Type::Array(&Type::Ref("Value")),
+      ),
+      prop(
+        "sorted",
+        "The output lines should be in order, compared by the bytes, the leading numbers, or case-insensitively",
+        Type::Enum(&["lines", "numeric", "case-insensitive"]),
+      ),
+      prop("unique", "No output line should appear more than once", Type::Bool),
+      prop(
+        "json-contains",
+        "The output JSON should contain these keys with the same values, the extra keys are ignored",
+        Type::Map(&Type::Any),
+      ),
+      prop(
+        "yaml-equal",
+        "The output YAML should equal to the golden, ignoring the key order and formatting",
+        Type::Bool,
+      ),
+      prop("custom", "External custom assert script", Type::Array(&Type::Ref("Custom"))),
+      prop(
+        "soft",
+        "Report the errors of that golden as warnings, which do not fail the task",
+        Type::Bool,
+      ),
+    ],
+    required: &[],
+    one_of: &["file", "files"],
+    any_of: &[],
+  },
+  Def {
+    name: "Match",
+    description: "Match pattern and assert the number (count) of it",
+    properties: &[
+      prop("pattern", "Regular expression", Type::String),
+      prop("count", "Exact count", Type::Integer),
+      prop("count-at-most", "At most count", Type::Integer),
+      prop("count-at-least", "At least count", Type::Integer),
+    ],
+    required: &["pattern"],
+    one_of: &["count", "count-at-most", "count-at-least"],
+    any_of: &[],
+  },
+  Def {
+    name: "Lines",
+    description: "Assert the number of the output lines",
+    properties: &[
+      prop("count", "Exact count", Type::Integer),
+      prop("count-at-most", "At most count", Type::Integer),
+      prop("count-at-least", "At least count", Type::Integer),
+    ],
+    required: &[],
+    one_of: &["count", "count-at-most", "count-at-least"],
+    any_of: &[],
+  },
+  Def {
+    name: "Value",
+    description: "Capture float number and assert the value of it, by one of `value`, `value-file`, `value-at-most`, `value-at-least`, and/or by `monotonic`",
+    properties: &[
+      prop("pattern-before", "Regular expression before the value", Type::String),
+      prop("pattern-after", "Regular expression after the value", Type::String),
+      prop("value", "Exact value within ±epsilon", Type::Number),
+      prop(
+        "value-file",
+        "The file in the golden dir whose single float is the exact value",
+        Type::String,
+      ),
+      prop("value-at-most", "At most value", Type::Number),
+      prop("value-at-least", "At least value", Type::Number),
+      prop(
+        "monotonic",
+        "The captured values should be monotonic, report the first violating pair",
+        Type::Enum(&["non-decreasing", "non-increasing"]),
+      ),
+      prop("epsilon", "The tolerance, default is the config's `epsilon`", Type::Number),
+      prop(
+        "key-pattern",
+        "The table form, regular expression capturing the key of each line",
+        Type::String,
+      ),
+      prop(
+        "value-pattern",
+        "The table form, regular expression capturing the value on the key's line",
+        Type::String,
+      ),
+      prop(
+        "expect",
+        "The table form, the expected value of each key, e.g., `parse = 1.5`",
+        Type::Map(&Type::OneOf(&[Type::Number, Type::Ref("Expect")])),
+      ),
+      prop(
+        "allow-extra",
+        "The table form, allow the keys not in `expect`, default is false",
+        Type::Bool,
+      ),
+    ],
+    required: &[],
+    one_of: &[],
+    any_of: &["pattern-before", "pattern-after", "key-pattern"],
+  },
+  Def {
+    name: "Expect",
+    description: "The expected value of a key in the table form of value",
+    properties: &[
+      prop("value", "Exact value within ±epsilon", Type::Number),
+      prop("epsilon", "The tolerance, default is the value's `epsilon`", Type::Number),
+    ],
+    required: &["value"],
+    one_of: &[],
+    any_of: &[],
+  },
+  Def {
+    name: "Custom",
+    description: "External custom assert script, called with output and golden paths",
+    properties: &[
+      prop("cmd", "The script path", Type::String),
+      prop("envs", "The environment variables", ENVS),
+      prop(
+        "workdir",
+        "The working directory of the script, default is the task's workdir, then the paths are absolute",
+        Type::String,
+      ),
+    ],
+    required: &["cmd"],
+    one_of: &[],
+    any_of: &[],
+  },
+];
+
+/// The JSON Schema of the TOML config
+pub fn config_schema() -> String {
+  let mut s = String::new();
+  write_schema(&mut s).expect("write to string");
+  s
+}
+
+fn write_schema(f: &mut String) -> fmt::Result {
+  writeln!(f, "{{")?;
+  writeln!(f, "  \"$schema\": \"http://json-schema.org/draft-07/schema#\",")?;
+  writeln!(f, "  \"title\": {:?},", ROOT.name)?;
+  write_def_body(f, &ROOT, 1)?;
+  writeln!(f, ",\n  \"definitions\": {{")?;
+  for (idx, def) in DEFS.iter().enumerate() {
+    writeln!(f, "    {:?}: {{", def.name)?;
+    write_def_body(f, def, 3)?;
+    write!(f, "\n    }}")?;
+    writeln!(f, "{}", if idx + 1 == DEFS.len() { "" } else { "," })?;
+  }
+  writeln!(f, "  }}\n}}")
+}
+
+fn write_def_body(f: &mut String, def: &Def, level: usize) -> fmt::Result {
+  let indent = "  ".repeat(level);
+  writeln!(f, "{indent}\"description\": {:?},", def.description)?;
+  writeln!(f, "{indent}\"type\": \"object\",")?;
+  writeln!(f, "{indent}\"additionalProperties\": false,")?;
+  writeln!(f, "{indent}\"properties\": {{")?;
+  for (idx, p) in def.properties.iter().enumerate() {
+    write!(f, "{indent}  {:?}: {{ \"description\": {:?}, ", p.name, p.description)?;
+    write_type(f, p.ty)?;
+    writeln!(f, " }}{}", if idx + 1 == def.properties.len() { "" } else { "," })?;
+  }
+  write!(f, "{indent}}}")?;
+  if !def.required.is_empty() {
+    write!(f, ",\n{indent}\"required\": {:?}", def.required)?;
+  }
+  let write_group = |f: &mut String, key: &str, group: &[&str]| -> fmt::Result {
+    if !group.is_empty() {
+      write!(f, ",\n{indent}{key:?}: [")?;
+      for (idx, name) in group.iter().enumerate() {
+        let sep = if idx == 0 { "" } else { ", " };
+        write!(f, "{sep}{{ \"required\": [{name:?}] }}")?;
+      }
+      write!(f, "]")?;
+    }
+    Ok(())
+  };
+  write_group(f, "oneOf", def.one_of)?;
+  write_group(f, "anyOf", def.any_of)
+}
+
+fn write_type(f: &mut String, ty: Type) -> fmt::Result {
+  match ty {
+    Type::Bool => write!(f, "\"type\": \"boolean\""),
+    Type::Integer => write!(f, "\"type\": \"integer\""),
+    Type::Number => write!(f, "\"type\": \"number\""),
+    Type::String => write!(f, "\"type\": \"string\""),
+    Type::Enum(variants) => write!(f, "\"type\": \"string\", \"enum\": {variants:?}"),
+    Type::Array(item) => {
+      write!(f, "\"type\": \"array\", \"items\": {{ ")?;
+      write_type(f, *item)?;
+      write!(f, " }}")
+    }
+    Type::Map(item) => {
+      write!(f, "\"type\": \"object\", \"additionalProperties\": {{ ")?;
+      write_type(f, *item)?;
+      write!(f, " }}")
+    }
+    Type::Ref(name) => write!(f, "\"$ref\": \"#/definitions/{name}\""),
+    Type::Any => {
+      write!(f, "\"type\": [\"boolean\", \"number\", \"string\", \"array\", \"object\"]")
+    }
+    Type::OneOf(tys) => {
+      write!(f, "\"oneOf\": [")?;
+      for (idx, ty) in tys.iter().enumerate() {
+        write!(f, "{}{{ ", if idx == 0 { "" } else { ", " })?;
+        write_type(f, *ty)?;
+        write!(f, " }}")?;
+      }
+      write!(f, "]")
+    }
+  }
+}
+
+/// Generate a sample TOML value that contains all keys
+#[cfg(test)]
+pub(crate) fn sample(ty: Type) -> toml::Value {
+  match ty {
+    Type::Bool => toml::Value::Boolean(true),
+    Type::Integer => toml::Value::Integer(1),
+    Type::Number => toml::Value::Float(1.0),
+    Type::String => toml::Value::String("sample".into()),
+    Type::Enum(variants) => toml::Value::String(variants[0].into()),
+    Type::Array(item) => toml::Value::Array(vec![sample(*item)]),
+    Type::Map(item) => {
+      toml::Value::Table([("key".to_owned(), sample(*item))].into_iter().collect())
+    }
+    Type::OneOf(tys) => sample(tys[0]),
+    Type::Any => toml::Value::Integer(1),
+    Type::Ref(name) => {
+      let def = DEFS.iter().find(|def| def.name == name).expect("undefined ref");
+      sample_def(def)
+    }
+  }
+}
+
+#[cfg(test)]
+pub(crate) fn sample_def(def: &Def) -> toml::Value {
+  toml::Value::Table(
+    def
+      .properties
+      .iter()
+      .map(|p| (p.name.to_owned(), sample(p.ty)))
+      .collect(),
+  )
+}
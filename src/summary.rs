@@ -0,0 +1,123 @@
+//! The Markdown summary of `--summary-md`, e.g., to post as a PR comment.
+use core::fmt::{self, Write as _};
+use std::{path::PathBuf, time::Duration};
+
+/// The counts and the failed tasks of a run
+pub(crate) struct Summary<'a> {
+  pub(crate) count_ok: usize,
+  pub(crate) count_ignored: usize,
+  pub(crate) count_filtered: usize,
+  /// The task ids and their errors text
+  pub(crate) faileds: &'a [(String, String)],
+  /// The task ids and their reasons
+  pub(crate) skippeds: &'a [(String, String)],
+  pub(crate) denieds: &'a [(PathBuf, &'static str)],
+  pub(crate) time: Duration,
+  /// The max bytes of each failure's errors text
+  pub(crate) budget: usize,
+}
+
+impl Summary<'_> {
+  pub(crate) fn to_markdown(&self) -> String {
+    let mut s = String::new();
+    self.write_markdown(&mut s).expect("write to string");
+    s
+  }
+  fn write_markdown(&self, f: &mut String) -> fmt::Result {
+    let success = self.faileds.is_empty() && self.denieds.is_empty();
+    writeln!(f, "## Test result: {}\n", if success { "ok" } else { "FAILED" })?;
+    writeln!(f, "| Passed | Failed | Skipped | Ignored | Filtered out | Time |")?;
+    writeln!(f, "| -- | -- | -- | -- | -- | -- |")?;
+    writeln!(
+      f,
+      "| {} | {} | {} | {} | {} | {:.2}s |",
+      self.count_ok,
+      self.faileds.len(),
+      self.skippeds.len(),
+      self.count_ignored,
+      self.count_filtered,
+      self.time.as_secs_f32()
+    )?;
+    if !self.denieds.is_empty() {
+      writeln!(f, "\nDenied:\n")?;
+      for (path, reason) in self.denieds {
+        writeln!(f, "+ `{}` is {reason}", path.display())?;
+      }
+    }
+    if !self.skippeds.is_empty() {
+      writeln!(f, "\nSkipped:\n")?;
+      for (id, reason) in self.skippeds {
+        writeln!(f, "+ `{id}`: {reason}")?;
+      }
+    }
+    for (id, errs_text) in self.faileds {
+      let (text, truncated) = truncate(errs_text, self.budget);
+      let fence = fence(text);
+      writeln!(f, "\n<details>\n<summary><code>{}</code></summary>\n", escape_html(id))?;
+      writeln!(f, "{fence}\n{}\n{fence}", text.trim_end())?;
+      if truncated != 0 {
+        writeln!(
+          f,
+          "\n*{truncated} bytes are truncated, see the report for all errors*"
+        )?;
+      }
+      writeln!(f, "\n</details>")?;
+    }
+    Ok(())
+  }
+}
+
+/// Keep at most `budget` bytes at a char boundary, return the truncated bytes
+fn truncate(text: &str, budget: usize) -> (&str, usize) {
+  if text.len() <= budget {
+    return (text, 0);
+  }
+  let end = (0..=budget)
+    .rev()
+    .find(|&idx| text.is_char_boundary(idx))
+    .unwrap_or(0);
+  (&text[..end], text.len() - end)
+}
+
+/// The code fence longer than any backtick run in the text, since the reports
+/// contain fenced commands
+fn fence(text: &str) -> String {
+  let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+  "`".repeat(longest.max(2) + 1)
+}
+
+pub(crate) fn escape_html(s: &str) -> String {
+  s.replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+#[test]
+fn test_summary_md() {
+  let faileds = [
+    ("a<b>.sh".to_owned(), "```bash\nbash a.sh\n```\n".to_owned()),
+    ("c.sh".to_owned(), "é".repeat(10)),
+  ];
+  let summary = Summary {
+    count_ok: 3,
+    count_ignored: 1,
+    count_filtered: 0,
+    faileds: &faileds,
+    skippeds: &[("d.sh".to_owned(), "dependency \"c.sh\" failed".to_owned())],
+    denieds: &[],
+    time: Duration::from_millis(1500),
+    budget: 5,
+  };
+  let md = summary.to_markdown();
+  assert!(md.starts_with("## Test result: FAILED\n"), "{md}");
+  assert!(md.contains("| 3 | 2 | 1 | 1 | 0 | 1.50s |"), "{md}");
+  assert!(md.contains("\nSkipped:\n\n+ `d.sh`: dependency \"c.sh\" failed\n"), "{md}");
+  assert!(md.contains("<summary><code>a&lt;b&gt;.sh</code></summary>"), "{md}");
+  // the fenced command is kept in a longer fence
+  assert!(md.contains("````\n```ba\n````"), "{md}");
+  // 2 bytes each char, 4 bytes are kept
+  assert!(md.contains("```\néé\n```"), "{md}");
+  assert!(md.contains("*16 bytes are truncated"), "{md}");
+  assert_eq!(md.matches("</details>").count(), 2);
+}
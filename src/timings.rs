@@ -0,0 +1,113 @@
+//! The durations of `--timings`, and the comparison with a `--baseline` of them.
+use std::{collections::BTreeMap, path::Path, time::Duration};
+
+use crate::regression::BuildError;
+
+/// The durations in seconds, keyed by the task paths relative to rootdir,
+/// so that a baseline is portable across checkouts
+pub(crate) type Timings = BTreeMap<String, f64>;
+
+pub(crate) fn read_timings(path: &Path) -> Result<Timings, BuildError> {
+  let s = std::fs::read_to_string(path)
+    .map_err(|e| BuildError::UnableToRead(path.to_path_buf(), e))?;
+  serde_json::from_str(&s).map_err(|e| BuildError::Json(path.to_path_buf(), e))
+}
+
+pub(crate) fn write_timings(path: &Path, timings: &Timings) -> Result<(), BuildError> {
+  serde_json::to_string_pretty(timings)
+    .map_err(std::io::Error::other)
+    .and_then(|json| std::fs::write(path, json))
+    .map_err(|e| BuildError::Write(path.to_path_buf(), e))
+}
+
+/// The tasks slower than the baseline, and the ones absent from it
+#[derive(Debug)]
+pub(crate) struct BaselineReport {
+  tolerance: f64,
+  /// Fail the run when any task is slower
+  strict: bool,
+  /// The task, its baseline, and its duration
+  slowers: Vec<(String, Duration, Duration)>,
+  news: Vec<String>,
+}
+
+impl BaselineReport {
+  /// Compare the durations of this run with the baseline, the task is slower
+  /// when its duration exceeds `tolerance` times of its baseline
+  pub(crate) fn new(
+    baseline: &Timings,
+    timings: &Timings,
+    tolerance: f64,
+    strict: bool,
+  ) -> Self {
+    let mut slowers = Vec::new();
+    let mut news = Vec::new();
+    for (task, &secs) in timings {
+      match baseline.get(task) {
+        Some(&baseline_secs) if secs > baseline_secs * tolerance => slowers.push((
+          task.clone(),
+          Duration::from_secs_f64(baseline_secs.max(0.0)),
+          Duration::from_secs_f64(secs),
+        )),
+        Some(_) => {}
+        None => news.push(task.clone()),
+      }
+    }
+    Self { tolerance, strict, slowers, news }
+  }
+  /// Whether it fails the run, only with `--baseline-strict`
+  pub(crate) fn failed(&self) -> bool {
+    self.strict && !self.slowers.is_empty()
+  }
+  pub(crate) fn is_empty(&self) -> bool {
+    self.slowers.is_empty() && self.news.is_empty()
+  }
+  /// The report lines, each one is prefixed with `prefix`, e.g., `# ` for TAP
+  pub(crate) fn lines(&self, prefix: &str) -> String {
+    let mut s = String::new();
+    if !self.slowers.is_empty() {
+      s.push_str(&format!(
+        "{prefix}slower than baseline (tolerance {:.2}x):\n",
+        self.tolerance
+      ));
+      for (task, baseline, time) in &self.slowers {
+        s.push_str(&format!(
+          "{prefix}     {task}: {:.2}s, baseline {:.2}s\n",
+          time.as_secs_f32(),
+          baseline.as_secs_f32()
+        ));
+      }
+    }
+    if !self.news.is_empty() {
+      s.push_str(&format!("{prefix}not in baseline:\n"));
+      for task in &self.news {
+        s.push_str(&format!("{prefix}     {task}\n"));
+      }
+    }
+    s
+  }
+}
+
+#[test]
+fn test_baseline_report() {
+  let baseline = Timings::from([("a.sh".into(), 1.0), ("b.sh".into(), 1.0)]);
+  let timings =
+    Timings::from([("a.sh".into(), 1.2), ("b.sh".into(), 1.5), ("c.sh".into(), 0.1)]);
+  let report = BaselineReport::new(&baseline, &timings, 1.3, false);
+  assert!(!report.failed());
+  assert_eq!(
+    report.lines("# "),
+    "# slower than baseline (tolerance 1.30x):\
+    \n#      b.sh: 1.50s, baseline 1.00s\
+    \n# not in baseline:\
+    \n#      c.sh\n"
+  );
+  assert!(BaselineReport::new(&baseline, &timings, 1.3, true).failed());
+  assert!(!BaselineReport::new(&baseline, &timings, 2.0, true).failed());
+  let tmpdir = crate::TempDir::new("baseline-report");
+  let path = tmpdir.join("baseline-report.json");
+  write_timings(&path, &timings).unwrap();
+  assert_eq!(read_timings(&path).unwrap(), timings);
+  std::fs::write(&path, "[1, 2]").unwrap();
+  assert!(matches!(read_timings(&path), Err(BuildError::Json(..))));
+}
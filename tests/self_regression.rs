@@ -5,6 +5,6 @@ async fn self_regression() -> TestExitCode {
   let args = Args::new("tests/self_regression")
     .workdir("tmp")
     .extensions(["sh"])
-    .cmd("bash");
+    .cmd(std::env::var("REGRESSION_SHELL").as_deref().unwrap_or("bash"));
   args.test().await
 }
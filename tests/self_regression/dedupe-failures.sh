@@ -0,0 +1,5 @@
+mkdir -p root/a root/b
+echo 'exit 127' > root/a/test1.sh && echo 'exit 127' > root/a/test2.sh && echo 'exit 127' > root/b/test3.sh
+echo 'exit 3' > root/test4.sh
+cargo regression root --extensions sh --cmd bash --workdir tmp --print-errs
+cargo regression root --extensions sh --cmd bash --workdir tmp --print-errs --no-dedupe-failures
@@ -0,0 +1 @@
+cargo regression ${REGRESSION_ROOTDIR}/../../demo --include ${REGRESSION_ROOTDIR}/../../demo/test-sh/test-ignore.sh --deny-ignored
\ No newline at end of file
@@ -1 +1 @@
-cargo regression ${rootdir}/../../demo --exclude ${rootdir}/../../demo/trybuild/*
\ No newline at end of file
+cargo regression ${REGRESSION_ROOTDIR}/../../demo --exclude ${REGRESSION_ROOTDIR}/../../demo/trybuild/*
\ No newline at end of file
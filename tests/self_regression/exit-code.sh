@@ -0,0 +1,8 @@
+mkdir -p passed failed build-error
+echo 'echo ok' > passed/test.sh
+echo 'exit 1' > failed/test.sh
+echo 'echo ok' > build-error/test.sh && echo 'cmd = ' > build-error/test.toml
+for root in passed failed build-error; do
+  cargo regression $root --extensions sh --cmd bash --workdir tmp-$root
+  echo "$root exit $?"
+done
@@ -0,0 +1,7 @@
+mkdir -p root/parser root/codegen/deep root/runtime
+echo 'echo ok' > root/parser/test.sh
+echo 'exit 1' > root/codegen/test1.sh && echo 'exit 1' > root/codegen/deep/test2.sh && echo 'echo ok' > root/codegen/test3.sh
+echo 'echo ok' > root/runtime/test.sh
+cargo regression root --extensions sh --cmd bash --workdir tmp
+cargo regression root --extensions sh --cmd bash --workdir tmp --group-depth 2
+cargo regression root --extensions sh --cmd bash --workdir tmp --group-depth 0
@@ -1 +1,3 @@
-cargo regression ${rootdir}/../../demo --include ${rootdir}/../../demo/trybuild/*
\ No newline at end of file
+cargo regression ${REGRESSION_ROOTDIR}/../../demo --include ${REGRESSION_ROOTDIR}/../../demo/trybuild/*
+# the other directories are pruned, unless the filtered out tasks are listed
+cargo regression ${REGRESSION_ROOTDIR}/../../demo --include ${REGRESSION_ROOTDIR}/../../demo/trybuild/* --show-skipped=all
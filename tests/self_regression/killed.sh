@@ -0,0 +1,2 @@
+mkdir -p root && echo 'kill -9 $$' > root/killed.sh
+cargo regression root --extensions sh --cmd bash --workdir tmp --print-errs
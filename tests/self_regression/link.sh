@@ -0,0 +1,26 @@
+mkdir -p root
+echo 'ls' > root/run.sh && echo 'ls' > root/run2.sh && echo 'ls' > root/collide.sh
+echo 'helper data' > root/helper_data.txt && echo 'data' > root/collide.txt
+# only `run` and `run.*` are linked, rather than sibling `run2.*`
+cat > root/run.toml <<'TOML'
+[[assert.golden]]
+file = "{{name}}.stdout"
+match = [
+  { pattern = '(?m)^run\.sh$', count = 1 },
+  { pattern = 'run2', count = 0 },
+  { pattern = 'helper', count = 0 },
+]
+TOML
+cat > root/run2.toml <<'TOML'
+inputs = ["helper*.txt"]
+[[assert.golden]]
+file = "{{name}}.stdout"
+match = [
+  { pattern = '(?m)^run2\.sh$', count = 1 },
+  { pattern = '(?m)^run\.', count = 0 },
+  { pattern = '(?m)^helper_data\.txt$', count = 1 },
+]
+TOML
+# `collide.txt` is linked as `collide.*`, and then as the input
+echo 'inputs = ["*.txt"]' > root/collide.toml
+cargo regression root --extensions sh --cmd bash --workdir tmp --print-errs
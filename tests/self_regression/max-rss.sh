@@ -0,0 +1,2 @@
+mkdir -p root && echo 'echo ok' > root/test.sh && printf '[assert]\nmax-rss = 1\n' > root/test.toml
+cargo regression root --extensions sh --cmd bash --workdir tmp --print-errs
@@ -0,0 +1,3 @@
+mkdir -p root && echo 'echo ok' > root/test.sh
+cargo regression root --extensions sh --cmd bash --workdir root/tmp
+cargo regression root --extensions sh --cmd bash --workdir root/tmp
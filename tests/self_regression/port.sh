@@ -0,0 +1,10 @@
+mkdir root && cat > root/test.sh <<'SH'
+python3 -m http.server "$1" --bind 127.0.0.1 --directory . > /dev/null 2>&1 &
+trap 'kill $!' EXIT
+for _ in $(seq 100); do curl -sf "http://127.0.0.1:$1/$(basename "$0")" > /dev/null && break; sleep 0.1; done
+[ "$REGRESSION_PORT" = "$1" ] && [ "$REGRESSION_PORT_2" = "$2" ] && [ "$1" != "$2" ] && curl -sf "http://127.0.0.1:$1/$(basename "$0")" | head -1
+SH
+cp root/test.sh root/test2.sh
+echo 'args = ["{{name}}.sh", "{{port}}", "{{port.2}}"]' > root/__all__.toml
+cargo regression root --extensions sh --cmd bash --workdir tmp
+cat tmp/test.sh/test.stdout tmp/test2.sh/test2.stdout
@@ -0,0 +1,8 @@
+mkdir -p root
+cat > root/test.sh <<'SH'
+for n in $(seq 1 300); do echo "log line $n" >> test.log; done
+exit 1
+SH
+echo 'report-files = ["{{name}}.log", "{{name}}.missing"]' > root/test.toml
+cargo regression root --extensions sh --cmd bash --workdir tmp
+cat tmp/test.sh/test.report
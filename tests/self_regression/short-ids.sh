@@ -0,0 +1,3 @@
+mkdir -p root/deep/nested && echo 'echo ok' > root/deep/nested/test.sh
+cargo regression root --extensions sh --cmd bash --workdir tmp --short-ids
+cargo regression root --extensions sh --cmd bash --workdir tmp --short-ids --verbose
@@ -0,0 +1,3 @@
+mkdir -p root && echo 'echo ok' > root/pass.sh && echo 'exit 3' > root/fail.sh
+cargo regression root --extensions sh --cmd bash --workdir tmp --short-ids --summary-md summary.md
+cat summary.md
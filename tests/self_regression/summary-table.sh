@@ -0,0 +1,4 @@
+mkdir -p root/parser root/codegen
+echo 'echo ok' > root/parser/test.sh
+echo 'exit 1' > root/codegen/test1.sh && echo 'echo ok' > root/codegen/test2.sh
+cargo regression root --extensions sh --cmd bash --workdir tmp --summary table
@@ -0,0 +1,5 @@
+mkdir root && echo 'touch $TMPDIR/fixed-name && echo $TMPDIR' > root/test.sh && echo 'tmpdir = true' > root/test.toml
+cargo regression root --extensions sh --cmd bash --workdir tmp
+tmpdir=$(cat tmp/test.sh/test.stdout)
+[ -d "$tmpdir" ] && echo "leaked $tmpdir"
+echo "tmpdir $tmpdir"